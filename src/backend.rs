@@ -0,0 +1,155 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use log::warn;
+use notify::{ErrorKind, Event, PollWatcher, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::Backend;
+
+// Where the watchers deliver their events
+type Sender = crossbeam_channel::Sender<notify::Result<Event>>;
+
+// The watchers behind watch mode, from --backend. The native watcher uses the OS's notifications,
+// and the poll watcher compares each directory against a snapshot every --poll-interval. With
+// "auto", directories the native watcher can't watch are polled instead, and everything is polled
+// if there's no native watcher at all. Both deliver to the same channel, so the watch loop doesn't
+// need to know which one an event came from.
+pub struct Watchers {
+    backend: Backend,
+    native: Option<RecommendedWatcher>,
+    // Only created once a directory has to be polled
+    poll: Option<PollWatcher>,
+    poll_interval: Duration,
+    // The directories being polled, so they're unwatched from the right watcher
+    polled: HashSet<PathBuf>,
+    sender: Sender,
+}
+
+impl Watchers {
+    pub fn new(backend: Backend, sender: Sender, poll_interval: Duration) -> Result<Watchers> {
+        let native = match backend {
+            Backend::Poll => None,
+            Backend::Native => Some(
+                RecommendedWatcher::new(sender.clone(), notify::Config::default())
+                    .with_context(|| "Failed to create watcher!")?,
+            ),
+            Backend::Auto => {
+                match RecommendedWatcher::new(sender.clone(), notify::Config::default()) {
+                    Ok(native) => Some(native),
+                    Err(e) => {
+                        warn!("Failed to create watcher ({}), polling instead", e);
+                        None
+                    }
+                }
+            }
+        };
+
+        Ok(Watchers {
+            backend,
+            native,
+            poll: None,
+            poll_interval,
+            polled: HashSet::new(),
+            sender,
+        })
+    }
+
+    // Start watching a path. With "auto", a path the native watcher can't watch is polled, unless
+    // it doesn't exist.
+    pub fn watch(&mut self, path: &Path, mode: RecursiveMode) -> notify::Result<()> {
+        if let Some(native) = &mut self.native {
+            match native.watch(path, mode) {
+                Ok(()) => return Ok(()),
+                Err(e)
+                    if self.backend == Backend::Auto
+                        && !matches!(e.kind, ErrorKind::PathNotFound) =>
+                {
+                    let hint = if is_watch_limit(&e) {
+                        ". Raise fs.inotify.max_user_watches to watch it natively."
+                    } else {
+                        ""
+                    };
+                    warn!(
+                        "Can't watch {} ({}), polling it every {} instead{}",
+                        path.display(),
+                        e,
+                        humantime::format_duration(self.poll_interval),
+                        hint
+                    );
+                    // Parts of a recursive watch may have been added before it failed
+                    let _ = native.unwatch(path);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        let poll = match &mut self.poll {
+            Some(poll) => poll,
+            None => self.poll.insert(PollWatcher::new(
+                self.sender.clone(),
+                notify::Config::default().with_poll_interval(self.poll_interval),
+            )?),
+        };
+        poll.watch(path, mode)?;
+        self.polled.insert(path.to_path_buf());
+        Ok(())
+    }
+
+    // Stop watching a path, with whichever watcher is watching it
+    pub fn unwatch(&mut self, path: &Path) -> notify::Result<()> {
+        if self.polled.remove(path) {
+            if let Some(poll) = &mut self.poll {
+                return poll.unwatch(path);
+            }
+        }
+
+        match &mut self.native {
+            Some(native) => native.unwatch(path),
+            None => Ok(()),
+        }
+    }
+}
+
+// Helper function to check whether watching failed because the inotify watch limit
+// (fs.inotify.max_user_watches) was reached, which the kernel reports as ENOSPC
+#[cfg(target_os = "linux")]
+pub fn is_watch_limit(e: &notify::Error) -> bool {
+    match &e.kind {
+        ErrorKind::MaxFilesWatch => true,
+        ErrorKind::Io(e) => e.raw_os_error() == Some(libc::ENOSPC),
+        _ => false,
+    }
+}
+
+// Other platforms have no such limit
+#[cfg(not(target_os = "linux"))]
+pub fn is_watch_limit(_e: &notify::Error) -> bool {
+    false
+}
+
+// Helper function to explain how to get past the inotify watch limit, given about how many
+// directories watch mode tried to watch. Each directory of a recursive watch takes a watch of its
+// own. The suggested limit leaves room for the directories to grow.
+pub fn watch_limit_help(directories: usize) -> String {
+    let limit = fs::read_to_string("/proc/sys/fs/inotify/max_user_watches")
+        .ok()
+        .and_then(|limit| limit.trim().parse::<usize>().ok());
+    let suggested = (directories * 2).max(limit.unwrap_or(0) * 2).max(524_288);
+
+    format!(
+        "The inotify watch limit{} was reached while watching about {} directories. Raise it with \
+        \"sudo sysctl fs.inotify.max_user_watches={}\" (add it to /etc/sysctl.conf to keep it), \
+        poll the directories with --backend poll, or keep watching the directories that could be \
+        watched with --best-effort.",
+        limit
+            .map(|limit| format!(" of {}", limit))
+            .unwrap_or_default(),
+        directories,
+        suggested
+    )
+}
@@ -0,0 +1,86 @@
+use std::{
+    collections::HashSet,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use log::{debug, info};
+
+use crate::{is_excluded_entry, should_hide_file, walker, Settings};
+
+// Time immediate mode on the given directories without hiding anything, for --benchmark. The walk
+// and the matching are timed separately, so the settings that affect each (e.g. --exclude-dir or
+// --gitignore) can be compared on real data. Every path is walked first and then matched, so the
+// paths are kept in memory in between.
+pub fn run(
+    directories: &HashSet<PathBuf>,
+    files: &HashSet<PathBuf>,
+    settings: &Settings,
+) -> Result<()> {
+    info!("Running benchmark, nothing will be hidden...");
+    let started = Instant::now();
+
+    // Walk every directory the same way immediate mode does, only collecting the paths. Errors
+    // are counted instead of reported, since they'd skew the timing.
+    let mut paths: Vec<PathBuf> = files.iter().cloned().collect();
+    let mut walk_errors = 0;
+    for directory in directories {
+        debug!("Walking directory: {}", directory.display());
+        let entries = walker(directory, settings)
+            .into_iter()
+            .filter_entry(|entry| !is_excluded_entry(entry, settings));
+        for entry in entries {
+            match entry {
+                Ok(entry) => paths.push(entry.into_path()),
+                Err(_) => walk_errors += 1,
+            }
+        }
+        if settings.include_root {
+            paths.push(directory.clone());
+        }
+    }
+    let walked = paths.len() - files.len();
+    let walk_time = started.elapsed();
+
+    // Match every path against the rules that apply to it, which is all test mode does with it
+    // besides printing
+    let matching = Instant::now();
+    let mut matched = 0;
+    let mut match_errors = 0;
+    for path in &paths {
+        match should_hide_file(path, settings.matcher_for(path)) {
+            Ok(Some(_)) => matched += 1,
+            Ok(None) => {}
+            Err(_) => match_errors += 1,
+        }
+    }
+    let match_time = matching.elapsed();
+
+    info!(
+        "Benchmark finished after {:.2?}:\n  Walk: {} paths in {:.2?} ({:.0} paths/s)\n  \
+        Match: {} paths in {:.2?} ({:.0} paths/s)\n  Matched: {}\n  Errors: {} walking, {} \
+        matching",
+        started.elapsed(),
+        walked,
+        walk_time,
+        rate(walked, walk_time),
+        paths.len(),
+        match_time,
+        rate(paths.len(), match_time),
+        matched,
+        walk_errors,
+        match_errors
+    );
+
+    Ok(())
+}
+
+// Helper function to get the number of paths handled per second
+fn rate(count: usize, elapsed: Duration) -> f64 {
+    if elapsed.is_zero() {
+        0.0
+    } else {
+        count as f64 / elapsed.as_secs_f64()
+    }
+}
@@ -0,0 +1,933 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context, Result};
+use clap::{parser::ValueSource, ArgMatches, FromArgMatches};
+use serde::Deserialize;
+
+#[cfg(windows)]
+use crate::WindowsAttribute;
+use crate::{
+    ActiveWindow, Args, Backend, FileType, OnCollision, OnOverlap, Operation, OutputFormat,
+    PathStyle, Timestamps, WalkOrder, WalkSort,
+};
+
+// Name of the config file looked up in the platform config directory when --config isn't given
+const CONFIG_FILE_NAME: &str = "config.toml";
+
+// Environment variables giving comma separated lists of directories, file names, and file
+// extensions. These are used when the option isn't given on the command line, and take precedence
+// over the config file.
+const ENV_DIRECTORIES: &str = "FILEHIDER_DIRS";
+const ENV_FILE_NAMES: &str = "FILEHIDER_NAMES";
+const ENV_FILE_EXTENSIONS: &str = "FILEHIDER_EXTENSIONS";
+
+// The merged configuration from the command line and the config file. Values given on the command
+// line always take precedence over values from the config file.
+#[derive(Debug)]
+pub struct Config {
+    // The canonicalized path of the config file in use, if any
+    pub path: Option<PathBuf>,
+    // Whether the directories defaulted to the current directory because none were given
+    pub use_current_directory: bool,
+    pub directories: Vec<String>,
+    pub file_names: Vec<String>,
+    pub file_extensions: Vec<String>,
+    // Names and extensions matched against directories instead of the file names, if given
+    pub dir_names: Vec<String>,
+    pub dir_extensions: Vec<String>,
+    pub match_dir_extensions: bool,
+    pub path_regex: Option<String>,
+    pub exclude_dirs: Vec<String>,
+    pub recursive: bool,
+    pub include_root: bool,
+    pub min_depth: Option<u64>,
+    pub max_depth: Option<u64>,
+    pub follow_symlinks: bool,
+    pub one_file_system: bool,
+    pub case_sensitive: bool,
+    pub match_stem: bool,
+    pub gitignore: bool,
+    pub test_mode: bool,
+    pub watch: bool,
+    pub immediate: bool,
+    pub watch_existing: bool,
+    pub only_new: bool,
+    pub match_files: bool,
+    pub on_collision: OnCollision,
+    pub on_overlap: OnOverlap,
+    pub stdin: bool,
+    pub null_data: bool,
+    pub no_filter: bool,
+    pub order: WalkOrder,
+    pub sort_by: Option<WalkSort>,
+    pub action: Operation,
+    pub list: bool,
+    pub print0: bool,
+    pub sort: bool,
+    pub count: bool,
+    pub format: OutputFormat,
+    pub path_style: PathStyle,
+    pub absolute_paths: bool,
+    pub relative_paths: bool,
+    pub show_targets: bool,
+    pub progress: bool,
+    pub heartbeat_secs: Option<u64>,
+    pub duration: Option<Duration>,
+    pub rescan: Option<Duration>,
+    pub stats_interval: Option<Duration>,
+    pub active_between: Option<ActiveWindow>,
+    pub threads_watch: Option<u64>,
+    pub event_buffer: u64,
+    pub backend: Backend,
+    pub poll_interval: Duration,
+    pub best_effort: bool,
+    pub debounce: Duration,
+    pub settle: Option<Duration>,
+    pub settle_max: Duration,
+    pub ipc: Option<PathBuf>,
+    pub on_hide: Option<String>,
+    pub on_hide_timeout: Duration,
+    pub match_command: Option<String>,
+    pub match_command_timeout: Duration,
+    pub summary_json: Option<PathBuf>,
+    pub emit_script: Option<PathBuf>,
+    pub journal: Option<PathBuf>,
+    pub log_file: Option<PathBuf>,
+    pub log_max_size: Option<u64>,
+    pub log_keep: u32,
+    pub timestamps: Timestamps,
+    pub utc: bool,
+    pub max_ops_per_sec: Option<u32>,
+    pub max_hides: Option<u64>,
+    pub fail_fast: bool,
+    pub max_errors_total: Option<u64>,
+    pub fail_on_match: bool,
+    pub no_lock: bool,
+    pub restore_on_exit: bool,
+    pub force: bool,
+    pub reveal_all: bool,
+    pub all: bool,
+    pub file_types: Vec<FileType>,
+    pub confirm: bool,
+    #[cfg(windows)]
+    pub windows_attrs: Vec<WindowsAttribute>,
+    #[cfg(windows)]
+    pub eventlog: bool,
+    #[cfg(all(unix, feature = "syslog"))]
+    pub syslog: bool,
+    #[cfg(feature = "notifications")]
+    pub notify: bool,
+    #[cfg(feature = "notifications")]
+    pub notify_window: Duration,
+    #[cfg(feature = "metrics")]
+    pub metrics_addr: Option<std::net::SocketAddr>,
+    #[cfg(target_os = "macos")]
+    pub macos_finder_hidden: bool,
+    #[cfg(not(windows))]
+    pub hidden_prefix: String,
+    #[cfg(unix)]
+    pub owner: Option<String>,
+    #[cfg(unix)]
+    pub mode: Option<String>,
+    pub sections: Vec<SectionConfig>,
+}
+
+// A [[watch]] section of the config file, giving a directory its own rules. Sections inherit the
+// global file names and extensions unless "inherit" is false, and use the global "recursive" and
+// "file-types" options unless they set their own.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SectionConfig {
+    pub path: String,
+    #[serde(default)]
+    pub names: Vec<String>,
+    #[serde(default)]
+    pub extensions: Vec<String>,
+    pub recursive: Option<bool>,
+    pub file_types: Option<Vec<FileType>>,
+    #[serde(default = "default_inherit")]
+    pub inherit: bool,
+
+    // Any keys that don't match the ones above, so they can be warned about
+    #[serde(flatten)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+
+// The contents of a config file. Keys mirror the long command line flags, and every key is
+// optional. Options that only make sense for a single invocation (e.g. --stdin or --list) can only
+// be given on the command line.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+struct FileConfig {
+    directories: Option<Vec<String>>,
+    file_names: Option<Vec<String>>,
+    file_extensions: Option<Vec<String>>,
+    dir_names: Option<Vec<String>>,
+    dir_extensions: Option<Vec<String>>,
+    match_dir_extensions: Option<bool>,
+    path_regex: Option<String>,
+    #[serde(rename = "exclude-dir")]
+    exclude_dirs: Option<Vec<String>>,
+    recursive: Option<bool>,
+    include_root: Option<bool>,
+    min_depth: Option<u64>,
+    max_depth: Option<u64>,
+    follow_symlinks: Option<bool>,
+    one_file_system: Option<bool>,
+    case_sensitive: Option<bool>,
+    match_stem: Option<bool>,
+    gitignore: Option<bool>,
+    #[serde(rename = "test")]
+    test_mode: Option<bool>,
+    // Named "watch-mode" rather than "watch" since "watch" is used for the sections below
+    #[serde(rename = "watch-mode")]
+    watch: Option<bool>,
+    immediate: Option<bool>,
+    watch_existing: Option<bool>,
+    only_new: Option<bool>,
+    match_files: Option<bool>,
+    on_collision: Option<OnCollision>,
+    on_overlap: Option<OnOverlap>,
+    order: Option<WalkOrder>,
+    sort_by: Option<WalkSort>,
+    action: Option<Operation>,
+    path_style: Option<PathStyle>,
+    absolute_paths: Option<bool>,
+    relative_paths: Option<bool>,
+    show_targets: Option<bool>,
+    heartbeat_secs: Option<u64>,
+    // Durations are parsed the same way as on the command line (e.g. "8h")
+    duration: Option<String>,
+    rescan: Option<String>,
+    stats_interval: Option<String>,
+    active_between: Option<String>,
+    threads_watch: Option<u64>,
+    event_buffer: Option<u64>,
+    backend: Option<Backend>,
+    poll_interval: Option<String>,
+    best_effort: Option<bool>,
+    debounce: Option<String>,
+    settle: Option<String>,
+    settle_max: Option<String>,
+    ipc: Option<PathBuf>,
+    on_hide: Option<String>,
+    on_hide_timeout: Option<String>,
+    match_command: Option<String>,
+    match_command_timeout: Option<String>,
+    summary_json: Option<PathBuf>,
+    emit_script: Option<PathBuf>,
+    journal: Option<PathBuf>,
+    log_file: Option<PathBuf>,
+    // Sizes are parsed the same way as on the command line (e.g. "10MB")
+    log_max_size: Option<String>,
+    log_keep: Option<u32>,
+    timestamps: Option<Timestamps>,
+    utc: Option<bool>,
+    max_ops_per_sec: Option<u32>,
+    max_hides: Option<u64>,
+    fail_fast: Option<bool>,
+    max_errors_total: Option<u64>,
+    no_lock: Option<bool>,
+    restore_on_exit: Option<bool>,
+    file_types: Option<Vec<FileType>>,
+    #[cfg(windows)]
+    windows_attrs: Option<Vec<WindowsAttribute>>,
+    #[cfg(windows)]
+    eventlog: Option<bool>,
+    #[cfg(all(unix, feature = "syslog"))]
+    syslog: Option<bool>,
+    #[cfg(feature = "notifications")]
+    notify: Option<bool>,
+    #[cfg(feature = "notifications")]
+    notify_window: Option<String>,
+    #[cfg(feature = "metrics")]
+    metrics_addr: Option<std::net::SocketAddr>,
+    #[cfg(target_os = "macos")]
+    macos_finder_hidden: Option<bool>,
+    #[cfg(not(windows))]
+    hidden_prefix: Option<String>,
+    #[cfg(unix)]
+    owner: Option<String>,
+    #[cfg(unix)]
+    mode: Option<String>,
+    #[serde(rename = "watch")]
+    sections: Vec<SectionConfig>,
+
+    // Any keys that don't match the ones above, so they can be warned about
+    #[serde(flatten)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+
+impl Config {
+    // Build the configuration from the command line arguments, filling in anything that wasn't
+    // given on the command line from the config file. The config file is either the one given with
+    // --config, or the default one in the platform config directory if it exists. This can be
+    // called again to reload the config file.
+    pub fn load(matches: &ArgMatches) -> Result<Config> {
+        let args = Args::from_arg_matches(matches)?;
+
+        let path = match &args.config {
+            Some(path) => Some(path.clone()),
+            None => default_config_path().filter(|path| path.is_file()),
+        };
+        let (path, file) = match path {
+            Some(path) => {
+                let file = read_config_file(&path)?;
+                let path = path.canonicalize().with_context(|| {
+                    format!("Failed to resolve absolute path of {}", path.display())
+                })?;
+                (Some(path), file)
+            }
+            None => (None, FileConfig::default()),
+        };
+
+        let duration = parse_duration("duration", file.duration.as_deref())?;
+        let rescan = file
+            .rescan
+            .as_deref()
+            .map(crate::parse_interval)
+            .transpose()
+            .map_err(|e| anyhow!("Invalid rescan in config file: {}", e))?;
+        let stats_interval = parse_duration("stats-interval", file.stats_interval.as_deref())?;
+        let on_hide_timeout = parse_duration("on-hide-timeout", file.on_hide_timeout.as_deref())?;
+        let match_command_timeout =
+            parse_duration("match-command-timeout", file.match_command_timeout.as_deref())?;
+        let debounce = parse_duration("debounce", file.debounce.as_deref())?;
+        let poll_interval = parse_duration("poll-interval", file.poll_interval.as_deref())?;
+        let settle = parse_duration("settle", file.settle.as_deref())?;
+        let settle_max = parse_duration("settle-max", file.settle_max.as_deref())?;
+        #[cfg(feature = "notifications")]
+        let notify_window = parse_duration("notify-window", file.notify_window.as_deref())?;
+        let active_between = file
+            .active_between
+            .as_deref()
+            .map(crate::schedule::parse_window)
+            .transpose()
+            .map_err(|e| anyhow!("Invalid active-between in config file: {}", e))?;
+        let log_max_size = file
+            .log_max_size
+            .as_deref()
+            .map(crate::logger::parse_size)
+            .transpose()
+            .map_err(|e| anyhow!("Invalid log-max-size in config file: {}", e))?;
+
+        // Whether an argument was given on the command line, rather than being left at its default
+        let given = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+
+        // The directories of the sections are watched too, unless directories are given on the
+        // command line or in the environment. Either way, the sections still provide the rules for
+        // paths under them.
+        let directories = if given("directories") {
+            args.directories
+        } else if let Some(directories) = env_list(ENV_DIRECTORIES) {
+            directories
+        } else {
+            let mut directories = file.directories.unwrap_or_default();
+            directories.extend(file.sections.iter().map(|section| section.path.clone()));
+            directories
+        };
+
+        let mut config = Config {
+            path,
+            use_current_directory: false,
+            directories,
+            file_names: pick(
+                given("file_names"),
+                args.file_names,
+                env_list(ENV_FILE_NAMES).or(file.file_names),
+            ),
+            file_extensions: pick(
+                given("file_extensions"),
+                args.file_extensions,
+                env_list(ENV_FILE_EXTENSIONS).or(file.file_extensions),
+            ),
+            dir_names: pick(given("dir_names"), args.dir_names, file.dir_names),
+            dir_extensions: pick(given("dir_extensions"), args.dir_extensions, file.dir_extensions),
+            match_dir_extensions: pick(
+                given("match_dir_extensions"),
+                args.match_dir_extensions,
+                file.match_dir_extensions,
+            ),
+            path_regex: pick(given("path_regex"), args.path_regex, file.path_regex.map(Some)),
+            exclude_dirs: pick(given("exclude_dirs"), args.exclude_dirs, file.exclude_dirs),
+            recursive: pick(given("recursive"), args.recursive, file.recursive),
+            include_root: pick(given("include_root"), args.include_root, file.include_root),
+            min_depth: pick(given("min_depth"), args.min_depth, file.min_depth.map(Some)),
+            max_depth: pick(given("max_depth"), args.max_depth, file.max_depth.map(Some)),
+            follow_symlinks: pick(
+                given("follow_symlinks"),
+                args.follow_symlinks,
+                file.follow_symlinks,
+            ),
+            one_file_system: pick(
+                given("one_file_system"),
+                args.one_file_system,
+                file.one_file_system,
+            ),
+            case_sensitive: pick(given("case_sensitive"), args.case_sensitive, file.case_sensitive),
+            match_stem: pick(given("match_stem"), args.match_stem, file.match_stem),
+            gitignore: pick(given("gitignore"), args.gitignore, file.gitignore),
+            test_mode: pick(given("test_mode"), args.test_mode, file.test_mode),
+            watch: pick(given("watch"), args.watch, file.watch),
+            // Immediate mode runs by default, so "-i" only overrides the config file
+            immediate: !args.no_immediate
+                && pick(given("immediate"), args.immediate, file.immediate),
+            watch_existing: pick(given("watch_existing"), args.watch_existing, file.watch_existing),
+            only_new: pick(given("only_new"), args.only_new, file.only_new),
+            match_files: pick(given("match_files"), args.match_files, file.match_files),
+            on_collision: pick(given("on_collision"), args.on_collision, file.on_collision),
+            on_overlap: pick(given("on_overlap"), args.on_overlap, file.on_overlap),
+            stdin: args.stdin,
+            null_data: args.null_data,
+            no_filter: args.no_filter,
+            order: pick(given("order"), args.order, file.order),
+            sort_by: pick(given("sort_by"), args.sort_by, file.sort_by.map(Some)),
+            action: pick(given("action"), args.action, file.action),
+            list: args.list,
+            print0: args.print0,
+            sort: args.sort,
+            count: args.count,
+            format: args.format,
+            path_style: pick(given("path_style"), args.path_style, file.path_style),
+            absolute_paths: pick(
+                given("absolute_paths"),
+                args.absolute_paths,
+                file.absolute_paths,
+            ),
+            relative_paths: pick(
+                given("relative_paths"),
+                args.relative_paths,
+                file.relative_paths,
+            ),
+            show_targets: pick(given("show_targets"), args.show_targets, file.show_targets),
+            progress: args.progress,
+            heartbeat_secs: pick(
+                given("heartbeat_secs"),
+                args.heartbeat_secs,
+                file.heartbeat_secs.map(Some),
+            ),
+            duration: pick(given("duration"), args.duration, duration.map(Some)),
+            rescan: pick(given("rescan"), args.rescan, rescan.map(Some)),
+            stats_interval: pick(
+                given("stats_interval"),
+                args.stats_interval,
+                stats_interval.map(Some),
+            ),
+            active_between: pick(
+                given("active_between"),
+                args.active_between,
+                active_between.map(Some),
+            ),
+            threads_watch: pick(
+                given("threads_watch"),
+                args.threads_watch,
+                file.threads_watch.map(Some),
+            ),
+            event_buffer: pick(given("event_buffer"), args.event_buffer, file.event_buffer),
+            backend: pick(given("backend"), args.backend, file.backend),
+            poll_interval: pick(given("poll_interval"), args.poll_interval, poll_interval),
+            best_effort: pick(given("best_effort"), args.best_effort, file.best_effort),
+            debounce: pick(given("debounce"), args.debounce, debounce),
+            settle: pick(given("settle"), args.settle, settle.map(Some)),
+            settle_max: pick(given("settle_max"), args.settle_max, settle_max),
+            ipc: pick(given("ipc"), args.ipc, file.ipc.map(Some)),
+            on_hide: pick(given("on_hide"), args.on_hide, file.on_hide.map(Some)),
+            on_hide_timeout: pick(
+                given("on_hide_timeout"),
+                args.on_hide_timeout,
+                on_hide_timeout,
+            ),
+            match_command: pick(
+                given("match_command"),
+                args.match_command,
+                file.match_command.map(Some),
+            ),
+            match_command_timeout: pick(
+                given("match_command_timeout"),
+                args.match_command_timeout,
+                match_command_timeout,
+            ),
+            summary_json: pick(
+                given("summary_json"),
+                args.summary_json,
+                file.summary_json.map(Some),
+            ),
+            emit_script: pick(
+                given("emit_script"),
+                args.emit_script,
+                file.emit_script.map(Some),
+            ),
+            journal: pick(given("journal"), args.journal, file.journal.map(Some)),
+            log_file: pick(given("log_file"), args.log_file, file.log_file.map(Some)),
+            log_max_size: pick(given("log_max_size"), args.log_max_size, log_max_size.map(Some)),
+            log_keep: pick(given("log_keep"), args.log_keep, file.log_keep),
+            timestamps: pick(given("timestamps"), args.timestamps, file.timestamps),
+            utc: pick(given("utc"), args.utc, file.utc),
+            max_ops_per_sec: pick(
+                given("max_ops_per_sec"),
+                args.max_ops_per_sec,
+                file.max_ops_per_sec.map(Some),
+            ),
+            max_hides: pick(given("max_hides"), args.max_hides, file.max_hides.map(Some)),
+            fail_fast: pick(given("fail_fast"), args.fail_fast, file.fail_fast),
+            max_errors_total: pick(
+                given("max_errors_total"),
+                args.max_errors_total,
+                file.max_errors_total.map(Some),
+            ),
+            no_lock: pick(given("no_lock"), args.no_lock, file.no_lock),
+            restore_on_exit: pick(
+                given("restore_on_exit"),
+                args.restore_on_exit,
+                file.restore_on_exit,
+            ),
+            fail_on_match: args.fail_on_match,
+            force: args.force,
+            reveal_all: args.reveal_all,
+            all: args.all,
+            file_types: pick(given("file_types"), args.file_types, file.file_types),
+            confirm: args.confirm,
+            #[cfg(windows)]
+            windows_attrs: pick(given("windows_attrs"), args.windows_attrs, file.windows_attrs),
+            #[cfg(windows)]
+            eventlog: pick(given("eventlog"), args.eventlog, file.eventlog),
+            #[cfg(all(unix, feature = "syslog"))]
+            syslog: pick(given("syslog"), args.syslog, file.syslog),
+            #[cfg(feature = "notifications")]
+            notify: pick(given("notify"), args.notify, file.notify),
+            #[cfg(feature = "notifications")]
+            notify_window: pick(given("notify_window"), args.notify_window, notify_window),
+            #[cfg(feature = "metrics")]
+            metrics_addr: pick(
+                given("metrics_addr"),
+                args.metrics_addr,
+                file.metrics_addr.map(Some),
+            ),
+            #[cfg(target_os = "macos")]
+            macos_finder_hidden: pick(
+                given("macos_finder_hidden"),
+                args.macos_finder_hidden,
+                file.macos_finder_hidden,
+            ),
+            #[cfg(not(windows))]
+            hidden_prefix: pick(given("hidden_prefix"), args.hidden_prefix, file.hidden_prefix),
+            #[cfg(unix)]
+            owner: pick(given("owner"), args.owner, file.owner.map(Some)),
+            #[cfg(unix)]
+            mode: pick(given("mode"), args.mode, file.mode.map(Some)),
+            sections: file.sections,
+        };
+
+        // A lone "-" positional is the same as passing --stdin
+        if config.directories.iter().any(|directory| directory == "-") {
+            if config.directories.len() > 1 {
+                return Err(anyhow!(
+                    "Reading paths from stdin can't be combined with other paths!"
+                ));
+            }
+            config.stdin = true;
+        }
+
+        // Paths are read from stdin instead, so don't validate any directories (e.g. from the
+        // config file)
+        if config.stdin {
+            config.directories.clear();
+        }
+
+        // Nothing is hidden while writing a script, the same as in test mode
+        if config.emit_script.is_some() {
+            config.test_mode = true;
+        }
+
+        // Watching the existing paths is immediate mode and watch mode together, with the
+        // directories watched first. The roots can't be hidden, since their watches would be left
+        // on the old names.
+        if config.watch_existing {
+            if !config.immediate {
+                return Err(anyhow!("--watch-existing can't be used with immediate mode disabled!"));
+            }
+            if config.include_root {
+                return Err(anyhow!("--watch-existing can't be combined with --include-root!"));
+            }
+            config.watch = true;
+        }
+
+        // Everything that already exists is left alone with --only-new, so immediate mode is off
+        if config.only_new {
+            if config.watch_existing {
+                return Err(anyhow!("--only-new can't be combined with --watch-existing!"));
+            }
+            config.watch = true;
+            config.immediate = false;
+        }
+
+        // Reading from stdin replaces the directory walk, so it can't be combined with watch mode
+        if config.stdin && config.watch {
+            return Err(anyhow!(
+                "Reading paths from stdin can't be combined with watch mode!"
+            ));
+        }
+
+        // The JSON document is only printed once the run finishes, which watch mode never does
+        if config.format == OutputFormat::Json && config.watch {
+            return Err(anyhow!("JSON output can't be combined with watch mode!"));
+        }
+
+        // Everything hidden is unhidden with --reveal-all, which watch mode would hide again
+        if config.reveal_all {
+            if config.watch {
+                return Err(anyhow!("--reveal-all can't be combined with watch mode!"));
+            }
+            config.action = Operation::Unhide;
+        }
+
+        // Toggling in watch mode would toggle every path back as soon as its rename is seen
+        if config.action == Operation::Toggle && config.watch {
+            return Err(anyhow!("Toggling can't be combined with watch mode!"));
+        }
+
+        // Nothing is restored unless watch mode stops
+        if config.restore_on_exit && !config.watch {
+            return Err(anyhow!("restore-on-exit can only be used in watch mode!"));
+        }
+
+        // The prefix is added to file names, so it has to stay within them
+        #[cfg(not(windows))]
+        if config.hidden_prefix.is_empty()
+            || config.hidden_prefix.contains(std::path::is_separator)
+        {
+            return Err(anyhow!(
+                "Invalid hidden prefix \"{}\"! It can't be empty or contain path separators.",
+                config.hidden_prefix
+            ));
+        }
+
+        // Clap already rejects --watch, but watch mode can also be enabled in the config file
+        if config.count && config.watch {
+            return Err(anyhow!("--count can't be used in watch mode!"));
+        }
+        if config.fail_on_match && config.watch {
+            return Err(anyhow!("--fail-on-match can't be used in watch mode!"));
+        }
+        if config.confirm && config.watch {
+            return Err(anyhow!("--confirm can't be used in watch mode!"));
+        }
+
+        // Clap already checks these counts, but they can also come from the config file. A depth of
+        // 0 would be the directory itself, which is never hidden.
+        let counts = [
+            ("event-buffer", Some(config.event_buffer)),
+            ("max-errors-total", config.max_errors_total),
+            ("min-depth", config.min_depth),
+            ("max-depth", config.max_depth),
+            ("max-ops-per-sec", config.max_ops_per_sec.map(u64::from)),
+            ("max-hides", config.max_hides),
+            ("threads-watch", config.threads_watch),
+            ("heartbeat-secs", config.heartbeat_secs),
+        ];
+        if let Some((name, _)) = counts.iter().find(|(_, count)| *count == Some(0)) {
+            return Err(anyhow!("{} must be at least 1!", name));
+        }
+        if config.poll_interval.is_zero() {
+            return Err(anyhow!("poll-interval must be more than 0!"));
+        }
+
+        // Hidden paths are printed as they're hidden, so only dry runs can be sorted
+        if config.sort && !config.test_mode && !config.list {
+            return Err(anyhow!("--sort can only be used in test mode or list mode!"));
+        }
+
+        // --path-style on the command line replaces the older switches in the config file
+        if given("path_style") {
+            config.absolute_paths = false;
+            config.relative_paths = false;
+        }
+
+        // Clap already rejects both flags, but they can also come from the config file
+        if config.absolute_paths && config.relative_paths {
+            return Err(anyhow!("absolute-paths can't be combined with relative-paths!"));
+        }
+
+        // If no directories were given, default to the current directory
+        if config.directories.is_empty() && !config.stdin {
+            config.directories.push(".".to_string());
+            config.use_current_directory = true;
+        }
+
+        Ok(config)
+    }
+}
+
+// Helper function to choose between a command line value and a config file value. The command line
+// value is used if it was given, or if the config file doesn't set the value either (in which case
+// it holds the default).
+fn pick<T>(given: bool, cli: T, file: Option<T>) -> T {
+    if given {
+        cli
+    } else {
+        file.unwrap_or(cli)
+    }
+}
+
+// Helper function to read a comma separated list from an environment variable. Whitespace around
+// each item is trimmed and empty items are ignored. A variable that's unset, not valid unicode, or
+// has no items is treated as not given.
+fn env_list(name: &str) -> Option<Vec<String>> {
+    let value = std::env::var(name).ok()?;
+    let items: Vec<String> = value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+        .collect();
+
+    (!items.is_empty()).then_some(items)
+}
+
+// Helper function to parse a duration from the config file the same way as on the command line
+fn parse_duration(key: &str, value: Option<&str>) -> Result<Option<Duration>> {
+    value
+        .map(humantime::parse_duration)
+        .transpose()
+        .map_err(|e| anyhow!("Invalid {} in config file: {}", key, e))
+}
+
+// Sections inherit the global rules by default
+fn default_inherit() -> bool {
+    true
+}
+
+// Helper function to get the path of the default config file in the platform config directory
+// (e.g. "~/.config/filehider/config.toml" on Linux)
+fn default_config_path() -> Option<PathBuf> {
+    directories::ProjectDirs::from("", "", "filehider")
+        .map(|dirs| dirs.config_dir().join(CONFIG_FILE_NAME))
+}
+
+// Read and parse a config file, warning about any unknown keys
+fn read_config_file(path: &Path) -> Result<FileConfig> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file {}", path.display()))?;
+
+    let file: FileConfig = toml::from_str(&contents)
+        .map_err(|e| anyhow!("Failed to parse config file {}: {}", path.display(), e))?;
+
+    for key in file.unknown.keys() {
+        log::warn!(
+            "Unknown key \"{}\" in config file {}",
+            key,
+            path.display()
+        );
+    }
+
+    for section in &file.sections {
+        for key in section.unknown.keys() {
+            log::warn!(
+                "Unknown key \"{}\" in [[watch]] section for {} in config file {}",
+                key,
+                section.path,
+                path.display()
+            );
+        }
+    }
+
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use clap::CommandFactory;
+
+    use super::*;
+
+    // Counter to keep the config files of tests running in parallel apart
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+    // A config file written to the temp directory, removed when it's dropped
+    struct TempConfig(PathBuf);
+
+    impl TempConfig {
+        fn new(contents: &str) -> TempConfig {
+            let path = std::env::temp_dir().join(format!(
+                "filehider-config-{}-{}.toml",
+                std::process::id(),
+                NEXT_ID.fetch_add(1, Ordering::SeqCst)
+            ));
+            fs::write(&path, contents).unwrap();
+            TempConfig(path)
+        }
+    }
+
+    impl Drop for TempConfig {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    // Helper function to load the config from the given arguments and config file contents. A
+    // config file is always given, so the user's own config file is never read.
+    fn load(args: &[&str], contents: &str) -> Result<Config> {
+        let file = TempConfig::new(contents);
+        let config = file.0.to_str().unwrap();
+        let matches = Args::command()
+            .try_get_matches_from(["filehider", "--config", config].iter().chain(args))?;
+        Config::load(&matches)
+    }
+
+    // Values from the config file are used when they aren't given on the command line
+    #[test]
+    fn config_file_values_used() {
+        let config = load(
+            &[],
+            r#"
+            directories = ["/tmp"]
+            file-names = ["Thumbs.db"]
+            file-extensions = ["tmp"]
+            recursive = true
+            case-sensitive = true
+            max-depth = 3
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.directories, ["/tmp"]);
+        assert_eq!(config.file_names, ["Thumbs.db"]);
+        assert_eq!(config.file_extensions, ["tmp"]);
+        assert!(config.recursive);
+        assert!(config.case_sensitive);
+        assert_eq!(config.max_depth, Some(3));
+        assert!(config.path.is_some());
+    }
+
+    // Values given on the command line take precedence over the config file
+    #[test]
+    fn command_line_overrides_config_file() {
+        let config = load(
+            &["/var", "-n", "desktop.ini", "-r", "--max-depth", "5"],
+            r#"
+            directories = ["/tmp"]
+            file-names = ["Thumbs.db"]
+            file-extensions = ["tmp"]
+            recursive = false
+            max-depth = 3
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.directories, ["/var"]);
+        assert_eq!(config.file_names, ["desktop.ini"]);
+        // Not given on the command line, so still from the config file
+        assert_eq!(config.file_extensions, ["tmp"]);
+        assert!(config.recursive);
+        assert_eq!(config.max_depth, Some(5));
+    }
+
+    // Defaults apply when neither the command line nor the config file give a value
+    #[test]
+    fn defaults_without_either() {
+        let config = load(&["-x", "tmp"], "").unwrap();
+
+        assert_eq!(config.directories, ["."]);
+        assert!(config.use_current_directory);
+        assert!(!config.recursive);
+        assert!(!config.case_sensitive);
+        assert_eq!(config.max_depth, None);
+    }
+
+    // Unknown keys are kept so they can be warned about, rather than failing the whole file
+    #[test]
+    fn unknown_keys_kept() {
+        let file = TempConfig::new("file-names = [\"a\"]\nfile-nmaes = [\"b\"]\n");
+        let config = read_config_file(&file.0).unwrap();
+
+        assert_eq!(config.file_names, Some(vec!["a".to_string()]));
+        assert!(config.unknown.contains_key("file-nmaes"));
+    }
+
+    // Whether immediate mode and watch mode are enabled, and whether that's a valid combination,
+    // for each combination of -i, --no-immediate, -w and the config file
+    #[test]
+    fn immediate_flag_combinations() {
+        let off = "immediate = false";
+        let watch = "watch-mode = true\nimmediate = false";
+        let cases: &[(&[&str], &str, bool, bool)] = &[
+            (&[], "", true, false),
+            (&["-i"], "", true, false),
+            (&["--no-immediate"], "", false, false),
+            (&["-w"], "", true, true),
+            (&["-w", "-i"], "", true, true),
+            (&["-w", "--no-immediate"], "", false, true),
+            // The last of -i and --no-immediate wins
+            (&["-i", "--no-immediate"], "", false, false),
+            (&["--no-immediate", "-i"], "", true, false),
+            (&[], off, false, false),
+            (&["-i"], off, true, false),
+            (&["--no-immediate"], off, false, false),
+            (&["-w"], off, false, true),
+            (&["-w", "-i"], off, true, true),
+            (&[], watch, false, true),
+            (&["-i"], watch, true, true),
+        ];
+
+        for (args, contents, immediate, watch) in cases {
+            let config = load(args, contents).unwrap();
+            let case = format!("{:?} with config {:?}", args, contents);
+            assert_eq!(config.immediate, *immediate, "immediate for {}", case);
+            assert_eq!(config.watch, *watch, "watch for {}", case);
+            assert_eq!(
+                crate::check_modes(config.watch, config.immediate).is_ok(),
+                *immediate || *watch,
+                "modes for {}",
+                case
+            );
+        }
+    }
+
+    // Counts below 1 are rejected on the command line by clap, and from the config file by load
+    #[test]
+    fn counts_below_one_fail() {
+        for name in [
+            "event-buffer",
+            "max-errors-total",
+            "min-depth",
+            "max-depth",
+            "max-ops-per-sec",
+            "max-hides",
+            "threads-watch",
+            "heartbeat-secs",
+        ] {
+            let flag = format!("--{}", name);
+            assert!(load(&[&flag, "0"], "").is_err(), "{}", flag);
+
+            let e = load(&[], &format!("{} = 0", name)).unwrap_err();
+            assert_eq!(e.to_string(), format!("{} must be at least 1!", name));
+        }
+
+        let config = load(&["--min-depth", "1"], "max-depth = 1").unwrap();
+        assert_eq!((config.min_depth, config.max_depth), (Some(1), Some(1)));
+    }
+
+    // A minimum depth greater than the maximum depth is an error, wherever each comes from
+    #[test]
+    fn min_depth_above_max_depth_fails() {
+        let config = load(&["--min-depth", "3"], "max-depth = 2").unwrap();
+
+        let e = crate::check_depths(config.min_depth, config.max_depth).unwrap_err();
+        assert_eq!(e.to_string(), "Minimum depth 3 is greater than maximum depth 2!");
+    }
+
+    // A config file that isn't valid TOML is an error naming the file
+    #[test]
+    fn invalid_config_file_fails() {
+        let e = load(&[], "file-names = [").unwrap_err();
+
+        assert!(e.to_string().starts_with("Failed to parse config file"));
+    }
+}
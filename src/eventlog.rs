@@ -0,0 +1,139 @@
+use std::{ffi::OsStr, os::windows::ffi::OsStrExt, ptr, sync::Mutex};
+
+use log::{warn, Level, Record};
+use winapi::{
+    shared::minwindef::WORD,
+    um::{
+        winbase::{DeregisterEventSource, RegisterEventSourceW, ReportEventW},
+        winnt::{
+            EVENTLOG_ERROR_TYPE, EVENTLOG_INFORMATION_TYPE, EVENTLOG_WARNING_TYPE, HANDLE,
+        },
+    },
+};
+
+use crate::logger;
+
+// Name of the event source the events are written under, in the Application log
+const SOURCE_NAME: &str = "filehider";
+
+// Event IDs, so the operations team can filter on them
+const EVENT_STARTED: u32 = 1;
+const EVENT_STOPPED: u32 = 2;
+const EVENT_HIDDEN: u32 = 100;
+const EVENT_WARNING: u32 = 200;
+const EVENT_ERROR: u32 = 300;
+
+// The registered event source, while --eventlog is in use
+static EVENT_LOG: Mutex<Option<EventLog>> = Mutex::new(None);
+
+// Windows Event Log source for --eventlog. Paths hidden are written as Information events, and
+// warnings and errors (with the Win32 error code in their message) as Warning and Error events.
+// Other messages stay on the terminal only. Deregistered when dropped, after writing the shutdown
+// event.
+struct EventLog {
+    handle: HANDLE,
+}
+
+// Keeps the event source registered until it's dropped at the end of the run
+pub struct EventLogGuard;
+
+impl Drop for EventLogGuard {
+    fn drop(&mut self) {
+        EVENT_LOG.lock().unwrap_or_else(|e| e.into_inner()).take();
+    }
+}
+
+// Start writing messages to the Windows Event Log as well. If the event source can't be registered
+// (e.g. because of insufficient rights), messages are only printed, with a single warning.
+pub fn start() -> Option<EventLogGuard> {
+    match EventLog::register() {
+        Some(event_log) => {
+            *EVENT_LOG.lock().unwrap_or_else(|e| e.into_inner()) = Some(event_log);
+            Some(EventLogGuard)
+        }
+        None => {
+            warn!(
+                "Failed to register event source {}, only printing messages: {}",
+                SOURCE_NAME,
+                std::io::Error::last_os_error()
+            );
+            None
+        }
+    }
+}
+
+// Write a log record to the event log, if it's in use
+pub fn write(record: &Record) {
+    if let Some(event_log) = EVENT_LOG.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        event_log.write(record);
+    }
+}
+
+// Event log handles can be used from any thread
+unsafe impl Send for EventLog {}
+unsafe impl Sync for EventLog {}
+
+impl EventLog {
+    // Register the event source and write the startup event. Returns None if it can't be
+    // registered.
+    fn register() -> Option<EventLog> {
+        let name = wide(SOURCE_NAME);
+        let handle = unsafe { RegisterEventSourceW(ptr::null(), name.as_ptr()) };
+        if handle.is_null() {
+            return None;
+        }
+
+        let event_log = EventLog { handle };
+        event_log.report(
+            EVENTLOG_INFORMATION_TYPE,
+            EVENT_STARTED,
+            &format!("filehider started with PID {}", std::process::id()),
+        );
+        Some(event_log)
+    }
+
+    // Write a log record as an event, if it's one that belongs in the event log
+    fn write(&self, record: &Record) {
+        let (kind, id) = match (record.level(), record.target()) {
+            (Level::Error, _) => (EVENTLOG_ERROR_TYPE, EVENT_ERROR),
+            (Level::Warn, _) => (EVENTLOG_WARNING_TYPE, EVENT_WARNING),
+            (Level::Info, logger::HIDDEN) => (EVENTLOG_INFORMATION_TYPE, EVENT_HIDDEN),
+            _ => return,
+        };
+        self.report(kind, id, &record.args().to_string());
+    }
+
+    // Write an event with a single message. Failures are ignored, since the message was already
+    // printed.
+    fn report(&self, kind: WORD, id: u32, message: &str) {
+        let message = wide(message);
+        let mut strings = [message.as_ptr()];
+        unsafe {
+            ReportEventW(
+                self.handle,
+                kind,
+                0,
+                id,
+                ptr::null_mut(),
+                1,
+                0,
+                strings.as_mut_ptr(),
+                ptr::null_mut(),
+            );
+        }
+    }
+}
+
+impl Drop for EventLog {
+    fn drop(&mut self) {
+        self.report(EVENTLOG_INFORMATION_TYPE, EVENT_STOPPED, "filehider stopped");
+        unsafe {
+            DeregisterEventSource(self.handle);
+        }
+    }
+}
+
+// Helper function to convert a string to a null terminated wide string
+fn wide(value: &str) -> Vec<u16> {
+    OsStr::new(value).encode_wide().chain(Some(0)).collect()
+}
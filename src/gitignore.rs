@@ -0,0 +1,106 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    Match,
+};
+use log::warn;
+
+// Checks whether git would ignore a path, for --gitignore. Paths are only ignored inside a git
+// repository, by the .gitignore files in the directories from the repository root down to the
+// path, then by .git/info/exclude, then by the global excludes file. Rules are read the first time
+// a directory is checked, and kept until the config file is reloaded.
+pub struct GitIgnored {
+    // The rules of each directory checked so far, or None if it has no rules
+    rules: Mutex<HashMap<PathBuf, Option<Arc<Gitignore>>>>,
+    global: Option<Gitignore>,
+}
+
+impl GitIgnored {
+    pub fn new() -> GitIgnored {
+        let (global, error) = Gitignore::global();
+        if let Some(e) = error {
+            warn!("Failed to read the global git excludes file: {}", e);
+        }
+
+        GitIgnored {
+            rules: Mutex::new(HashMap::new()),
+            global: (!global.is_empty()).then_some(global),
+        }
+    }
+
+    // Check if git would ignore a path, either itself or because a directory it's in is ignored
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+
+        // Nothing in git's own directory is ignored, and nothing outside a repository is
+        if path.components().any(|component| component.as_os_str() == ".git") {
+            return false;
+        }
+        let directories: Vec<&Path> = path.ancestors().skip(1).collect();
+        let Some(root) = directories.iter().position(|directory| is_repository(directory)) else {
+            return false;
+        };
+
+        // Deeper rules take precedence, so the first directory with a matching rule decides
+        for (index, directory) in directories[..=root].iter().enumerate() {
+            let Some(rules) = self.rules_for(directory, index == root) else {
+                continue;
+            };
+            match rules.matched_path_or_any_parents(&path, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => {}
+            }
+        }
+
+        self.global.as_ref().is_some_and(|global| {
+            matches!(global.matched_path_or_any_parents(&path, is_dir), Match::Ignore(_))
+        })
+    }
+
+    // Get the rules of a directory, reading them if it wasn't checked yet. The rules of the
+    // repository root include .git/info/exclude, which its .gitignore takes precedence over.
+    fn rules_for(&self, directory: &Path, root: bool) -> Option<Arc<Gitignore>> {
+        let mut rules = self.rules.lock().unwrap_or_else(|e| e.into_inner());
+        rules
+            .entry(directory.to_path_buf())
+            .or_insert_with(|| {
+                let mut builder = GitignoreBuilder::new(directory);
+                if root {
+                    add_rules(&mut builder, &directory.join(".git").join("info").join("exclude"));
+                }
+                add_rules(&mut builder, &directory.join(".gitignore"));
+
+                match builder.build() {
+                    Ok(gitignore) if !gitignore.is_empty() => Some(Arc::new(gitignore)),
+                    Ok(_) => None,
+                    Err(e) => {
+                        warn!("Failed to read git ignore rules in {}: {}", directory.display(), e);
+                        None
+                    }
+                }
+            })
+            .clone()
+    }
+}
+
+// Helper function to add the rules in a file to a builder, if it exists. Invalid lines are warned
+// about and skipped, like git does.
+fn add_rules(builder: &mut GitignoreBuilder, path: &Path) {
+    if path.is_file() {
+        if let Some(e) = builder.add(path) {
+            warn!("Invalid rule in {}: {}", path.display(), e);
+        }
+    }
+}
+
+// Helper function to check if a directory is the root of a git repository. ".git" is a file in
+// worktrees and submodules.
+fn is_repository(directory: &Path) -> bool {
+    directory.join(".git").exists()
+}
@@ -0,0 +1,190 @@
+use std::{
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Stdio},
+    sync::{Arc, Mutex},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Context, Result};
+use log::{debug, error};
+
+// How often a running hook is checked on while waiting for it to finish
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// A hook to run and the path it's run for, queued for the hook thread
+type HookJob = (Arc<Hook>, PathBuf, &'static str);
+
+// The thread hooks run on in watch mode, once it's started
+static WORKER: Mutex<Option<(crossbeam_channel::Sender<HookJob>, JoinHandle<()>)>> =
+    Mutex::new(None);
+
+// A command run for each path hidden, from --on-hide. It's run through the shell, with "{}"
+// replaced by the path, or the path added as the last argument if there's no "{}".
+#[derive(Debug)]
+pub struct Hook {
+    command: String,
+    timeout: Duration,
+}
+
+impl Hook {
+    pub fn new(command: String, timeout: Duration) -> Hook {
+        Hook { command, timeout }
+    }
+
+    // Run the hook for a path that was hidden or unhidden. Failures are reported, but don't affect
+    // the path or the rest of the run.
+    fn run(&self, path: &Path, action: &str) {
+        debug!("Running --on-hide command for {}", path.display());
+        let result = self.spawn(path, action).and_then(|child| wait(child, self.timeout));
+        match result {
+            Ok(status) if status.success() => {}
+            Ok(status) => error!(
+                "--on-hide command failed for {}: {}",
+                path.display(),
+                status
+            ),
+            Err(e) => error!("--on-hide command failed for {}: {:#}", path.display(), e),
+        }
+    }
+
+    // Helper function to start the command through the shell. Its output goes to stderr, so stdout
+    // is left for --print0 and --format json.
+    fn spawn(&self, path: &Path, action: &str) -> Result<std::process::Child> {
+        let mut command = shell_command(&self.command, path);
+        command
+            .env("FILEHIDER_PATH", path)
+            .env("FILEHIDER_ACTION", action)
+            .stdin(Stdio::null())
+            .stdout(std::io::stderr());
+        command
+            .spawn()
+            .with_context(|| format!("Failed to run \"{}\"", self.command))
+    }
+}
+
+// A command that decides whether a path the other filters matched is hidden, from --match-command.
+// It's run through the shell like a hook, with the path in FILEHIDER_PATH as well, and the path is
+// only hidden if it exits with 0.
+#[derive(Debug)]
+pub struct MatchCommand {
+    command: String,
+    timeout: Duration,
+}
+
+impl MatchCommand {
+    pub fn new(command: String, timeout: Duration) -> MatchCommand {
+        MatchCommand { command, timeout }
+    }
+
+    // Run the command for a path and check whether it accepts it. Failing to run it, or running
+    // past the timeout, is an error for the path.
+    pub fn accepts(&self, path: &Path) -> Result<bool> {
+        debug!("Running --match-command for {}", path.display());
+        let mut command = shell_command(&self.command, path);
+        command
+            .env("FILEHIDER_PATH", path)
+            .stdin(Stdio::null())
+            .stdout(std::io::stderr());
+        let status = command
+            .spawn()
+            .with_context(|| format!("Failed to run \"{}\"", self.command))
+            .and_then(|child| wait(child, self.timeout))
+            .with_context(|| format!("--match-command failed for {}!", path.display()))?;
+        Ok(status.success())
+    }
+}
+
+// Helper function to wait for a command to exit, killing it once it runs past the timeout
+fn wait(mut child: std::process::Child, timeout: Duration) -> Result<ExitStatus> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(anyhow!(
+                "killed after running for {}",
+                humantime::format_duration(timeout)
+            ));
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+// Start running hooks on their own thread, one at a time in the order they were queued, so a slow
+// hook can't hold up watch mode
+pub fn start_worker() {
+    let mut worker = WORKER.lock().unwrap_or_else(|e| e.into_inner());
+    if worker.is_some() {
+        return;
+    }
+
+    let (sender, receiver) = crossbeam_channel::unbounded::<HookJob>();
+    let thread = std::thread::spawn(move || {
+        for (hook, path, action) in receiver {
+            hook.run(&path, action);
+        }
+    });
+    *worker = Some((sender, thread));
+}
+
+// Run a hook for a path, on the hook thread if it's started, and right away otherwise
+pub fn submit(hook: &Arc<Hook>, path: &Path, action: &'static str) {
+    let worker = WORKER.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((sender, _)) = worker.as_ref() {
+        let _ = sender.send((Arc::clone(hook), path.to_path_buf(), action));
+        return;
+    }
+    drop(worker);
+
+    hook.run(path, action);
+}
+
+// Wait for the hooks still queued to finish, and stop the hook thread
+pub fn finish() {
+    let worker = WORKER.lock().unwrap_or_else(|e| e.into_inner()).take();
+    if let Some((sender, thread)) = worker {
+        // Closing the queue stops the thread once it's empty
+        drop(sender);
+        let _ = thread.join();
+    }
+}
+
+// Helper function to build the shell command for a hook or --match-command. On Unix, the path is
+// passed as "$1" so it never has to be quoted.
+#[cfg(unix)]
+fn shell_command(command: &str, path: &Path) -> Command {
+    let script = if command.contains("{}") {
+        command.replace("{}", "\"$1\"")
+    } else {
+        format!("{} \"$1\"", command)
+    };
+
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(script).arg("filehider").arg(path);
+    shell
+}
+
+// Helper function to build the shell command for a hook or --match-command. On Windows, cmd.exe has
+// no positional arguments, so "{}" becomes "%FILEHIDER_PATH%" in quotes. cmd.exe expands it once,
+// so a "%" in the path itself is kept as it is. The script is passed with raw_arg, since cmd.exe
+// doesn't understand the backslash escaping arg would add to its quotes, and /S makes it strip only
+// the outer quotes added here.
+#[cfg(windows)]
+fn shell_command(command: &str, _path: &Path) -> Command {
+    use std::os::windows::process::CommandExt;
+
+    let quoted = "\"%FILEHIDER_PATH%\"";
+    let script = if command.contains("{}") {
+        command.replace("{}", quoted)
+    } else {
+        format!("{} {}", command, quoted)
+    };
+
+    let mut shell = Command::new("cmd");
+    shell.arg("/S").arg("/C").raw_arg(format!("\"{}\"", script));
+    shell
+}
@@ -0,0 +1,186 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+// An action taken in watch mode, sent to IPC clients as a single line of JSON
+// (e.g. {"action":"hide","path":"/home/user/Downloads/file.txt"})
+#[derive(Debug, Serialize)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+pub enum Action {
+    // A path was hidden
+    Hide { path: String },
+    // A path would have been hidden, but test mode or list mode is enabled
+    WouldHide { path: String },
+    // Handling a path failed
+    Error { path: String, message: String },
+}
+
+impl Action {
+    // Helper function to convert a path for an action. Paths that aren't valid UTF-8 are converted
+    // lossily, since JSON strings have to be valid UTF-8.
+    pub fn path(path: &Path) -> String {
+        path.to_string_lossy().into_owned()
+    }
+}
+
+// Unix domain socket connections on Unix, and named pipe instances on Windows
+#[cfg(unix)]
+type Client = std::os::unix::net::UnixStream;
+#[cfg(windows)]
+type Client = std::fs::File;
+
+// Sink that streams actions as JSON lines to every connected client. Clients are accepted on a
+// background thread, and dropped as soon as writing to them fails (e.g. when they disconnect).
+pub struct IpcSink {
+    path: PathBuf,
+    clients: Arc<Mutex<Vec<Client>>>,
+}
+
+impl IpcSink {
+    // Start listening for clients on a Unix domain socket or a Windows named pipe
+    // (e.g. "/tmp/filehider.sock" or "\\.\pipe\filehider")
+    pub fn listen(path: &Path) -> Result<IpcSink> {
+        let clients = Arc::new(Mutex::new(Vec::new()));
+        accept_clients(path, Arc::clone(&clients))?;
+
+        Ok(IpcSink {
+            path: path.to_path_buf(),
+            clients,
+        })
+    }
+
+    // Send an action to every connected client
+    pub fn send(&self, action: &Action) {
+        let mut line = match serde_json::to_string(action) {
+            Ok(line) => line,
+            Err(e) => {
+                log::error!("Failed to serialize IPC action: {}", e);
+                return;
+            }
+        };
+        line.push('\n');
+
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        clients.retain_mut(|client| client.write_all(line.as_bytes()).is_ok());
+    }
+}
+
+// Remove the socket file when exiting so the next run can bind to it again
+#[cfg(unix)]
+impl Drop for IpcSink {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+// Bind the Unix domain socket and accept clients on a background thread. A socket file left over
+// from a previous run is replaced, but any other existing file is an error.
+#[cfg(unix)]
+fn accept_clients(path: &Path, clients: Arc<Mutex<Vec<Client>>>) -> Result<()> {
+    use std::os::unix::{fs::FileTypeExt, net::UnixListener};
+
+    if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        std::fs::remove_file(path)
+            .with_context(|| format!("Failed to remove old IPC socket {}", path.display()))?;
+    }
+
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("Failed to create IPC socket {}", path.display()))?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    // Don't let a client that stops reading block watch mode
+                    let _ = stream.set_write_timeout(Some(std::time::Duration::from_secs(1)));
+                    clients.lock().unwrap_or_else(|e| e.into_inner()).push(stream);
+                }
+                Err(e) => log::error!("Failed to accept IPC client: {}", e),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+// Create the named pipe and accept clients on a background thread. Each client connects to its own
+// instance of the pipe, so a new instance is created after each connection.
+#[cfg(windows)]
+fn accept_clients(path: &Path, clients: Arc<Mutex<Vec<Client>>>) -> Result<()> {
+    use std::{
+        ffi::OsStr,
+        os::windows::{ffi::OsStrExt, io::FromRawHandle},
+        ptr::null_mut,
+    };
+    use winapi::{
+        shared::winerror::ERROR_PIPE_CONNECTED,
+        um::{
+            handleapi::INVALID_HANDLE_VALUE,
+            namedpipeapi::{ConnectNamedPipe, CreateNamedPipeW},
+            winbase::{PIPE_ACCESS_OUTBOUND, PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT},
+            winnt::HANDLE,
+        },
+    };
+
+    // Convert the path to a null-terminated wide string
+    let name: Vec<u16> = OsStr::new(path).encode_wide().chain(Some(0)).collect();
+
+    let create_pipe = move || -> std::io::Result<HANDLE> {
+        let handle = unsafe {
+            CreateNamedPipeW(
+                name.as_ptr(),
+                PIPE_ACCESS_OUTBOUND,
+                PIPE_TYPE_BYTE | PIPE_WAIT,
+                PIPE_UNLIMITED_INSTANCES,
+                4096,
+                0,
+                0,
+                null_mut(),
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            Err(std::io::Error::last_os_error())
+        } else {
+            Ok(handle)
+        }
+    };
+
+    // Create the first instance up front so an invalid pipe name is reported immediately. Raw
+    // handles aren't Send, so it's passed to the thread as an integer.
+    let first = create_pipe()
+        .with_context(|| format!("Failed to create IPC pipe {}", path.display()))?
+        as usize;
+
+    thread::spawn(move || {
+        let mut handle = first as HANDLE;
+        loop {
+            // A client that connected between creating the instance and waiting for a connection
+            // is reported as ERROR_PIPE_CONNECTED, which is still a success
+            let connected = unsafe { ConnectNamedPipe(handle, null_mut()) } != 0
+                || std::io::Error::last_os_error().raw_os_error()
+                    == Some(ERROR_PIPE_CONNECTED as i32);
+
+            // Take ownership of the handle so it's closed when the client is dropped
+            let client = unsafe { Client::from_raw_handle(handle as _) };
+            if connected {
+                clients.lock().unwrap_or_else(|e| e.into_inner()).push(client);
+            }
+
+            handle = match create_pipe() {
+                Ok(handle) => handle,
+                Err(e) => {
+                    log::error!("Failed to create IPC pipe instance: {}", e);
+                    return;
+                }
+            };
+        }
+    });
+
+    Ok(())
+}
@@ -0,0 +1,414 @@
+use std::{
+    collections::HashSet,
+    ffi::OsStr,
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::{Arc, Mutex, OnceLock},
+    time::SystemTime,
+};
+
+use anyhow::{anyhow, Context, Result};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::EXIT_ERRORS;
+
+// Name of the journal file in the platform data directory
+const JOURNAL_FILE_NAME: &str = "journal.jsonl";
+
+// The attribute and flag that mark a path as hidden on Windows and macOS, so an entry that cleared
+// them can be told apart from one that set them on any platform
+const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+const UF_HIDDEN: u32 = 0x8000;
+
+// Journal of every path hidden, one JSON object per line, so a run can be undone with
+// "filehider undo". Lines are only appended once a hide succeeded, and each one is written with a
+// single write to a file opened for appending, so worker threads and other instances can't
+// interleave them.
+pub struct Journal {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+// A line of the journal
+// (e.g. {"run": "2023-03-01T08:00:00Z-1234", "path": "/home/user/a.tmp", "action": "rename", ...})
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    // Identifies the run that hid the path, so the last run can be undone on its own
+    run: String,
+    time: String,
+    // The path before it was hidden
+    path: PathBuf,
+    #[serde(flatten)]
+    change: Change,
+}
+
+// What hiding a path changed, with what's needed to change it back
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+pub enum Change {
+    // Renamed on non-Windows platforms, by adding or removing the dot
+    Rename { renamed_to: PathBuf },
+    // Attributes set or cleared on Windows, with the attributes the path had before
+    Attributes { previous: u32 },
+    // UF_HIDDEN flag set or cleared on macOS, with the flags the path had before
+    Flags { previous: u32 },
+}
+
+impl Journal {
+    pub fn open(path: &Path) -> Result<Journal> {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent).with_context(|| {
+                format!("Failed to create journal directory {}!", parent.display())
+            })?;
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open journal {}!", path.display()))?;
+
+        Ok(Journal {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+        })
+    }
+
+    // Record a path that was hidden. The path is already hidden, so failing to record it is only a
+    // warning.
+    pub fn record(&self, path: &Path, change: Change) {
+        let entry = Entry::new(path, change);
+        let result = serde_json::to_string(&entry)
+            .map_err(anyhow::Error::from)
+            .and_then(|line| {
+                let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+                Ok(file.write_all(format!("{}\n", line).as_bytes())?)
+            });
+        if let Err(e) = result {
+            warn!(
+                "Failed to record {} in journal {}: {}",
+                path.display(),
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
+
+impl Entry {
+    fn new(path: &Path, change: Change) -> Entry {
+        Entry {
+            run: run_id().to_string(),
+            time: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+            path: path.to_path_buf(),
+            change,
+        }
+    }
+}
+
+// The paths hidden so far in this run, kept in memory for --restore-on-exit so they can be made
+// visible again when watch mode stops, whether or not the journal could be opened
+#[derive(Default)]
+pub struct Session {
+    entries: Mutex<Vec<Entry>>,
+}
+
+impl Session {
+    pub fn record(&self, path: &Path, change: Change) {
+        self.entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(Entry::new(path, change));
+    }
+
+    // Undo every hide of this run, newest first, the same way "filehider undo" does. Returns the
+    // number of errors.
+    pub fn restore_all(&self) -> usize {
+        let entries = std::mem::take(&mut *self.entries.lock().unwrap_or_else(|e| e.into_inner()));
+        if entries.is_empty() {
+            return 0;
+        }
+        info!("Restoring the {} paths hidden in this session", entries.len());
+
+        let (mut restored, mut errors) = (0, 0);
+        for entry in entries.iter().rev() {
+            match restore(entry) {
+                Ok(true) => {
+                    debug!("Restored {}", entry.path.display());
+                    restored += 1;
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    error!("{:#}", e);
+                    errors += 1;
+                }
+            }
+        }
+
+        info!("Restored {} paths, {} errors", restored, errors);
+        errors
+    }
+}
+
+// Restores a session once it's dropped, so the paths watch mode hid are made visible again with
+// --restore-on-exit however it stops, including on errors. Restoring the session before that
+// leaves nothing for it to do.
+pub struct RestoreOnExit(pub Option<Arc<Session>>);
+
+impl Drop for RestoreOnExit {
+    fn drop(&mut self) {
+        if let Some(session) = &self.0 {
+            session.restore_all();
+        }
+    }
+}
+
+// The journal in the platform data directory
+// (e.g. "~/.local/share/filehider/journal.jsonl" on Linux)
+pub fn default_path() -> Option<PathBuf> {
+    directories::ProjectDirs::from("", "", "filehider")
+        .map(|dirs| dirs.data_dir().join(JOURNAL_FILE_NAME))
+}
+
+// Identifier of this run, from the time it started and the process id. Config reloads in watch
+// mode keep it, so a whole watch session is undone together.
+fn run_id() -> &'static str {
+    static RUN_ID: OnceLock<String> = OnceLock::new();
+    RUN_ID.get_or_init(|| {
+        format!(
+            "{}-{}",
+            humantime::format_rfc3339_seconds(SystemTime::now()),
+            std::process::id()
+        )
+    })
+}
+
+// Read the paths a journal records hiding, as they are now, for --reveal-all. The entries are
+// replayed in order, so paths inside a directory hidden later move along with it, and paths
+// unhidden since are dropped. Undone entries are already gone from the journal. Lines that can't
+// be parsed are ignored.
+pub fn hidden_paths(path: &Path, hidden_prefix: &str) -> Result<HashSet<PathBuf>> {
+    let contents = fs::read_to_string(path).with_context(|| {
+        format!(
+            "Failed to read journal {}! Pass --force to unhide every hidden path.",
+            path.display()
+        )
+    })?;
+
+    let mut hidden: HashSet<PathBuf> = HashSet::new();
+    for entry in contents
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Entry>(line).ok())
+    {
+        // Whether the entry unhid the path rather than hiding it
+        let unhid = match &entry.change {
+            Change::Rename { renamed_to } => {
+                // Paths inside a renamed directory were recorded under its old name
+                let moved: Vec<PathBuf> = hidden
+                    .iter()
+                    .filter(|hidden| hidden.starts_with(&entry.path) && **hidden != entry.path)
+                    .cloned()
+                    .collect();
+                for path in moved {
+                    hidden.remove(&path);
+                    if let Ok(relative) = path.strip_prefix(&entry.path) {
+                        hidden.insert(renamed_to.join(relative));
+                    }
+                }
+
+                entry
+                    .path
+                    .file_name()
+                    .and_then(OsStr::to_str)
+                    .is_some_and(|name| name.starts_with(hidden_prefix))
+            }
+            Change::Attributes { previous } => previous & FILE_ATTRIBUTE_HIDDEN != 0,
+            Change::Flags { previous } => previous & UF_HIDDEN != 0,
+        };
+
+        if unhid {
+            hidden.remove(&entry.path);
+        } else {
+            hidden.insert(match entry.change {
+                Change::Rename { renamed_to } => renamed_to,
+                Change::Attributes { .. } | Change::Flags { .. } => entry.path,
+            });
+        }
+    }
+    Ok(hidden)
+}
+
+// Undo the hides recorded in a journal, newest first, either for the last run or for all of them.
+// Paths that no longer exist are skipped. Undone and skipped entries are removed from the journal,
+// so undoing again moves on to the run before.
+pub fn undo(path: &Path, last_only: bool) -> Result<ExitCode> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read journal {}!", path.display()))?;
+
+    // Lines that can't be parsed are kept as they are and otherwise ignored
+    let lines: Vec<(&str, Option<Entry>)> = contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| (line, serde_json::from_str(line).ok()))
+        .collect();
+
+    let last_run = lines
+        .iter()
+        .rev()
+        .find_map(|(_, entry)| entry.as_ref().map(|entry| entry.run.clone()));
+    let Some(last_run) = last_run else {
+        info!("Nothing to undo in journal {}", path.display());
+        return Ok(ExitCode::SUCCESS);
+    };
+
+    let selected = |entry: &Entry| !last_only || entry.run == last_run;
+
+    let (mut restored, mut skipped, mut errors) = (0, 0, 0);
+    let entries = lines.iter().rev().filter_map(|(_, entry)| entry.as_ref());
+    for entry in entries.filter(|entry| selected(entry)) {
+        match restore(entry) {
+            Ok(true) => {
+                info!("Restored {}", entry.path.display());
+                restored += 1;
+            }
+            Ok(false) => skipped += 1,
+            Err(e) => {
+                error!("{:#}", e);
+                errors += 1;
+            }
+        }
+    }
+
+    // Keep the entries that failed to undo, so they can be tried again
+    if errors == 0 {
+        let kept: String = lines
+            .iter()
+            .filter(|(_, entry)| !entry.as_ref().is_some_and(selected))
+            .map(|(line, _)| format!("{}\n", line))
+            .collect();
+        fs::write(path, kept)
+            .with_context(|| format!("Failed to update journal {}!", path.display()))?;
+    } else {
+        warn!(
+            "Keeping journal {} as it is, since some paths couldn't be restored",
+            path.display()
+        );
+    }
+
+    info!(
+        "Undo finished: restored {} paths, skipped {} that no longer exist, {} errors",
+        restored, skipped, errors
+    );
+
+    Ok(if errors > 0 {
+        ExitCode::from(EXIT_ERRORS)
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+// Undo a single entry. Returns false if it was skipped because the path no longer exists.
+fn restore(entry: &Entry) -> Result<bool> {
+    match &entry.change {
+        Change::Rename { renamed_to } => {
+            if fs::symlink_metadata(renamed_to).is_err() {
+                debug!("Skipping {}, which no longer exists", renamed_to.display());
+                return Ok(false);
+            }
+            if fs::symlink_metadata(&entry.path).is_ok() {
+                return Err(anyhow!(
+                    "Failed to restore {}: {} already exists!",
+                    renamed_to.display(),
+                    entry.path.display()
+                ));
+            }
+
+            fs::rename(renamed_to, &entry.path)
+                .with_context(|| format!("Failed to rename path {}", renamed_to.display()))?;
+            Ok(true)
+        }
+        Change::Attributes { previous } => {
+            if fs::symlink_metadata(&entry.path).is_err() {
+                debug!("Skipping {}, which no longer exists", entry.path.display());
+                return Ok(false);
+            }
+            restore_attributes(&entry.path, *previous)?;
+            Ok(true)
+        }
+        Change::Flags { previous } => {
+            if fs::symlink_metadata(&entry.path).is_err() {
+                debug!("Skipping {}, which no longer exists", entry.path.display());
+                return Ok(false);
+            }
+            restore_flags(&entry.path, *previous)?;
+            Ok(true)
+        }
+    }
+}
+
+// Windows only function to set the attributes a path had before it was hidden
+#[cfg(windows)]
+fn restore_attributes(path: &Path, previous: u32) -> Result<()> {
+    use std::{ffi::OsStr, io::Error, os::windows::ffi::OsStrExt};
+
+    use winapi::{
+        shared::minwindef::FALSE,
+        um::{fileapi::SetFileAttributesW, winnt::FILE_ATTRIBUTE_NORMAL},
+    };
+
+    let os_path = OsStr::new(path)
+        .encode_wide()
+        .chain(Some(0))
+        .collect::<Vec<_>>();
+
+    // A path with no attributes at all has to be set to FILE_ATTRIBUTE_NORMAL instead
+    let attributes = if previous == 0 {
+        FILE_ATTRIBUTE_NORMAL
+    } else {
+        previous
+    };
+
+    if unsafe { SetFileAttributesW(os_path.as_ptr(), attributes) } == FALSE {
+        return Err::<(), anyhow::Error>(Error::last_os_error().into())
+            .with_context(|| format!("Failed to restore attributes of path {}", path.display()));
+    }
+    Ok(())
+}
+
+#[cfg(not(windows))]
+fn restore_attributes(path: &Path, _previous: u32) -> Result<()> {
+    Err(anyhow!(
+        "Failed to restore {}: attributes can only be restored on Windows!",
+        path.display()
+    ))
+}
+
+// macOS only function to set the flags a directory had before it was hidden from Finder
+#[cfg(target_os = "macos")]
+fn restore_flags(path: &Path, previous: u32) -> Result<()> {
+    use std::{ffi::CString, io::Error, os::unix::ffi::OsStrExt};
+
+    let c_path = CString::new(path.as_os_str().as_bytes()).with_context(|| {
+        format!(
+            "Failed to convert path to C string for path {}",
+            path.display()
+        )
+    })?;
+
+    if unsafe { libc::chflags(c_path.as_ptr(), previous) } != 0 {
+        return Err::<(), anyhow::Error>(Error::last_os_error().into())
+            .with_context(|| format!("Failed to restore flags of path {}", path.display()));
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "macos"))]
+fn restore_flags(path: &Path, _previous: u32) -> Result<()> {
+    Err(anyhow!(
+        "Failed to restore {}: Finder flags can only be restored on macOS!",
+        path.display()
+    ))
+}
@@ -0,0 +1,94 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use fslock::LockFile;
+
+// Lock held while hiding in a set of directories, so a second instance started on the same
+// directories refuses to run instead of racing to rename the same paths. The lock is released when
+// it's dropped, and by the OS if the process exits any other way (e.g. a second Ctrl+C).
+pub struct InstanceLock {
+    _file: LockFile,
+}
+
+// Result of trying to take the lock
+pub enum Acquired {
+    Locked(InstanceLock),
+    // Another instance holds the lock, with its PID if it could be read
+    HeldBy(Option<u32>),
+}
+
+impl InstanceLock {
+    // Take the lock for the given directories. The lock file is named after a hash of the
+    // directories, which are already canonicalized, so the same set always maps to the same file.
+    pub fn acquire(directories: &HashSet<PathBuf>) -> Result<Acquired> {
+        let dir = lock_dir();
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create lock directory {}!", dir.display()))?;
+
+        let path = dir.join(format!("filehider-{:016x}.lock", hash_directories(directories)));
+        let mut file = LockFile::open(&path)
+            .with_context(|| format!("Failed to open lock file {}!", path.display()))?;
+
+        if file
+            .try_lock_with_pid()
+            .with_context(|| format!("Failed to lock {}!", path.display()))?
+        {
+            Ok(Acquired::Locked(InstanceLock { _file: file }))
+        } else {
+            Ok(Acquired::HeldBy(read_pid(&path)))
+        }
+    }
+}
+
+// The platform runtime directory if there is one (e.g. "/run/user/1000/filehider" on Linux), or
+// the temp directory otherwise
+fn lock_dir() -> PathBuf {
+    directories::ProjectDirs::from("", "", "filehider")
+        .and_then(|dirs| dirs.runtime_dir().map(Path::to_path_buf))
+        .unwrap_or_else(|| std::env::temp_dir().join("filehider"))
+}
+
+// Helper function to hash a set of directories independently of their order. This uses 64-bit
+// FNV-1a over the raw bytes of the sorted paths, each followed by a NUL byte, rather than the
+// standard library's hasher, whose algorithm can change between Rust releases. Otherwise binaries
+// built with different compilers (e.g. an old watcher still running after an upgrade) could lock
+// different files for the same directories.
+fn hash_directories(directories: &HashSet<PathBuf>) -> u64 {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    let mut sorted: Vec<&PathBuf> = directories.iter().collect();
+    sorted.sort();
+
+    sorted
+        .iter()
+        .flat_map(|directory| crate::path_to_bytes(directory).into_iter().chain([0]))
+        .fold(FNV_OFFSET_BASIS, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+        })
+}
+
+// Helper function to read the PID the holder of the lock wrote to it. Returns None if it can't be
+// read, e.g. because the holder hasn't written it yet.
+fn read_pid(path: &Path) -> Option<u32> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The hash is fixed by definition, so every build locks the same file for the same directories
+    #[cfg(unix)]
+    #[test]
+    fn hash_directories_is_stable() {
+        let directories: HashSet<PathBuf> =
+            ["/srv/b", "/srv/a"].into_iter().map(PathBuf::from).collect();
+
+        assert_eq!(hash_directories(&directories), 0x5e1a_2032_6563_4f6e);
+    }
+}
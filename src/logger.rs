@@ -0,0 +1,362 @@
+use std::{
+    fmt::Display,
+    fs::{self, File, OpenOptions},
+    io::{IsTerminal, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, OnceLock,
+    },
+};
+
+use anyhow::{Context, Result};
+use chrono::{Local, Utc};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::ColorChoice;
+
+// Log targets for the messages about a path being hidden or skipped, which are styled differently
+// from other messages (e.g. info!(target: logger::HIDDEN, ...))
+pub const HIDDEN: &str = "hidden";
+pub const WOULD_HIDE: &str = "would-hide";
+pub const ALREADY_HIDDEN: &str = "already-hidden";
+pub const SKIPPED: &str = "skipped";
+
+// ANSI escape codes for the styles used
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+// Size at which the log file is rotated when --log-max-size isn't given
+pub const DEFAULT_LOG_MAX_SIZE: u64 = 10 * 1024 * 1024;
+
+// The file messages are also written to with --log-file, once it's opened
+static LOG_FILE: OnceLock<LogFile> = OnceLock::new();
+
+// Whether printed messages start with a timestamp, and whether timestamps are in UTC rather than
+// local time, from --timestamps and --utc
+static TIMESTAMPS: AtomicBool = AtomicBool::new(false);
+static UTC: AtomicBool = AtomicBool::new(false);
+
+// RFC 3339 with the offset from UTC, to the second (e.g. "2023-03-01T08:00:00+01:00")
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%:z";
+
+// Logger for the messages printed while running. Errors and warnings go to stderr, and everything
+// else goes to stdout, unless stdout is reserved for bare paths.
+struct Logger {
+    stdout: bool,
+    // Whether to color the messages printed to each stream
+    color_stdout: bool,
+    color_stderr: bool,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let style = match (record.level(), record.target()) {
+            (Level::Error, _) => Some(RED),
+            (_, HIDDEN) => Some(GREEN),
+            (_, WOULD_HIDE | SKIPPED) => Some(YELLOW),
+            (_, ALREADY_HIDDEN) => Some(DIM),
+            _ => None,
+        };
+        let prefix = if record.level() == Level::Warn {
+            "Warning: "
+        } else {
+            ""
+        };
+
+        let to_stdout = self.stdout && record.level() > Level::Warn;
+        let color = if to_stdout {
+            self.color_stdout
+        } else {
+            self.color_stderr
+        };
+
+        let (style, reset) = match style {
+            Some(style) if color => (style, RESET),
+            _ => ("", ""),
+        };
+
+        // Written straight to the locked stream, so nothing is allocated for the message. Printing
+        // only fails once the stream is closed, and then there's nowhere left to report it.
+        let timestamp = TIMESTAMPS.load(Ordering::Relaxed).then(timestamp);
+        let styled = (style, prefix, reset);
+        let _ = if to_stdout {
+            print_line(&mut std::io::stdout().lock(), timestamp, styled, record)
+        } else {
+            print_line(&mut std::io::stderr().lock(), timestamp, styled, record)
+        };
+
+        if let Some(file) = LOG_FILE.get() {
+            file.write(record);
+        }
+
+        #[cfg(windows)]
+        crate::eventlog::write(record);
+
+        #[cfg(all(unix, feature = "syslog"))]
+        crate::system_log::write(record);
+    }
+
+    fn flush(&self) {}
+}
+
+// Install the logger, printing messages up to the given level. Messages other than errors and
+// warnings go to stdout if it's allowed, and to stderr otherwise. With "auto", each stream is only
+// colored if it's a terminal and NO_COLOR isn't set (https://no-color.org).
+pub fn init(level: LevelFilter, stdout: bool, color: ColorChoice) {
+    let use_color = |is_terminal: bool| match color {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            is_terminal && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+        }
+    };
+
+    let logger = Logger {
+        stdout,
+        color_stdout: use_color(std::io::stdout().is_terminal()) && enable_ansi(false),
+        color_stderr: use_color(std::io::stderr().is_terminal()) && enable_ansi(true),
+    };
+
+    // Setting the logger only fails if one was already set
+    if log::set_boxed_logger(Box::new(logger)).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+// Helper function to print a message on its own line, after its timestamp if it has one. The style,
+// prefix and reset code surround the message itself.
+fn print_line(
+    stream: &mut impl Write,
+    timestamp: Option<impl Display>,
+    (style, prefix, reset): (&str, &str, &str),
+    record: &Record,
+) -> std::io::Result<()> {
+    if let Some(timestamp) = timestamp {
+        write!(stream, "{} ", timestamp)?;
+    }
+    writeln!(stream, "{}{}{}{}", style, prefix, record.args(), reset)
+}
+
+// Start printed messages with a timestamp or not, and give every timestamp in UTC or local time
+pub fn set_timestamps(enabled: bool, utc: bool) {
+    TIMESTAMPS.store(enabled, Ordering::Relaxed);
+    UTC.store(utc, Ordering::Relaxed);
+}
+
+// Get the current time as an RFC 3339 timestamp, in UTC with --utc and local time otherwise. It's
+// only formatted when it's printed.
+pub fn timestamp() -> impl Display {
+    if UTC.load(Ordering::Relaxed) {
+        Utc::now().format(TIMESTAMP_FORMAT)
+    } else {
+        Local::now().format(TIMESTAMP_FORMAT)
+    }
+}
+
+// Log file for --log-file, written to as well as the terminal so a background service still leaves
+// a trace. Once it grows past the maximum size, it's renamed to "<path>.1" (shifting older files to
+// "<path>.2" and so on, up to --log-keep of them) and a new one is started. Each new file starts
+// with the PID and the effective configuration.
+struct LogFile {
+    path: PathBuf,
+    max_size: u64,
+    keep: u32,
+    header: String,
+    state: Mutex<LogState>,
+}
+
+struct LogState {
+    // Closed after a failed write, and opened again on the next message
+    file: Option<File>,
+    size: u64,
+    // Whether the last write failed, so a failure is only reported once until writing works again
+    failed: bool,
+}
+
+impl LogFile {
+    fn write(&self, record: &Record) {
+        let line = format!("{} {:<5} {}\n", timestamp(), record.level(), record.args());
+
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let result = self.write_line(&mut state, &line);
+        match result {
+            Ok(()) => state.failed = false,
+            // Reported on stderr directly, since logging it would write to the file again. The
+            // file is dropped so it's reopened on the next message, in case it was deleted.
+            Err(e) => {
+                state.file = None;
+                if !state.failed {
+                    state.failed = true;
+                    eprintln!(
+                        "Warning: Failed to write to log file {}, only printing messages until \
+                        it works again: {}",
+                        self.path.display(),
+                        e
+                    );
+                }
+            }
+        }
+    }
+
+    fn write_line(&self, state: &mut LogState, line: &str) -> std::io::Result<()> {
+        // A file with nothing but the header isn't rotated, even if the header alone is too big
+        if state.size > self.header.len() as u64 && state.size + line.len() as u64 > self.max_size {
+            state.file = None;
+            self.rotate()?;
+            state.size = 0;
+        }
+
+        let file = match &mut state.file {
+            Some(file) => file,
+            None => {
+                let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+                state.size = file.metadata()?.len();
+                // Only a new file gets the header again
+                if state.size == 0 {
+                    file.write_all(self.header.as_bytes())?;
+                    state.size = self.header.len() as u64;
+                }
+                state.file.insert(file)
+            }
+        };
+
+        file.write_all(line.as_bytes())?;
+        state.size += line.len() as u64;
+        Ok(())
+    }
+
+    // Shift the older files along, dropping the oldest, and move the current file to "<path>.1"
+    fn rotate(&self) -> std::io::Result<()> {
+        for index in (1..self.keep).rev() {
+            let from = rotated_path(&self.path, index);
+            if from.exists() {
+                fs::rename(&from, rotated_path(&self.path, index + 1))?;
+            }
+        }
+
+        if self.keep > 0 {
+            fs::rename(&self.path, rotated_path(&self.path, 1))
+        } else {
+            fs::remove_file(&self.path)
+        }
+    }
+}
+
+// Start writing messages to a log file as well, rotating it once it grows past max_size and
+// keeping the given number of old files. The header is written at the top of the file now, and of
+// every new file after a rotation. Failing to open it at first is an error, but failing to write to
+// it later is only a warning, so watch mode keeps running.
+pub fn log_to_file(path: &Path, max_size: u64, keep: u32, header: String) -> Result<()> {
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create log directory {}!", parent.display()))?;
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open log file {}!", path.display()))?;
+    let size = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+
+    // A file left by an earlier run is appended to, with the header marking where this run starts
+    let separator = if size > 0 { "\n" } else { "" };
+    file.write_all(format!("{}{}", separator, header).as_bytes())
+        .with_context(|| format!("Failed to write to log file {}!", path.display()))?;
+    let size = size + (separator.len() + header.len()) as u64;
+
+    let log_file = LogFile {
+        path: path.to_path_buf(),
+        max_size,
+        keep,
+        header,
+        state: Mutex::new(LogState {
+            file: Some(file),
+            size,
+            failed: false,
+        }),
+    };
+
+    // Only fails if a log file was already set
+    let _ = LOG_FILE.set(log_file);
+    Ok(())
+}
+
+// The path a log file is moved to when it's rotated (e.g. "filehider.log.1")
+pub fn rotated_path(path: &Path, index: u32) -> PathBuf {
+    let mut rotated = path.as_os_str().to_os_string();
+    rotated.push(format!(".{}", index));
+    PathBuf::from(rotated)
+}
+
+// Parse a size for --log-max-size, as a number of bytes with an optional unit. Units are powers of
+// 1024 (e.g. "500K", "10MB", or "1GiB").
+pub fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let split = size.find(|c: char| !c.is_ascii_digit()).unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid size \"{}\", expected e.g. \"10MB\"", size))?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1024,
+        "m" | "mb" | "mib" => 1024 * 1024,
+        "g" | "gb" | "gib" => 1024 * 1024 * 1024,
+        unit => return Err(format!("unknown size unit \"{}\", expected K, M, or G", unit)),
+    };
+
+    match number.checked_mul(multiplier) {
+        Some(0) => Err("size must be more than 0 bytes".to_string()),
+        Some(bytes) => Ok(bytes),
+        None => Err(format!("size \"{}\" is too large", size)),
+    }
+}
+
+// Windows consoles only understand ANSI escape codes once virtual terminal processing is turned on,
+// which needs Windows 10 or later. Returns false if it can't be, so plain text is printed instead.
+// Streams that aren't consoles (e.g. pipes) are left alone.
+#[cfg(windows)]
+fn enable_ansi(stderr: bool) -> bool {
+    use winapi::um::{
+        consoleapi::{GetConsoleMode, SetConsoleMode},
+        processenv::GetStdHandle,
+        winbase::{STD_ERROR_HANDLE, STD_OUTPUT_HANDLE},
+        wincon::ENABLE_VIRTUAL_TERMINAL_PROCESSING,
+    };
+
+    unsafe {
+        let handle = GetStdHandle(if stderr {
+            STD_ERROR_HANDLE
+        } else {
+            STD_OUTPUT_HANDLE
+        });
+
+        let mut mode = 0;
+        if GetConsoleMode(handle, &mut mode) == 0 {
+            return true;
+        }
+
+        mode & ENABLE_VIRTUAL_TERMINAL_PROCESSING != 0
+            || SetConsoleMode(handle, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING) != 0
+    }
+}
+
+// Every other terminal understands ANSI escape codes
+#[cfg(not(windows))]
+fn enable_ansi(_stderr: bool) -> bool {
+    true
+}
@@ -1,7 +1,59 @@
-use std::{collections::HashSet, fs, path::{Path, PathBuf}};
+use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    ffi::OsStr,
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    io::IsTerminal,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
 
 use anyhow::{anyhow, Context, Result};
-use clap::{Parser, ValueEnum};
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum, ValueHint};
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::{Deserialize, Serialize};
+
+use config::Config;
+use gitignore::GitIgnored;
+use hooks::{Hook, MatchCommand};
+use ipc::{Action, IpcSink};
+use journal::{Change, Journal, RestoreOnExit, Session};
+use output::{EventKind, JsonOutput, PathAction};
+use report::Report;
+use schedule::ActiveWindow;
+use script::Script;
+use log::{debug, error, info, trace, warn, LevelFilter};
+use regex::{Regex, RegexBuilder};
+
+mod backend;
+mod benchmark;
+mod config;
+#[cfg(windows)]
+mod eventlog;
+mod gitignore;
+mod hooks;
+mod ipc;
+mod journal;
+mod lock;
+mod logger;
+#[cfg(feature = "metrics")]
+mod metrics;
+#[cfg(feature = "notifications")]
+mod notifications;
+mod output;
+mod replay;
+mod report;
+mod schedule;
+mod script;
+mod signals;
+#[cfg(all(unix, feature = "syslog"))]
+mod system_log;
 
 // Number of errors to allow before exiting
 const ERROR_LIMIT: usize = 20;
@@ -9,516 +61,5385 @@ const ERROR_LIMIT: usize = 20;
 // Time limit for errors to occur within (in seconds)
 const ERROR_TIME_LIMIT: u64 = 5;
 
+// Exit code for a run that finished, but failed to handle some paths. Fatal errors exit with 1.
+const EXIT_ERRORS: u8 = 2;
+
+// Exit code for test mode or list mode finding paths to hide, with --fail-on-match
+const EXIT_MATCHES: u8 = 3;
+
+// Exit code for refusing to start because another instance is hiding in the same directories
+const EXIT_LOCKED: u8 = 4;
+
+// Description of the exit codes, shown at the end of the help message
+const EXIT_CODES_HELP: &str = "Exit codes:
+  0  Success
+  1  Fatal error (e.g. invalid arguments, or a directory that can't be read)
+  2  Finished, but some paths couldn't be handled
+  3  Test mode or list mode found paths to hide, with --fail-on-match
+  4  Another instance is already hiding in the same directories";
+
+// How often watch mode first checks whether a watched directory that was removed is back, and the
+// longest it waits between checks as they back off. Also how often every watched directory is
+// checked for still existing, for backends that don't report removing one.
+const MISSING_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const MISSING_POLL_MAX_INTERVAL: Duration = Duration::from_secs(60);
+
+// The longest watch mode waits for an event before checking whether Ctrl+C was pressed
+const INTERRUPT_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+// Whether Ctrl+C was pressed, so immediate mode and watch mode stop at the next path or event
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// The shortest time between the sweeps watch mode runs after its event queue overflows
+const OVERFLOW_SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+// Number of watch mode events that can be waiting for a worker thread before the watch loop waits
+const WATCH_QUEUE_SIZE: usize = 1024;
+
 #[derive(Debug, Parser)]
-#[clap(version)]
+#[clap(version, after_help = EXIT_CODES_HELP)]
 struct Args {
-    /// The directories to watch
-    /// (e.g. "C:\Users\user\Documents" or "test/test")
-    #[clap(value_parser, num_args = 1.., required = true, verbatim_doc_comment)]
+    /// The directories to watch, or files to hide directly. Pass "-" to read paths from stdin.
+    /// Glob patterns are expanded to the directories they match (e.g. "D:\Projects\*\build").
+    /// (e.g. "C:\Users\user\Documents" or "test/test" or "test/file.txt")
+    /// [env: FILEHIDER_DIRS, comma separated]
+    /// [default: the current directory]
+    #[clap(value_parser, num_args = 0.., value_hint = ValueHint::AnyPath, verbatim_doc_comment)]
     directories: Vec<String>,
 
-    /// The file names to automatically hide
-    /// (e.g. "file.txt" or "file")
+    /// The file names to automatically hide. Names without a dot only match files without an
+    /// extension, unless --match-stem is given. Each value can be a list separated by commas or
+    /// semicolons.
+    /// (e.g. "file.txt" or "file" or "Thumbs.db,desktop.ini")
+    /// [env: FILEHIDER_NAMES, comma separated]
     #[clap(short = 'n', long, value_parser, num_args = 1.., verbatim_doc_comment)]
     file_names: Vec<String>,
 
-    /// The file extensions to automatically hide
-    /// (e.g. "txt" or ".txt")
+    /// The file extensions to automatically hide. Each value can be a list separated by commas or
+    /// semicolons.
+    /// (e.g. "txt" or ".txt" or "tmp;log")
+    /// [env: FILEHIDER_EXTENSIONS, comma separated]
     #[clap(short = 'x', long, value_parser, num_args = 1.., verbatim_doc_comment)]
     file_extensions: Vec<String>,
 
+    /// The directory names to automatically hide, instead of --file-names. Directories are matched
+    /// by --file-names unless this is given. Each value can be a list separated by commas or
+    /// semicolons.
+    /// (e.g. "build" or "__pycache__,.cache")
+    #[clap(long, value_parser, num_args = 1.., verbatim_doc_comment)]
+    dir_names: Vec<String>,
+
+    /// The directory extensions to automatically hide (e.g. for bundles like "Photos.app").
+    /// Directories aren't matched by extension unless this is given. Each value can be a list
+    /// separated by commas or semicolons.
+    /// (e.g. "app" or ".bundle;lproj")
+    #[clap(long, value_parser, num_args = 1.., verbatim_doc_comment)]
+    dir_extensions: Vec<String>,
+
+    /// Switch to match directories by --file-extensions as well, so the same extensions hide
+    /// both (e.g. for bundles and versioned directory names like "My.Bundle" or "lib.v2")
+    /// [default: false]
+    #[clap(long, default_value = "false", verbatim_doc_comment)]
+    match_dir_extensions: bool,
+
+    /// A regular expression to match against the whole path of each file and directory. Paths
+    /// must match it as well as the file names and extensions if any are given, or it decides on
+    /// its own. Either way, only the file types given with --file-types are hidden. It's matched
+    /// anywhere in the path unless anchored with ^ or $, and case insensitively unless
+    /// --case-sensitive is given. When recursive, each path found in a subdirectory is matched on
+    /// its own, starting with the absolute path of the given directory.
+    /// Parts of paths that aren't valid UTF-8 are matched as the replacement character.
+    /// (e.g. "/cache/.*\.tmp$" hides "/home/user/cache/a/b.tmp")
+    #[clap(long, value_parser, verbatim_doc_comment)]
+    path_regex: Option<String>,
+
+    /// Directory names or glob patterns to skip entirely, without hiding anything inside them or
+    /// descending into them. Can be given more than once.
+    /// (e.g. "node_modules" or ".git" or "target*")
+    #[clap(long = "exclude-dir", value_parser, num_args = 1.., verbatim_doc_comment)]
+    exclude_dirs: Vec<String>,
+
     /// Switch to enable recursive watching
     /// (i.e. watch all subdirectories)
     /// [default: false]
     #[clap(short, long, default_value = "false", verbatim_doc_comment)]
     recursive: bool,
 
+    /// Switch to also hide the given directories themselves if they match, not just what's in them.
+    /// Each one is handled after its contents in immediate mode.
+    /// [default: false]
+    #[clap(long, default_value = "false", verbatim_doc_comment)]
+    include_root: bool,
+
+    /// Only hide paths at least this many levels below the given directories. The contents of a
+    /// directory are at depth 1. Only applies with --recursive, since otherwise only the contents
+    /// of each directory are handled.
+    /// (e.g. "2")
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..), verbatim_doc_comment)]
+    min_depth: Option<u64>,
+
+    /// Only hide paths at most this many levels below the given directories. The contents of a
+    /// directory are at depth 1. Only applies with --recursive, since otherwise only the contents
+    /// of each directory are handled.
+    /// (e.g. "2")
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..), verbatim_doc_comment)]
+    max_depth: Option<u64>,
+
+    /// Switch to follow symlinks to directories when recursive. In watch mode, symlinked
+    /// directories found at startup are watched as well.
+    /// [default: false]
+    #[clap(long, default_value = "false", verbatim_doc_comment)]
+    follow_symlinks: bool,
+
+    /// Switch to stay on the file system of each given directory when recursive, skipping any
+    /// file systems mounted below it, like "find -xdev". Bind mounts on Linux can share the device
+    /// of the file system they're mounted on, in which case they aren't skipped.
+    /// [default: false]
+    #[clap(long, visible_alias = "no-follow-mounts", default_value = "false", verbatim_doc_comment)]
+    one_file_system: bool,
+
     /// Switch to enable case sensitivity in file names and extensions
     /// (e.g. "file.txt" and "FILE.TXT" are the same)
     /// [default: false]
     #[clap(short = 'c', long, default_value = "false", verbatim_doc_comment)]
     case_sensitive: bool,
 
+    /// Switch to let file names without a dot match files with any extension, by comparing them
+    /// against the name without its extension
+    /// (e.g. "file" hides "file.txt" and "file.log")
+    /// [default: false]
+    #[clap(long, default_value = "false", verbatim_doc_comment)]
+    match_stem: bool,
+
+    /// Switch to also hide every path git would ignore, following the .gitignore files,
+    /// .git/info/exclude, and the global excludes file of the repository each path is in. Paths
+    /// outside a repository are only matched by their names and extensions. On platforms other
+    /// than Windows, git sees hidden paths under their new names.
+    /// [default: false]
+    #[clap(long, default_value = "false", verbatim_doc_comment)]
+    gitignore: bool,
+
     /// Switch to enable test mode. In test mode, the program will not actually hide files
-    /// and will instead print the paths of the files that would be hidden.
+    /// and will instead print the paths of the files that would be hidden, with the rule each one
+    /// matched.
     /// [default: false]
     #[clap(long = "test", default_value = "false", verbatim_doc_comment)]
     test_mode: bool,
 
     /// Switch to enable watch mode, which will watch for changes to the files and directories
-    /// and automatically hide them.
+    /// and automatically hide them. On Unix, SIGHUP reloads the config file and SIGUSR1 sweeps
+    /// every directory again right away. On Windows, Ctrl+Break sweeps them again.
     /// [default: false]
     #[clap(short, long, default_value = "false", verbatim_doc_comment)]
     watch: bool,
 
     /// Switch to enable immediate mode, which will immediately hide all files and directories
-    /// that match the given file names and extensions.
+    /// that match the given file names and extensions. Overrides "immediate = false" in the
+    /// config file.
     /// [default: true]
-    #[clap(short, long, default_value = "false", verbatim_doc_comment)]
+    #[clap(
+        short,
+        long,
+        default_value = "true",
+        overrides_with = "no_immediate",
+        verbatim_doc_comment
+    )]
     immediate: bool,
 
+    /// Switch to disable immediate mode, so only new files and directories are hidden in watch
+    /// mode.
+    /// [default: false]
+    #[clap(long, default_value = "false", overrides_with = "immediate", verbatim_doc_comment)]
+    no_immediate: bool,
+
+    /// Switch to hide everything that matches now, then keep watching for new files and
+    /// directories. The directories are watched before they're walked, so nothing created while
+    /// immediate mode runs is missed. Implies --watch.
+    /// [default: false]
+    #[clap(long, default_value = "false", conflicts_with = "no_immediate", verbatim_doc_comment)]
+    watch_existing: bool,
+
+    /// Switch to only hide files and directories created after watch mode started, going by their
+    /// creation time, or their modification time where it isn't available. Implies --watch and
+    /// --no-immediate.
+    /// [default: false]
+    #[clap(long, default_value = "false", conflicts_with = "watch_existing", verbatim_doc_comment)]
+    only_new: bool,
+
+    /// Switch to apply the file name and extension filters to files given directly as arguments.
+    /// By default, files given directly are always hidden.
+    /// [default: false]
+    #[clap(long, default_value = "false", verbatim_doc_comment)]
+    match_files: bool,
+
+    /// What to do with the matching paths. "unhide" shows hidden paths again, and "toggle" hides
+    /// visible paths and shows hidden ones. When unhiding or toggling on non-Windows platforms,
+    /// hidden paths are matched by their name without the dot (e.g. "file.txt" matches
+    /// ".file.txt"). "toggle" can't be combined with watch mode.
+    #[clap(long, value_enum, default_value = "hide", verbatim_doc_comment)]
+    action: Operation,
+
+    /// Switch to unhide every hidden path in the directories, whatever its name. Only the paths
+    /// the journal records hiding are unhidden, so dotfiles that were never hidden by filehider
+    /// are left alone, unless --force is given. Implies --action unhide, and can't be combined
+    /// with watch mode.
+    /// [default: false]
+    #[clap(
+        long,
+        default_value = "false",
+        conflicts_with_all = ["watch", "watch_existing", "only_new", "action"],
+        verbatim_doc_comment
+    )]
+    reveal_all: bool,
+
+    /// What to do when hiding a path would overwrite an existing hidden path on non-Windows
+    /// platforms (e.g. "file.txt" when ".file.txt" already exists). "rename" appends a number,
+    /// giving ".file (1).txt".
+    #[clap(long, value_enum, default_value = "error", verbatim_doc_comment)]
+    on_collision: OnCollision,
+
+    /// What to do when a directory is inside another directory that's handled recursively, which
+    /// would otherwise be walked and watched twice. "merge" keeps only the outer directory with a
+    /// warning. Paths that are the same after resolving symlinks are always merged.
+    #[clap(long, value_enum, default_value = "merge", verbatim_doc_comment)]
+    on_overlap: OnOverlap,
+
+    /// Switch to read newline separated paths to hide from stdin instead of walking directories.
+    /// Can't be combined with watch mode.
+    /// [default: false]
+    #[clap(long, default_value = "false", conflicts_with_all = ["watch", "directories"], verbatim_doc_comment)]
+    stdin: bool,
+
+    /// Switch to read NUL separated paths from stdin instead of newline separated ones
+    /// (e.g. the output of "find -print0")
+    /// [default: false]
+    #[clap(short = '0', long = "null", default_value = "false", verbatim_doc_comment)]
+    null_data: bool,
+
+    /// Switch to hide every path read from stdin without applying the file name and extension
+    /// filters
+    /// [default: false]
+    #[clap(long, default_value = "false", verbatim_doc_comment)]
+    no_filter: bool,
+
+    /// The order in which immediate mode walks directories. "contents-first" handles the contents
+    /// of a directory before the directory itself, so renaming a directory to hide it can't break
+    /// the paths to its contents. "auto" uses "contents-first" on non-Windows platforms, where
+    /// hiding renames, and "parents-first" on Windows.
+    #[clap(long, value_enum, default_value = "auto", verbatim_doc_comment)]
+    order: WalkOrder,
+
+    /// Sort the entries of each directory before immediate mode handles them, so the paths are
+    /// handled and printed in the same order on every platform. The given directories are sorted
+    /// too. "size" and "mtime" read the metadata of every entry, which makes the walk slower.
+    /// (e.g. "name" or "mtime")
+    /// [default: the order the file system returns them in]
+    #[clap(long, value_enum, verbatim_doc_comment)]
+    sort_by: Option<WalkSort>,
+
+    /// Switch to enable list mode. In list mode, the program will not hide files and will instead
+    /// print the bare path of every matching file to stdout, one per line. All other messages are
+    /// printed to stderr.
+    /// [default: false]
+    #[clap(short, long, default_value = "false", verbatim_doc_comment)]
+    list: bool,
+
+    /// Switch to print the paths that are hidden (or would be hidden in test mode) to stdout as
+    /// bare paths terminated by NUL bytes, for use with tools like "xargs -0". All other messages
+    /// are printed to stderr.
+    /// [default: false]
+    #[clap(long, default_value = "false", verbatim_doc_comment)]
+    print0: bool,
+
+    /// Switch to print the paths found in test mode or list mode sorted, once every path has been
+    /// examined, instead of in the order they're found. In test mode, they're grouped under the
+    /// given directory they're in, with the number of paths in each. Watch mode isn't affected.
+    /// [default: false]
+    #[clap(long, default_value = "false", verbatim_doc_comment)]
+    sort: bool,
+
+    /// Switch to only print the number of paths hidden (or that would be hidden in test mode)
+    /// once immediate mode finishes, instead of a message for each path. With --format json, a
+    /// JSON object with the number of paths each rule matched is printed instead. Can't be used in
+    /// watch mode.
+    /// (e.g. "filehider --test --count -x tmp")
+    /// [default: false]
+    #[clap(
+        long,
+        default_value = "false",
+        conflicts_with_all = ["print0", "watch"],
+        verbatim_doc_comment
+    )]
+    count: bool,
+
+    /// The format of the output. "json" prints a single JSON document to stdout once immediate
+    /// mode finishes, with what happened to each path and a summary, and can't be combined with
+    /// watch mode. "ndjson" prints a line of JSON for each path as soon as it's handled instead,
+    /// between a line with the directories and a line with the summary. All other messages are
+    /// printed to stderr. Can't be combined with --list or --print0.
+    #[clap(
+        long,
+        value_enum,
+        default_value = "human",
+        conflicts_with_all = ["list", "print0"],
+        verbatim_doc_comment
+    )]
+    format: OutputFormat,
+
+    /// How paths are printed, in every mode. "absolute" prints absolute paths, with symlinks in
+    /// their directories resolved. "relative" prints paths relative to the given directory
+    /// they're in, so the output doesn't depend on where the directories are, and other paths as
+    /// absolute paths.
+    /// (e.g. "build/file.tmp" instead of "/home/user/project/build/file.tmp")
+    #[clap(
+        long,
+        value_enum,
+        default_value = "absolute",
+        conflicts_with_all = ["absolute_paths", "relative_paths"],
+        verbatim_doc_comment
+    )]
+    path_style: PathStyle,
+
+    /// Switch to print paths as absolute paths. The same as --path-style absolute.
+    /// [default: false]
+    #[clap(long, default_value = "false", conflicts_with = "relative_paths", verbatim_doc_comment)]
+    absolute_paths: bool,
+
+    /// Switch to print paths relative to the given directory they're in. The same as
+    /// --path-style relative.
+    /// [default: false]
+    #[clap(long, default_value = "false", verbatim_doc_comment)]
+    relative_paths: bool,
+
+    /// Switch to show where symlinks point in the messages about them. Only informational, the
+    /// symlink itself is still what gets hidden.
+    /// (e.g. "Hid file: /home/user/link.tmp -> ../a.tmp")
+    /// [default: false]
+    #[clap(long, default_value = "false", verbatim_doc_comment)]
+    show_targets: bool,
+
+    /// Switch to show a spinner with the number of paths examined and hidden so far in immediate
+    /// mode, and the directory being walked. Only shown when stderr is a terminal, and not with
+    /// --quiet or --format json or ndjson.
+    /// [default: false]
+    #[clap(long, default_value = "false", verbatim_doc_comment)]
+    progress: bool,
+
+    /// Print more about what's happening. Once shows paths that are skipped or already hidden,
+    /// each directory walked, and the rule each hidden path matched, and twice also shows why each
+    /// path did or didn't match.
+    /// (e.g. "-v" or "-vv")
+    #[clap(short = 'v', long, action = clap::ArgAction::Count, verbatim_doc_comment)]
+    verbose: u8,
+
+    /// Switch to only print errors
+    /// [default: false]
+    #[clap(short = 'q', long, default_value = "false", conflicts_with = "verbose", verbatim_doc_comment)]
+    quiet: bool,
+
+    /// When to color the output. Hidden paths are green, paths that would be hidden or are
+    /// skipped are yellow, and errors are red. "auto" colors it when printing to a terminal,
+    /// unless the NO_COLOR environment variable is set.
+    #[clap(long, value_enum, default_value = "auto", verbatim_doc_comment)]
+    color: ColorChoice,
+
+    /// Switch to never color the output. The same as --color never.
+    /// [default: false]
+    #[clap(long, default_value = "false", conflicts_with = "color", verbatim_doc_comment)]
+    no_color: bool,
+
+    /// Print a heartbeat line in watch mode whenever no events arrive for the given number of
+    /// seconds, showing that the watcher is still alive
+    /// (e.g. "60")
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..), verbatim_doc_comment)]
+    heartbeat_secs: Option<u64>,
+
+    /// Stop watch mode and exit successfully once it has run for the given time, printing a
+    /// summary of what was handled
+    /// (e.g. "8h" or "1h 30m")
+    #[clap(long, value_parser = humantime::parse_duration, verbatim_doc_comment)]
+    duration: Option<Duration>,
+
+    /// Sweep every directory again at the given interval while watching, the same way immediate
+    /// mode does, to catch paths whose events were lost (e.g. on network shares). A bare number is
+    /// taken as seconds.
+    /// (e.g. "30m" or "600")
+    #[clap(
+        long,
+        visible_alias = "rescan-secs",
+        value_parser = parse_interval,
+        verbatim_doc_comment
+    )]
+    rescan: Option<Duration>,
+
+    /// Print a line with what watch mode has handled in the last interval and in total at the given
+    /// interval, even when nothing happened. With --format ndjson, a "stats" record is printed to
+    /// stdout as well.
+    /// (e.g. "10m")
+    #[clap(long, value_parser = humantime::parse_duration, verbatim_doc_comment)]
+    stats_interval: Option<Duration>,
+
+    /// Only hide paths in watch mode between the given times of day, in local time. Paths from
+    /// events outside the window are left alone, and only hidden later if --rescan sweeps find
+    /// them during the window. A window that ends before it starts crosses midnight. Immediate
+    /// mode isn't affected.
+    /// (e.g. "22:00-06:00")
+    #[clap(long, value_parser = schedule::parse_window, verbatim_doc_comment)]
+    active_between: Option<ActiveWindow>,
+
+    /// Handle watch mode events on this many worker threads, so a slow hide doesn't hold up the
+    /// events after it. Can't be combined with --confirm.
+    /// (e.g. "4")
+    /// [default: events are handled one at a time as they arrive]
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..), conflicts_with = "confirm", verbatim_doc_comment)]
+    threads_watch: Option<u64>,
+
+    /// The number of watcher events that can be waiting to be handled in watch mode. Once it's
+    /// full, the watcher waits for room, and a warning is printed when it's nearly full. If the
+    /// OS drops events in the meantime, the directories are swept for the paths that were
+    /// missed. Isn't changed when the config file is reloaded.
+    /// (e.g. "65536")
+    /// [default: 4096]
+    #[clap(
+        long,
+        value_parser = clap::value_parser!(u64).range(1..),
+        default_value = "4096",
+        verbatim_doc_comment
+    )]
+    event_buffer: u64,
+
+    /// How watch mode finds out about new paths. "native" uses the OS's notifications, "poll"
+    /// checks the directories for changes every --poll-interval, and "auto" only polls the
+    /// directories the OS can't watch. Polling works on network shares, FUSE mounts and container
+    /// volumes that don't deliver notifications, but it walks every polled directory each time,
+    /// so it costs more CPU and disk access the more paths they hold. Isn't changed when the
+    /// config file is reloaded.
+    #[clap(long, value_enum, default_value = "auto", verbatim_doc_comment)]
+    backend: Backend,
+
+    /// How often the poll backend checks the directories for changes. Shorter intervals find new
+    /// paths sooner, at the cost of more CPU.
+    /// (e.g. "10s")
+    #[clap(
+        long,
+        value_parser = humantime::parse_duration,
+        default_value = "2s",
+        verbatim_doc_comment
+    )]
+    poll_interval: Duration,
+
+    /// Switch to keep watching the directories that could be watched when others can't be (e.g.
+    /// because the inotify watch limit was reached), instead of stopping watch mode
+    /// [default: false]
+    #[clap(long, default_value = "false", verbatim_doc_comment)]
+    best_effort: bool,
+
+    /// How long to collect watch mode events before handling them. Repeated events for the same
+    /// path within the window are handled once, so a burst of events for one file only hides it
+    /// once and counts at most one error. "0s" handles each event as it arrives. Isn't changed
+    /// when the config file is reloaded.
+    /// (e.g. "1s")
+    #[clap(
+        long,
+        value_parser = humantime::parse_duration,
+        default_value = "250ms",
+        verbatim_doc_comment
+    )]
+    debounce: Duration,
+
+    /// Wait until a newly created file's size and modification time haven't changed for the given
+    /// duration before hiding it in watch mode, so files that are still being written (e.g.
+    /// downloads) aren't renamed under the program writing them. Files removed in the meantime
+    /// are dropped.
+    /// (e.g. "5s")
+    /// [default: files are hidden as soon as they're created]
+    #[clap(long, value_parser = humantime::parse_duration, verbatim_doc_comment)]
+    settle: Option<Duration>,
+
+    /// How long to keep waiting with --settle for a file that keeps changing. After that, it's
+    /// left visible for a later rescan.
+    /// (e.g. "1h")
+    #[clap(
+        long,
+        value_parser = humantime::parse_duration,
+        default_value = "10m",
+        verbatim_doc_comment
+    )]
+    settle_max: Duration,
+
+    /// Stream each action taken in watch mode as a line of JSON to clients of a Unix domain socket
+    /// or a Windows named pipe at the given path, so external tools can follow along
+    /// (e.g. "/tmp/filehider.sock" or "\\.\pipe\filehider")
+    #[clap(long, value_parser, verbatim_doc_comment)]
+    ipc: Option<PathBuf>,
+
+    /// Run the given command through the shell for each path hidden (or unhidden), with "{}"
+    /// replaced by the path, or the path added as the last argument if there's no "{}". The path
+    /// and the action ("hide" or "unhide") are also in the FILEHIDER_PATH and FILEHIDER_ACTION
+    /// environment variables. Failures are reported, but don't stop the run. In watch mode, the
+    /// commands run one at a time on their own thread, so a slow one doesn't hold up events.
+    /// (e.g. "rsync -a {} backup:")
+    #[clap(long, value_parser, verbatim_doc_comment)]
+    on_hide: Option<String>,
+
+    /// Kill an --on-hide command once it runs for longer than the given time
+    /// (e.g. "1m")
+    #[clap(
+        long,
+        value_parser = humantime::parse_duration,
+        default_value = "30s",
+        verbatim_doc_comment
+    )]
+    on_hide_timeout: Duration,
+
+    /// Only hide the paths that match the other filters if the given command accepts them. It's
+    /// run through the shell for each of those paths like --on-hide, with the path also in the
+    /// FILEHIDER_PATH environment variable, and a path is only hidden if it exits with 0.
+    /// Starting a process for every path is slow, so keep the other filters narrow, and consider
+    /// --threads-watch in watch mode.
+    /// (e.g. "git check-ignore -q {}")
+    #[clap(long, value_parser, verbatim_doc_comment)]
+    match_command: Option<String>,
+
+    /// Kill a --match-command once it runs for longer than the given time, which is an error for
+    /// the path
+    /// (e.g. "1m")
+    #[clap(
+        long,
+        value_parser = humantime::parse_duration,
+        default_value = "10s",
+        verbatim_doc_comment
+    )]
+    match_command_timeout: Duration,
+
+    /// Write a report of immediate mode to the given file as JSON once it finishes, with the
+    /// totals, the counts for each directory, and every path hidden (or that would be hidden in
+    /// test mode) with the time it was hidden
+    /// (e.g. "report.json")
+    #[clap(long, value_parser, value_hint = ValueHint::FilePath, verbatim_doc_comment)]
+    summary_json: Option<PathBuf>,
+
+    /// Write the commands that would hide each matching path to a script at the given path,
+    /// instead of hiding anything, so they can be reviewed and run later. It's a shell script, or
+    /// a PowerShell script on Windows, with the command that undoes each one in a comment after
+    /// it. Implies --test.
+    /// (e.g. "hide.sh")
+    #[clap(
+        long,
+        value_parser,
+        value_hint = ValueHint::FilePath,
+        conflicts_with = "list",
+        verbatim_doc_comment
+    )]
+    emit_script: Option<PathBuf>,
+
+    /// Record every path hidden in the given journal file, so it can be undone with
+    /// "filehider undo". Entries are appended as JSON lines.
+    /// (e.g. "hidden.jsonl")
+    /// [default: journal.jsonl in the platform data directory]
+    #[clap(long, value_parser, value_hint = ValueHint::FilePath, verbatim_doc_comment)]
+    journal: Option<PathBuf>,
+
+    /// Also write every message to the given log file, at the same verbosity, for when nobody
+    /// sees the terminal (e.g. running as a service). Each new file starts with the PID and the
+    /// effective configuration. Isn't changed when the config file is reloaded.
+    /// (e.g. "filehider.log")
+    #[clap(long, value_parser, value_hint = ValueHint::FilePath, verbatim_doc_comment)]
+    log_file: Option<PathBuf>,
+
+    /// Rotate the log file once it grows past the given size, moving it to "<file>.1" and
+    /// starting a new one
+    /// (e.g. "500K" or "10MB")
+    /// [default: 10MB]
+    #[clap(long, value_parser = logger::parse_size, verbatim_doc_comment)]
+    log_max_size: Option<u64>,
+
+    /// The number of rotated log files to keep, as "<file>.1" to "<file>.N", deleting older ones
+    /// (e.g. "3")
+    #[clap(long, value_parser, default_value = "5", verbatim_doc_comment)]
+    log_keep: u32,
+
+    /// When to start each message with an RFC 3339 timestamp. "auto" adds them when --log-file is
+    /// given, since the output is then usually kept as well. The log file and --format ndjson
+    /// always have them.
+    /// (e.g. "2023-03-01T08:00:00+01:00 Hid file: /home/user/a.tmp")
+    #[clap(long, value_enum, default_value = "auto", verbatim_doc_comment)]
+    timestamps: Timestamps,
+
+    /// Switch to give timestamps in UTC instead of local time, in messages, the log file, and
+    /// --format ndjson
+    /// [default: false]
+    #[clap(long, default_value = "false", verbatim_doc_comment)]
+    utc: bool,
+
+    /// Limit the number of files hidden per second in immediate mode, to avoid hammering slow
+    /// disks. Doesn't apply in test mode, list mode, or watch mode.
+    /// (e.g. "100")
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..), verbatim_doc_comment)]
+    max_ops_per_sec: Option<u32>,
+
+    /// Stop immediate mode with an error after hiding this many paths, to catch file names and
+    /// extensions that match far more than intended. Doesn't apply in test mode or list mode.
+    /// (e.g. "1000")
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..), verbatim_doc_comment)]
+    max_hides: Option<u64>,
+
+    /// Switch to stop immediate mode at the first error walking a directory or hiding a path,
+    /// instead of moving on and printing every error together once it finishes
+    /// [default: false]
+    #[clap(long, default_value = "false", verbatim_doc_comment)]
+    fail_fast: bool,
+
+    /// Stop immediate mode once this many errors have happened in total, however long they took,
+    /// printing the summary and exiting with code 2 without going on to watch mode. Watch mode
+    /// keeps its own limit on errors in a short period instead.
+    /// (e.g. "50")
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..), verbatim_doc_comment)]
+    max_errors_total: Option<u64>,
+
+    /// Switch to exit with code 3 if test mode or list mode finds any paths to hide, for use as a
+    /// check in scripts and CI. The paths found are printed to stderr, so stdout is left for
+    /// --count and --format json. Can't be used in watch mode.
+    /// [default: false]
+    #[clap(
+        long,
+        visible_alias = "fail-if-found",
+        default_value = "false",
+        conflicts_with = "watch",
+        verbatim_doc_comment
+    )]
+    fail_on_match: bool,
+
+    /// Switch to run even if another instance is already hiding in the same directories. By
+    /// default, that's refused with exit code 4, since both would try to rename the same paths.
+    /// [default: false]
+    #[clap(long, default_value = "false", verbatim_doc_comment)]
+    no_lock: bool,
+
+    /// Switch to make every path hidden in this run visible again when watch mode stops, on
+    /// Ctrl+C or once --duration has passed, for trying out file names and extensions. Press
+    /// Ctrl+C twice to exit without restoring. Isn't changed when the config file is reloaded.
+    /// [default: false]
+    #[clap(long, default_value = "false", verbatim_doc_comment)]
+    restore_on_exit: bool,
+
+    /// Switch to skip the safety checks, ignoring --max-hides and hiding everything when no file
+    /// names or extensions are given. With --reveal-all, every hidden path is unhidden rather than
+    /// only the ones the journal records hiding, including dotfiles filehider never hid.
+    /// [default: false]
+    #[clap(long, default_value = "false", verbatim_doc_comment)]
+    force: bool,
+
+    /// Switch to hide every path when no file names or extensions are given. Without it, that's
+    /// refused outside of test mode and list mode, since it's usually a mistake.
+    /// [default: false]
+    #[clap(long, default_value = "false", verbatim_doc_comment)]
+    all: bool,
+
     /// Types of files to hide
-    #[clap(short = 't', long, value_parser, num_args = 1.., value_delimiter = ' ', default_value = "file directory", verbatim_doc_comment)]
+    #[clap(short = 't', long, value_enum, num_args = 1.., value_delimiter = ' ', default_value = "file directory", verbatim_doc_comment)]
     file_types: Vec<FileType>,
+
+    /// Switch to ask for confirmation before hiding each path. Answer "y" to hide it, "n" to skip
+    /// it, "a" to hide it and everything after it without asking, or "q" to stop and print the
+    /// summary. Ignored with a warning when stdin isn't a terminal. Can't be used in watch mode.
+    /// [default: false]
+    #[clap(
+        long,
+        default_value = "false",
+        conflicts_with_all = ["stdin", "watch", "watch_existing", "only_new"],
+        verbatim_doc_comment
+    )]
+    confirm: bool,
+
+    /// The attributes to set when hiding a file on Windows. "system" keeps files hidden even when
+    /// Explorer is set to show hidden files, and "readonly" marks them as read only.
+    /// (e.g. "hidden,system")
+    #[cfg(windows)]
+    #[clap(long, value_enum, num_args = 1.., value_delimiter = ',', default_value = "hidden", verbatim_doc_comment)]
+    windows_attrs: Vec<WindowsAttribute>,
+
+    /// Switch to also write the paths hidden, warnings, and errors to the Windows Event Log, under
+    /// the "filehider" source in the Application log, along with when filehider starts and stops.
+    /// Messages are only printed if the source can't be registered. (Windows only)
+    /// [default: false]
+    #[cfg(windows)]
+    #[clap(long, default_value = "false", verbatim_doc_comment)]
+    eventlog: bool,
+
+    /// Switch to also send messages to the systemd journal, or to syslog where there's no journal,
+    /// tagged "filehider". In the journal, the path of each hide is in the FILEHIDER_PATH field.
+    /// (Unix only, needs the "syslog" feature)
+    /// [default: false]
+    #[cfg(all(unix, feature = "syslog"))]
+    #[clap(long, default_value = "false", verbatim_doc_comment)]
+    syslog: bool,
+
+    /// Switch to show a desktop notification for each path watch mode hides. When more than 3 are
+    /// hidden within --notify-window, a single notification with the count is shown instead.
+    /// (needs the "notifications" feature)
+    /// [default: false]
+    #[cfg(feature = "notifications")]
+    #[clap(long, default_value = "false", verbatim_doc_comment)]
+    notify: bool,
+
+    /// How long to wait for more paths to be hidden before showing notifications with --notify, so
+    /// bursts are shown as one notification
+    /// (e.g. "10s")
+    #[cfg(feature = "notifications")]
+    #[clap(
+        long,
+        value_parser = humantime::parse_duration,
+        default_value = "3s",
+        verbatim_doc_comment
+    )]
+    notify_window: Duration,
+
+    /// Serve Prometheus metrics for watch mode at "/metrics" on the given address: the events
+    /// received by kind, the files and directories hidden, errors, directories watched again after
+    /// being removed, and the number of directories watched. Failing to bind the address is fatal.
+    /// (needs the "metrics" feature)
+    /// (e.g. "127.0.0.1:9188")
+    #[cfg(feature = "metrics")]
+    #[clap(long, value_parser, verbatim_doc_comment)]
+    metrics_addr: Option<std::net::SocketAddr>,
+
+    /// Switch to hide directories from Finder by setting their hidden flag (like "chflags hidden")
+    /// instead of adding a dot to their names, so their paths don't change. Files are still
+    /// renamed. (macOS only)
+    /// [default: false]
+    #[cfg(target_os = "macos")]
+    #[clap(long, default_value = "false", verbatim_doc_comment)]
+    macos_finder_hidden: bool,
+
+    /// The prefix added to the names of paths to hide them, instead of a dot. Names that start
+    /// with it count as hidden, and unhiding removes it. (not on Windows)
+    /// (e.g. "_hidden_")
+    #[cfg(not(windows))]
+    #[clap(long, value_parser, default_value = ".", verbatim_doc_comment)]
+    hidden_prefix: String,
+
+    /// Only hide paths owned by the given user, as a user name or uid (Unix only)
+    /// (e.g. "alice" or "1000")
+    #[cfg(unix)]
+    #[clap(long, value_parser, value_hint = ValueHint::Username, verbatim_doc_comment)]
+    owner: Option<String>,
+
+    /// Only hide paths with all of the given permission bits set, as an octal mask (Unix only)
+    /// (e.g. "002" for world writable paths, or "4000" for setuid files)
+    #[cfg(unix)]
+    #[clap(long, value_parser, verbatim_doc_comment)]
+    mode: Option<String>,
+
+    /// A TOML config file to read options from. Options given on the command line take
+    /// precedence over the config file. If not given, "config.toml" in the platform config
+    /// directory is used if it exists.
+    /// (e.g. "~/.config/filehider/config.toml" on Linux or
+    /// "C:\Users\user\AppData\Roaming\filehider\config\config.toml" on Windows)
+    #[clap(long, global = true, value_parser, value_hint = ValueHint::FilePath, verbatim_doc_comment)]
+    config: Option<PathBuf>,
+
+    /// Feed the events in the given file through watch mode instead of watching for real, one
+    /// JSON object per line, to test watch mode deterministically. Only used for testing.
+    /// (e.g. {"kind": "create", "paths": ["/tmp/dir/file.txt"]})
+    #[clap(long, hide = true, value_parser, value_hint = ValueHint::FilePath, verbatim_doc_comment)]
+    replay_events: Option<PathBuf>,
+
+    /// Switch to time immediate mode without hiding anything, and print how many paths per second
+    /// were walked and matched, to compare settings on real data. Only used for tuning.
+    /// [default: false]
+    #[clap(long, hide = true, default_value = "false", verbatim_doc_comment)]
+    benchmark: bool,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+// Enum for the subcommands
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Check the config file and command line options for errors without hiding anything, and
+    /// print a summary of the effective rules
+    CheckConfig,
+
+    /// Print a shell completion script to stdout
+    Completions {
+        /// The shell to generate the completion script for
+        #[clap(value_enum)]
+        shell: clap_complete::Shell,
+    },
+
+    /// Print a man page to stdout
+    Manpage,
+
+    /// Undo hides recorded in the journal, newest first, restoring the original names or
+    /// attributes. Paths that no longer exist are skipped.
+    Undo {
+        /// Only undo the last run. This is the default unless --journal is given, in which case
+        /// every run in it is undone.
+        #[clap(long)]
+        last: bool,
+
+        /// The journal to undo, instead of the one from the config file or the default one
+        #[clap(long, value_hint = ValueHint::FilePath)]
+        journal: Option<PathBuf>,
+    },
 }
 
 // Enum for the file types to hide
-#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 enum FileType {
     File,
     Directory,
 }
 
-fn main() -> Result<()> {
-    // Parse the command line arguments
-    let args: Args = Args::parse();
+// Enum for what to do when the hidden name of a path already exists
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum OnCollision {
+    Error,
+    Skip,
+    Rename,
+}
+
+// Enum for what to do with the matching paths
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Operation {
+    Hide,
+    Unhide,
+    Toggle,
+}
+
+// The hidden paths --reveal-all unhides: all of them with --force, and otherwise only the ones the
+// journal records hiding
+enum Reveal {
+    Everything,
+    Journaled(HashSet<PathBuf>),
+}
+
+// What handling a path will do, planned from its current state before anything is changed. Test
+// mode prints the plan and a real run carries it out, so the preview always matches.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PlannedAction {
+    Hide,
+    Unhide,
+    // Nothing to do, since the path is already hidden (or already visible when unhiding)
+    Skip { hidden: bool },
+}
+
+// Enum for what to do when a directory is inside another recursive directory
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum OnOverlap {
+    Merge,
+    Error,
+}
+
+// Enum for the attributes to set when hiding a file on Windows
+#[cfg(windows)]
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum WindowsAttribute {
+    Hidden,
+    System,
+    Readonly,
+}
+
+// Enum for the order in which immediate mode walks directories
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum WalkOrder {
+    Auto,
+    ParentsFirst,
+    ContentsFirst,
+}
+
+// Enum for how the entries of each directory are sorted in immediate mode, from --sort-by
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum WalkSort {
+    // Sorting siblings by name sorts the whole walk by path
+    #[value(alias = "path")]
+    #[serde(alias = "path")]
+    Name,
+    Size,
+    Mtime,
+}
+
+impl WalkSort {
+    // Compare two entries of the same directory, by name when they're otherwise equal or their
+    // metadata can't be read
+    fn compare(self, a: &walkdir::DirEntry, b: &walkdir::DirEntry) -> std::cmp::Ordering {
+        let by_metadata = match self {
+            WalkSort::Name => std::cmp::Ordering::Equal,
+            WalkSort::Size => {
+                let size = |entry: &walkdir::DirEntry| entry.metadata().ok().map(|m| m.len());
+                size(a).cmp(&size(b))
+            }
+            WalkSort::Mtime => {
+                let mtime = |entry: &walkdir::DirEntry| {
+                    entry.metadata().ok().and_then(|metadata| metadata.modified().ok())
+                };
+                mtime(a).cmp(&mtime(b))
+            }
+        };
+        by_metadata.then_with(|| a.file_name().cmp(b.file_name()))
+    }
+}
+
+// Enum for how paths are printed, from --path-style
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+enum PathStyle {
+    Absolute,
+    Relative,
+}
+
+// Enum for how watch mode is told about changes, from --backend
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+enum Backend {
+    Auto,
+    Native,
+    Poll,
+}
+
+// Enum for the format of the output
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum OutputFormat {
+    Human,
+    Json,
+    Ndjson,
+}
+
+// Enum for when to color the output
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+// Enum for when to start messages with a timestamp
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Timestamps {
+    Auto,
+    Always,
+    Never,
+}
+
+// The rules deciding which paths to hide
+struct Matcher {
+    file_names: HashSet<String>,
+    file_extensions: HashSet<String>,
+    // Names and extensions matched against directories, from --dir-names and --dir-extensions.
+    // Directories are matched by the file names instead when no directory names are given.
+    dir_names: Option<HashSet<String>>,
+    dir_extensions: HashSet<String>,
+    // Whether directories are matched by the file extensions too, from --match-dir-extensions
+    match_dir_extensions: bool,
+    // Matched against the whole path, from --path-regex
+    path_regex: Option<Regex>,
+    case_sensitive: bool,
+    // Whether file names without a dot also match the stems of files, from --match-stem
+    match_stem: bool,
+    // The prefix hidden names are matched without, when unhiding or toggling on non-Windows
+    // platforms
+    visible_prefix: Option<String>,
+    // Matches the paths git would ignore as well, from --gitignore
+    gitignore: Option<Arc<GitIgnored>>,
+    hide_files: bool,
+    hide_directories: bool,
+    #[cfg(unix)]
+    owner: Option<u32>,
+    #[cfg(unix)]
+    mode: Option<u32>,
+}
+
+impl Matcher {
+    // Check if the matcher has no rules restricting it, so it matches every path of the hidden
+    // file types
+    fn matches_everything(&self) -> bool {
+        let restricted =
+            self.has_patterns() || self.gitignore.is_some() || self.path_regex.is_some();
+        #[cfg(unix)]
+        let restricted = restricted || self.owner.is_some() || self.mode.is_some();
+        !restricted
+    }
+
+    // Check if any names or extensions were given, for files or directories
+    fn has_patterns(&self) -> bool {
+        !self.file_names.is_empty()
+            || !self.file_extensions.is_empty()
+            || self.dir_names.is_some()
+            || !self.dir_extensions.is_empty()
+    }
+
+    // Get the names directories are matched by
+    fn directory_names(&self) -> &HashSet<String> {
+        self.dir_names.as_ref().unwrap_or(&self.file_names)
+    }
+
+    // Check if directories with the given (normalized) extension are hidden
+    fn matches_dir_extension(&self, extension: &str) -> bool {
+        self.dir_extensions.contains(extension)
+            || (self.match_dir_extensions && self.file_extensions.contains(extension))
+    }
+}
+
+// A directory with its own rules, from a [[watch]] section of the config file
+struct Section {
+    path: PathBuf,
+    recursive: bool,
+    matcher: Matcher,
+}
+
+// Settings shared by immediate mode, watch mode, and the functions that handle each path
+struct Settings {
+    matcher: Matcher,
+    sections: Vec<Section>,
+    recursive: bool,
+    // Whether the given directories themselves can be hidden in immediate mode
+    include_root: bool,
+    // Depth limits for recursive walks and watches, relative to the given directories
+    min_depth: Option<usize>,
+    max_depth: Option<usize>,
+    follow_symlinks: bool,
+    one_file_system: bool,
+    // Device ids of the given directories, recorded for --one-file-system
+    root_devices: HashMap<PathBuf, u64>,
+    // Patterns for directories to skip, from --exclude-dir
+    exclude_dirs: Vec<glob::Pattern>,
+    test_mode: bool,
+    list: bool,
+    match_files: bool,
+    operation: Operation,
+    on_collision: OnCollision,
+    #[cfg(windows)]
+    windows_attributes: u32,
+    #[cfg(target_os = "macos")]
+    macos_finder_hidden: bool,
+    // The prefix hide_file adds to names, from --hidden-prefix
+    #[cfg(not(windows))]
+    hidden_prefix: String,
+    contents_first: bool,
+    // How the entries of each directory are sorted in immediate mode, from --sort-by
+    walk_sort: Option<WalkSort>,
+    print0: bool,
+    // The paths found so far, when they're printed sorted with --sort
+    sorted: Option<SortedPaths>,
+    // The number of paths found for each rule, when only the number is printed with --count
+    counts: Option<RuleCounts>,
+    path_style: PathStyle,
+    // Whether messages show where symlinks point, from --show-targets
+    show_targets: bool,
+    // The given directories, which paths are printed relative to with --path-style relative
+    roots: Vec<PathBuf>,
+    // When watch mode started, if only paths created since then are hidden with --only-new
+    only_new_since: Option<SystemTime>,
+    heartbeat: Option<Duration>,
+    // How long watch mode runs for, from --duration
+    duration: Option<Duration>,
+    // How often watch mode sweeps every directory again, from --rescan
+    rescan: Option<Duration>,
+    // How often watch mode prints a summary, from --stats-interval
+    stats_interval: Option<Duration>,
+    // The hours watch mode hides paths in, from --active-between
+    active_between: Option<ActiveWindow>,
+    // How long bursts of hides are coalesced for, if watch mode shows notifications with --notify
+    #[cfg(feature = "notifications")]
+    notify: Option<Duration>,
+    watch_threads: Option<usize>,
+    // The number of watcher events that can be waiting, from --event-buffer
+    event_buffer: usize,
+    // How watch mode is told about changes, from --backend and --poll-interval
+    backend: Backend,
+    poll_interval: Duration,
+    // Whether watch mode carries on without the directories it can't watch, from --best-effort
+    best_effort: bool,
+    // How long watch mode collects events before handling each path once, from --debounce
+    debounce: Option<Duration>,
+    // How long new files have to stay unchanged before they're hidden, and how long to wait for
+    // that at most, from --settle and --settle-max
+    settle: Option<Duration>,
+    settle_max: Duration,
+    ipc_path: Option<PathBuf>,
+    // The command run for each path hidden, from --on-hide
+    on_hide: Option<Arc<Hook>>,
+    // The command that decides whether matching paths are hidden, from --match-command
+    match_command: Option<MatchCommand>,
+    // Which hidden paths are unhidden with --reveal-all, which replaces the filters
+    reveal: Option<Reveal>,
+    // The minimum time between hides in immediate mode, if throttled
+    hide_interval: Option<Duration>,
+    // The maximum number of paths to hide in immediate mode, unless forced
+    max_hides: Option<usize>,
+    // Whether immediate mode stops at the first error, or after how many errors in total
+    fail_fast: bool,
+    max_errors_total: Option<usize>,
+    config_path: Option<PathBuf>,
+    // The canonicalized paths of filehider's own files (the config file and the IPC socket), which
+    // are never hidden
+    own_files: Vec<PathBuf>,
+    // Where every path hidden is recorded for "filehider undo", unless nothing is hidden. Kept
+    // when the config file is reloaded.
+    journal: Option<Arc<Journal>>,
+    // Where the commands that would hide each path are written instead, with --emit-script. Kept
+    // when the config file is reloaded.
+    script: Option<Arc<Script>>,
+    // The paths hidden in this run, to restore when watch mode stops with --restore-on-exit. Kept
+    // when the config file is reloaded.
+    session: Option<Arc<Session>>,
+    // What happened to each path, for --format json and --format ndjson
+    output: Option<Arc<JsonOutput>>,
+    // Cleared once the user answers "a" to the confirmation prompt
+    confirm: AtomicBool,
+    // Set once the user answers "q" to the confirmation prompt, so immediate mode stops
+    quit: AtomicBool,
+}
+
+impl Settings {
+    // Record a change made to a path in the journal, and in the session for --restore-on-exit
+    fn record_change(&self, path: &Path, change: Change) {
+        if let Some(session) = &self.session {
+            session.record(path, change.clone());
+        }
+        if let Some(journal) = &self.journal {
+            journal.record(path, change);
+        }
+    }
+
+    // Get the matcher for a path. This is the matcher of the section with the longest path
+    // containing it (so nested sections win), or the global matcher if no section contains it.
+    fn matcher_for(&self, path: &Path) -> &Matcher {
+        self.sections
+            .iter()
+            .filter(|section| path.starts_with(&section.path))
+            .max_by_key(|section| section.path.components().count())
+            .map_or(&self.matcher, |section| &section.matcher)
+    }
+
+    // Check if a directory being watched or walked should be recursed into
+    fn recursive_for(&self, directory: &Path) -> bool {
+        self.sections
+            .iter()
+            .find(|section| section.path == directory)
+            .map_or(self.recursive, |section| section.recursive)
+    }
+
+    // Check if a path at the given depth below a recursive directory is within the depth limits
+    fn within_depth(&self, depth: usize) -> bool {
+        self.min_depth.is_none_or(|min| depth >= min)
+            && self.max_depth.is_none_or(|max| depth <= max)
+    }
+
+    // Check if a path is one of filehider's own files. Only paths with the same file name are
+    // canonicalized, since paths from the walk and from events are almost never one of them.
+    fn is_own_file(&self, path: &Path) -> bool {
+        self.own_files.iter().any(|own_file| {
+            own_file.file_name() == path.file_name()
+                && canonicalize_file(path).is_ok_and(|path| path == *own_file)
+        })
+    }
+
+    // Get a path the way it should be printed, whichever mode found it. Paths in the given
+    // directories are already absolute, so only other paths (e.g. from stdin) are resolved, and
+    // are printed as given if they can't be.
+    fn display_path<'a>(&self, path: &'a Path) -> Cow<'a, Path> {
+        let path = if self.root_of(path).is_some() {
+            Cow::Borrowed(path)
+        } else {
+            canonicalize_file(path).map_or(Cow::Borrowed(path), Cow::Owned)
+        };
+
+        match (self.path_style, path) {
+            (PathStyle::Absolute, path) => path,
+            (PathStyle::Relative, Cow::Borrowed(path)) => Cow::Borrowed(self.relative_path(path)),
+            (PathStyle::Relative, Cow::Owned(path)) => {
+                Cow::Owned(self.relative_path(&path).to_path_buf())
+            }
+        }
+    }
+
+    // Get the given directory a path is in. With nested directories, it's the deepest one.
+    fn root_of(&self, path: &Path) -> Option<&PathBuf> {
+        self.roots
+            .iter()
+            .filter(|root| path.starts_with(root))
+            .max_by_key(|root| root.components().count())
+    }
+
+    // Helper function to get a path relative to the given directory it's in, or the path itself
+    // if it isn't in one
+    fn relative_path<'a>(&self, path: &'a Path) -> &'a Path {
+        match self.root_of(path).and_then(|root| path.strip_prefix(root).ok()) {
+            // The given directory itself, with --include-root
+            Some(relative) if relative.as_os_str().is_empty() => Path::new("."),
+            Some(relative) => relative,
+            None => path,
+        }
+    }
+
+    // Record what happened to a path for --format json and --format ndjson, with the rule it
+    // matched if it was matched
+    fn record_action(&self, path: &Path, action: PathAction, reason: Option<&MatchReason>) {
+        if let Some(output) = &self.output {
+            output.record(path, action, reason, EVENT.get());
+        }
+    }
+
+    // Record a path that couldn't be handled for --format json and --format ndjson
+    fn record_error(&self, path: &Path, e: &anyhow::Error) {
+        if let Some(output) = &self.output {
+            output.record_error(path, e, EVENT.get());
+        }
+    }
+
+    // Check if a directory name matches one of the excluded patterns
+    fn is_excluded(&self, name: &OsStr) -> bool {
+        let options = glob::MatchOptions {
+            case_sensitive: self.matcher.case_sensitive,
+            ..glob::MatchOptions::new()
+        };
+        let name = name.to_string_lossy();
+
+        self.exclude_dirs
+            .iter()
+            .any(|pattern| pattern.matches_with(&name, options))
+    }
+}
+
+fn main() -> Result<ExitCode> {
+    // Parse the command line arguments, keeping the matches around so the config file can tell
+    // which arguments were actually given, and so the config file can be reloaded later. Invalid
+    // arguments are a fatal error, so they exit with 1 rather than clap's usual 2.
+    let matches = Args::command().try_get_matches().unwrap_or_else(|e| {
+        let _ = e.print();
+        std::process::exit(if e.use_stderr() { 1 } else { 0 });
+    });
+
+    let args = Args::from_arg_matches(&matches)?;
+
+    // Set up logging before anything else prints. Bare paths from --list and --print0 and the JSON
+    // document are the only output on stdout, so messages go to stderr in those modes. With
+    // --fail-on-match, the paths found go to stderr like other failures.
+    let level = if args.quiet {
+        LevelFilter::Error
+    } else if args.count {
+        LevelFilter::Warn
+    } else {
+        match args.verbose {
+            0 => LevelFilter::Info,
+            1 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        }
+    };
+    let stdout = !args.list
+        && !args.print0
+        && !args.count
+        && !args.fail_on_match
+        && args.format == OutputFormat::Human;
+    let color = if args.no_color {
+        ColorChoice::Never
+    } else {
+        args.color
+    };
+    logger::init(level, stdout, color);
+
+    // Handle the subcommands, which don't hide anything
+    match args.command {
+        // Only validate the configuration
+        Some(Command::CheckConfig) => return check_config(&matches),
+        Some(Command::Completions { shell }) => {
+            clap_complete::generate(
+                shell,
+                &mut Args::command(),
+                "filehider",
+                &mut std::io::stdout(),
+            );
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Manpage) => {
+            clap_mangen::Man::new(Args::command())
+                .render(&mut std::io::stdout())
+                .with_context(|| "Failed to write man page")?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Undo { last, journal }) => {
+            let last = last || journal.is_none();
+            // The config file is only read when no journal is given, so a broken one doesn't
+            // get in the way
+            let path = match journal {
+                Some(journal) => Some(journal),
+                None => Config::load(&matches)?.journal,
+            }
+            .or_else(journal::default_path)
+            .with_context(|| "Failed to find the journal, pass it with --journal!")?;
+            return journal::undo(&path, last);
+        }
+        None => {}
+    }
+
+    // Merge the command line arguments with the config file
+    let config = Config::load(&matches)?;
+
+    // Timestamps are on by default when there's a log file, since the output is likely kept too
+    logger::set_timestamps(
+        match config.timestamps {
+            Timestamps::Auto => config.log_file.is_some(),
+            Timestamps::Always => true,
+            Timestamps::Never => false,
+        },
+        config.utc,
+    );
+
+    // Start writing to the log file before anything else is printed, so it has the whole run
+    if let Some(log_file) = &config.log_file {
+        let header = format!(
+            "filehider {} started with PID {}\nEffective configuration: {:#?}\n",
+            env!("CARGO_PKG_VERSION"),
+            std::process::id(),
+            config
+        );
+        logger::log_to_file(
+            log_file,
+            config.log_max_size.unwrap_or(logger::DEFAULT_LOG_MAX_SIZE),
+            config.log_keep,
+            header,
+        )?;
+    }
+
+    // Write to the event log until main returns
+    #[cfg(windows)]
+    let _event_log = if config.eventlog {
+        eventlog::start()
+    } else {
+        None
+    };
+
+    #[cfg(all(unix, feature = "syslog"))]
+    if config.syslog {
+        system_log::start()?;
+    }
+
+    let use_current_directory = config.use_current_directory;
+
+    let watch = config.watch;
+    let (run_immediate, watch_existing) = (config.immediate, config.watch_existing);
+    #[cfg(feature = "metrics")]
+    let metrics_addr = config.metrics_addr;
+    let (stdin, null_data, no_filter) = (config.stdin, config.null_data, config.no_filter);
+    let progress = config.progress;
+    // Matches only mean something when nothing is actually hidden
+    let fail_on_match = config.fail_on_match && (config.test_mode || config.list);
+    let summary_json = config.summary_json.clone();
+    let emit_script = config.emit_script.clone();
+    let journal_path = config.journal.clone();
+    let no_lock = config.no_lock;
+
+    // Create the set of directories to watch and files to hide, validating that they exist, and set
+    // up the rest of the configuration. Return an error if they don't exist or if files are given
+    // in watch mode.
+    let (directories, files, mut settings) = setup(config)?;
+
+    // Kept to print the end of the JSON output, since watch mode takes the settings
+    let output = settings.output.clone();
+
+    if use_current_directory {
+        for directory in &directories {
+            info!(
+                "No directories given, using current directory: {}",
+                directory.display()
+            );
+        }
+    }
+
+    // Time the walk and the matching instead of hiding anything, with --benchmark
+    if args.benchmark {
+        if stdin {
+            return Err(anyhow!("--benchmark can't be used with --stdin!"));
+        }
+        benchmark::run(&directories, &files, &settings)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    // Created here rather than in setup, so checking or reloading the config file doesn't
+    // replace it
+    if let Some(path) = &emit_script {
+        settings.script = Some(Arc::new(Script::create(path)?));
+    }
+
+    // Confirmation prompts need a terminal to answer them. Only checked here, where they would be
+    // shown, rather than in setup.
+    if settings.confirm.load(Ordering::Relaxed) && !std::io::stdin().is_terminal() {
+        warn!(
+            "Stdin is not a terminal, so --confirm is ignored and paths will be hidden without \
+            asking."
+        );
+        settings.confirm.store(false, Ordering::Relaxed);
+    }
+
+    // Open the journal here as well, and only when paths will actually be hidden. Failing to open
+    // the default one is only a warning, since it wasn't asked for.
+    if !settings.test_mode && !settings.list && settings.script.is_none() {
+        settings.journal = match &journal_path {
+            Some(path) => Some(Journal::open(path)?),
+            None => journal::default_path().and_then(|path| {
+                Journal::open(&path)
+                    .map_err(|e| warn!("{:#}, so hides can't be undone", e))
+                    .ok()
+            }),
+        }
+        .map(Arc::new);
+    }
+
+    // If test mode is enabled, then print a message saying that test mode is enabled and no files
+    // will be hidden.
+    if settings.test_mode {
+        info!("Test mode enabled. No files will be hidden.");
+    }
+
+    // If reading from stdin, then handle each path read instead of walking the directories
+    if stdin {
+        let outcome = stdin_mode(&settings, null_data, no_filter);
+        if let Some(sorted) = &settings.sorted {
+            sorted.print(&settings)?;
+        }
+        if let Some(counts) = &settings.counts {
+            counts.print(outcome.hidden)?;
+        }
+        if let Some(output) = &output {
+            output.finish(outcome)?;
+        }
+        return Ok(exit_code(outcome, fail_on_match));
+    }
+
+    // Print an error message if both watch mode and immediate mode are disabled.
+    check_modes(watch, run_immediate)?;
+    if args.replay_events.is_some() && !watch {
+        return Err(anyhow!("--replay-events can only be used in watch mode!"));
+    }
+
+    // Refuse to run alongside another instance hiding in the same directories, since both would
+    // try to rename the same paths. Nothing is hidden in test mode or list mode, so they don't
+    // need the lock. It's held until main returns.
+    let _lock = if no_lock || settings.test_mode || settings.list || directories.is_empty() {
+        None
+    } else {
+        match lock::InstanceLock::acquire(&directories)? {
+            lock::Acquired::Locked(lock) => Some(lock),
+            lock::Acquired::HeldBy(pid) => {
+                error!(
+                    "Another instance of filehider{} is already hiding in these directories! Pass \
+                    --no-lock to run anyway.",
+                    pid.map_or(String::new(), |pid| format!(" (PID {})", pid))
+                );
+                return Ok(ExitCode::from(EXIT_LOCKED));
+            }
+        }
+    };
+
+    handle_interrupts()?;
+
+    // Serve the metrics before immediate mode, so an address that's in use fails right away
+    #[cfg(feature = "metrics")]
+    if let Some(addr) = metrics_addr.filter(|_| watch) {
+        metrics::serve(addr)?;
+    }
+
+    if let Some(output) = &output {
+        output.start(&directories);
+    }
+
+    // If immediate mode is enabled, then immediately hide all files and directories that match the
+    // given file names and extensions. With --watch-existing, it's run by watch mode instead, once
+    // the directories are watched.
+    let immediate = ImmediateRun {
+        files,
+        progress: progress && !args.quiet,
+        summary_json,
+    };
+    let mut outcome = Outcome::default();
+    // Replayed events can't race the walk, so it still runs first with --replay-events
+    let existing = if watch_existing && args.replay_events.is_none() {
+        Some(immediate)
+    } else {
+        if run_immediate {
+            outcome = immediate.run(&directories, &mut settings)?;
+        }
+        None
+    };
+
+    // If watch mode is enabled, then watch for changes to the files and directories and automatically
+    // hide them. Watch mode only stops with a fatal error, on Ctrl+C, or once --duration has
+    // passed.
+    if watch && !interrupted() && !outcome.aborted {
+        if settings.test_mode {
+            info!("Running watch mode...");
+        }
+
+        // Directories hidden by immediate mode were renamed, so they have to be watched under
+        // their new names
+        #[cfg(not(windows))]
+        let directories = if settings.include_root && run_immediate {
+            hidden_roots(directories, &settings.hidden_prefix)
+        } else {
+            directories
+        };
+
+        outcome = outcome
+            + match &args.replay_events {
+                Some(events) => replay::replay_events(events, directories, settings)?,
+                None => watch_mode(directories, settings, &matches, existing)?,
+            };
+    }
+
+    if let Some(output) = &output {
+        output.finish(outcome)?;
+    }
+
+    Ok(exit_code(outcome, fail_on_match))
+}
+
+// What immediate mode needs besides the directories and settings, so it can run either before
+// watch mode or, with --watch-existing, once the directories are watched
+struct ImmediateRun {
+    files: HashSet<PathBuf>,
+    progress: bool,
+    summary_json: Option<PathBuf>,
+}
+
+impl ImmediateRun {
+    // Hide the files given and everything that matches in the directories, then print the errors,
+    // the summary and the counts, and write the report. Fails if the hide limit is reached.
+    fn run(self, directories: &HashSet<PathBuf>, settings: &mut Settings) -> Result<Outcome> {
+        let started = Instant::now();
+        if settings.test_mode {
+            info!("Running immediate mode...");
+        }
+        let mut limiter = Limiter::default();
+        let progress = Progress::new(self.progress, settings);
+        let report = Report::new(self.summary_json, directories);
+        let errors = ErrorList::limited(settings.max_errors_total);
+        let result = files_mode(&self.files, settings, &mut limiter, &progress, &report, &errors)
+            .and_then(|files| {
+                let stopped = limiter.reached(settings)
+                    || settings.quit.load(Ordering::Relaxed)
+                    || errors.reached();
+                if stopped {
+                    Ok(files)
+                } else {
+                    let directories = immediate_mode(
+                        directories,
+                        settings,
+                        &mut limiter,
+                        &progress,
+                        &report,
+                        &errors,
+                    )?;
+                    Ok(files + directories)
+                }
+            });
+        progress.finish();
+
+        // Print the errors so far together, even if a directory couldn't be read at all
+        errors.print("immediate mode");
+        if let Some(sorted) = settings.sorted.take() {
+            sorted.print(settings)?;
+        }
+
+        // Only fails with --fail-fast, or when a directory can't be read at all
+        let mut outcome = match result {
+            Ok(outcome) => outcome,
+            Err(e) if settings.fail_fast => {
+                return Err(e.context(format!(
+                    "Stopped at the first error because of --fail-fast, after examining {} paths \
+                    and hiding {}!",
+                    progress.examined.get(),
+                    progress.hidden.get()
+                )));
+            }
+            Err(e) => return Err(e),
+        };
+
+        outcome.aborted = errors.reached();
+        let title = if interrupted() {
+            "Immediate mode interrupted"
+        } else if outcome.aborted {
+            "Immediate mode aborted at the limit set by --max-errors-total"
+        } else if settings.quit.load(Ordering::Relaxed) {
+            "Immediate mode stopped at the prompt"
+        } else {
+            "Immediate mode finished"
+        };
+        outcome.print_summary(title, started.elapsed(), settings);
+        if let Some(counts) = &settings.counts {
+            counts.print(outcome.hidden)?;
+        }
+
+        // Write the report even if the hide limit was reached below, since that's worth auditing
+        report.write(outcome, settings.test_mode || settings.list)?;
+
+        // Stop instead of going on to watch mode, since the patterns are likely broader than
+        // intended
+        if limiter.reached(settings) {
+            return Err(anyhow!(
+                "Stopped after hiding {} paths, the limit set by --max-hides! Check the file \
+                names and extensions, or pass --force to hide more.",
+                limiter.hidden
+            ));
+        }
+
+        Ok(outcome)
+    }
+}
+
+// Helper function to find the directories that --include-root hid in immediate mode by adding a dot
+// to their names, so they can still be watched. Directories that can't be found under either name
+// are dropped with a warning.
+#[cfg(not(windows))]
+fn hidden_roots(directories: HashSet<PathBuf>, prefix: &str) -> HashSet<PathBuf> {
+    directories
+        .into_iter()
+        .filter_map(|directory| {
+            if directory.is_dir() {
+                return Some(directory);
+            }
+
+            let hidden = directory
+                .file_name()
+                .and_then(OsStr::to_str)
+                .map(|name| directory.with_file_name(format!("{}{}", prefix, name)))
+                .filter(|hidden| hidden.is_dir());
+            match hidden {
+                Some(hidden) => {
+                    info!(
+                        "Directory {} was hidden, watching {} instead",
+                        directory.display(),
+                        hidden.display()
+                    );
+                    Some(hidden)
+                }
+                None => {
+                    warn!(
+                        "Directory {} no longer exists, so it won't be watched",
+                        directory.display()
+                    );
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+// Helper function to pick the exit code for a run that finished. Errors take precedence over
+// matches, since a run with errors may not have seen every path.
+fn exit_code(outcome: Outcome, fail_on_match: bool) -> ExitCode {
+    if outcome.errors > 0 {
+        ExitCode::from(EXIT_ERRORS)
+    } else if fail_on_match && outcome.hidden > 0 {
+        ExitCode::from(EXIT_MATCHES)
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+// Load the config file and command line options and run the same validation as a normal run
+// without hiding anything. Print every problem found and exit with 1, or print a summary of the
+// effective rules if there are none.
+fn check_config(matches: &ArgMatches) -> Result<ExitCode> {
+    let errors = ErrorList::default();
+    let config = match Config::load(matches) {
+        Ok(config) => config,
+        // Nothing else can be checked without the config
+        Err(e) => {
+            errors.push(None, e);
+            errors.print("check-config");
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+    if !config.stdin {
+        if let Err(e) = check_modes(config.watch, config.immediate) {
+            errors.push(None, e);
+        }
+    }
+
+    // Run the checks of setup that don't depend on each other, so they're all reported at once.
+    // The rest of setup's validation needs the directories and patterns, so it only runs once
+    // these pass.
+    for path in &config.directories {
+        if let Err(e) = resolve_targets(path, config.watch) {
+            errors.push(None, e);
+        }
+    }
+    if let Err(e) = check_depths(config.min_depth, config.max_depth) {
+        errors.push(None, e);
+    }
+    for e in pattern_errors(&config) {
+        errors.push(None, e);
+    }
+    for pattern in &config.exclude_dirs {
+        if let Err(e) = exclude_dir_pattern(pattern) {
+            errors.push(None, e);
+        }
+    }
+    if let Err(e) = path_regex(&config) {
+        errors.push(None, e);
+    }
+    #[cfg(unix)]
+    if let Some(Err(e)) = config.owner.as_deref().map(parse_owner) {
+        errors.push(None, e);
+    }
+    #[cfg(unix)]
+    if let Some(Err(e)) = config.mode.as_deref().map(parse_mode) {
+        errors.push(None, e);
+    }
+    for section in &config.sections {
+        if let Err(e) = resolve_section_path(&section.path) {
+            errors.push(None, e);
+        }
+    }
+
+    let config_path = config.path.clone();
+    if errors.is_empty() {
+        match setup(config) {
+            Ok((directories, files, settings)) => {
+                println!("OK");
+                print_summary(config_path.as_deref(), &directories, &files, &settings);
+                return Ok(ExitCode::SUCCESS);
+            }
+            Err(e) => errors.push(None, e),
+        }
+    }
+
+    errors.print("check-config");
+    Ok(ExitCode::FAILURE)
+}
+
+// Helper function to find the file names and extensions that can never match a path
+fn pattern_errors(config: &Config) -> Vec<anyhow::Error> {
+    let mut errors = Vec::new();
+
+    // File names and extensions are only ever compared against the last component of a path, so
+    // ones containing a path separator can never match
+    let names = split_patterns(&config.file_names)
+        .chain(split_patterns(&config.dir_names))
+        .chain(
+            config
+                .sections
+                .iter()
+                .flat_map(|section| split_patterns(&section.names)),
+        );
+    for name in names {
+        if name.is_empty() || name.contains(std::path::is_separator) {
+            errors.push(anyhow!("File name \"{}\" can never match a file!", name));
+        }
+    }
+
+    let extensions = split_patterns(&config.file_extensions)
+        .chain(split_patterns(&config.dir_extensions))
+        .chain(
+            config
+                .sections
+                .iter()
+                .flat_map(|section| split_patterns(&section.extensions)),
+        );
+    for extension in extensions {
+        let bare = bare_extension(extension);
+        if bare.is_empty() || bare.contains('.') || bare.contains(std::path::is_separator) {
+            errors.push(anyhow!(
+                "File extension \"{}\" can never match a file!",
+                extension
+            ));
+        }
+    }
+
+    errors
+}
+
+// Helper function to print a summary of the effective rules for check-config
+fn print_summary(
+    config_path: Option<&Path>,
+    directories: &HashSet<PathBuf>,
+    files: &HashSet<PathBuf>,
+    settings: &Settings,
+) {
+    match config_path {
+        Some(path) => println!("Config file: {}", path.display()),
+        None => println!("Config file: none"),
+    }
+
+    let mut directories: Vec<&PathBuf> = directories.iter().collect();
+    directories.sort();
+    for directory in directories {
+        let recursive = settings.recursive_for(directory);
+        println!(
+            "Directory: {}{}",
+            directory.display(),
+            if recursive { " (recursive)" } else { "" }
+        );
+    }
+
+    let mut files: Vec<&PathBuf> = files.iter().collect();
+    files.sort();
+    for file in files {
+        println!("File: {}", file.display());
+    }
+
+    if let Some(min_depth) = settings.min_depth {
+        println!("Minimum depth: {}", min_depth);
+    }
+    if let Some(max_depth) = settings.max_depth {
+        println!("Maximum depth: {}", max_depth);
+    }
+    if !settings.exclude_dirs.is_empty() {
+        let patterns: Vec<&str> = settings.exclude_dirs.iter().map(glob::Pattern::as_str).collect();
+        println!("Excluded directories: {}", patterns.join(", "));
+    }
+
+    print_matcher("", &settings.matcher);
+    println!("Test mode: {}", settings.test_mode);
+
+    for section in &settings.sections {
+        println!("Section: {}", section.path.display());
+        println!("  Recursive: {}", section.recursive);
+        print_matcher("  ", &section.matcher);
+    }
+}
+
+// Helper function to print the rules of a matcher for check-config
+fn print_matcher(indent: &str, matcher: &Matcher) {
+    let sorted = |set: &HashSet<String>| {
+        let mut values: Vec<&str> = set.iter().map(String::as_str).collect();
+        values.sort_unstable();
+        if values.is_empty() {
+            "(any)".to_string()
+        } else {
+            values.join(", ")
+        }
+    };
+
+    println!("{}File names: {}", indent, sorted(&matcher.file_names));
+    println!(
+        "{}File extensions: {}",
+        indent,
+        sorted(&matcher.file_extensions)
+    );
+    if let Some(dir_names) = &matcher.dir_names {
+        println!("{}Directory names: {}", indent, sorted(dir_names));
+    }
+    if !matcher.dir_extensions.is_empty() {
+        println!(
+            "{}Directory extensions: {}",
+            indent,
+            sorted(&matcher.dir_extensions)
+        );
+    }
+
+    let mut file_types = Vec::new();
+    if matcher.hide_files {
+        file_types.push("file");
+    }
+    if matcher.hide_directories {
+        file_types.push("directory");
+    }
+    println!("{}File types: {}", indent, file_types.join(", "));
+    println!("{}Case sensitive: {}", indent, matcher.case_sensitive);
+    println!("{}Match stems: {}", indent, matcher.match_stem);
+    println!(
+        "{}Match directory extensions: {}",
+        indent, matcher.match_dir_extensions
+    );
+    println!("{}Git ignored paths: {}", indent, matcher.gitignore.is_some());
+    if let Some(path_regex) = &matcher.path_regex {
+        println!("{}Path regex: {}", indent, path_regex.as_str());
+    }
+
+    #[cfg(unix)]
+    if let Some(owner) = matcher.owner {
+        println!("{}Owner: {}", indent, owner);
+    }
+    #[cfg(unix)]
+    if let Some(mode) = matcher.mode {
+        println!("{}Mode: {:o}", indent, mode);
+    }
+}
+
+// Helper function to split file names or extensions given as lists (e.g. "a.tmp,b.tmp;c.tmp"). The
+// empty segments left by repeated or trailing delimiters are dropped.
+fn split_patterns(patterns: &[String]) -> impl Iterator<Item = &str> {
+    patterns
+        .iter()
+        .flat_map(|pattern| pattern.split([',', ';']))
+        .map(str::trim)
+        .filter(|pattern| !pattern.is_empty())
+}
+
+// Helper function to remove the leading dot from an extension, if it has one
+fn bare_extension(extension: &str) -> &str {
+    extension.strip_prefix('.').unwrap_or(extension)
+}
+
+// Helper function to stop gracefully on Ctrl+C, so immediate mode and watch mode can print their
+// summaries and clean up (e.g. restore paths with --restore-on-exit) before exiting. A second
+// Ctrl+C exits right away.
+fn handle_interrupts() -> Result<()> {
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(130);
+        }
+    })
+    .with_context(|| "Failed to set Ctrl+C handler!")
+}
+
+// Helper function to check whether Ctrl+C was pressed
+fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+// Helper function to check that at least one of watch mode and immediate mode is enabled
+fn check_modes(watch: bool, immediate: bool) -> Result<()> {
+    if !watch && !immediate {
+        Err(anyhow!("Both watch mode and immediate mode are disabled. At least one of these modes must be enabled."))
+    } else {
+        Ok(())
+    }
+}
+
+// Helper function to check that the minimum depth isn't greater than the maximum depth
+fn check_depths(min_depth: Option<u64>, max_depth: Option<u64>) -> Result<()> {
+    match (min_depth, max_depth) {
+        (Some(min_depth), Some(max_depth)) if min_depth > max_depth => Err(anyhow!(
+            "Minimum depth {} is greater than maximum depth {}!",
+            min_depth,
+            max_depth
+        )),
+        _ => Ok(()),
+    }
+}
+
+// Immediate mode function. Returns the counts of paths examined, hidden and failed. Errors are
+// printed and skipped, unless --fail-fast is given, in which case the first one is returned. A
+// directory that can't be read at all is always an error.
+fn immediate_mode(
+    directories: &HashSet<PathBuf>,
+    settings: &Settings,
+    limiter: &mut Limiter,
+    progress: &Progress,
+    report: &Report,
+    errors: &ErrorList,
+) -> Result<Outcome> {
+    // Small helper function to get a path from an entry result. Used to have consistent error
+    // messages.
+    fn get_path(entry: &walkdir::Result<walkdir::DirEntry>) -> Option<PathBuf> {
+        match entry {
+            Ok(entry) => Some(entry.path().to_path_buf()),
+            Err(e) => e.path().map(|p| p.to_path_buf()),
+        }
+    }
+
+    let mut outcome = Outcome::default();
+
+    // The given directories are walked in order as well with --sort-by
+    let mut directories: Vec<&PathBuf> = directories.iter().collect();
+    if settings.walk_sort.is_some() {
+        directories.sort();
+    }
+
+    for directory in directories {
+        progress.suspend(|| debug!("Walking directory: {}", directory.display()));
+
+        // Prune excluded directories so they aren't descended into. The given directory itself is
+        // never excluded. Pruning only works when directories are visited before their contents,
+        // so contents first order is handled below instead of by the walker.
+        let entries = walker(directory, settings)
+            .into_iter()
+            .filter_entry(|entry| {
+                let excluded = is_excluded_entry(entry, settings);
+                if excluded {
+                    progress.suspend(|| {
+                        debug!(
+                            target: logger::SKIPPED,
+                            "Skipping excluded directory: {}",
+                            entry.path().display()
+                        )
+                    });
+                }
+                !excluded
+            });
+
+        // Directories waiting for their contents to be handled first, with their depths
+        let mut pending: Vec<(PathBuf, usize)> = Vec::new();
+
+        for entry in entries {
+            let path = get_path(&entry);
+
+            // Following symlinks can lead back to a directory that's already being walked
+            if let Some(ancestor) = entry.as_ref().err().and_then(|e| e.loop_ancestor()) {
+                warn!(
+                    "Skipping symlink loop at {}, which points to {}",
+                    path.as_deref().unwrap_or(Path::new("")).display(),
+                    ancestor.display()
+                );
+                continue;
+            }
+
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    let root = e.depth() == 0;
+                    let e = anyhow::Error::from(e).context(if let Some(path) = &path {
+                        format!("Failed to get path from entry: {}", path.display())
+                    } else {
+                        "Failed to get path from entry".to_string()
+                    });
+
+                    if root {
+                        return Err(e);
+                    }
+                    if let Some(path) = &path {
+                        settings.record_error(path, &e);
+                    }
+                    outcome.errors += 1;
+                    progress.suspend(|| report_error(e, path, settings, errors))?;
+                    if errors.reached() {
+                        return Ok(outcome);
+                    }
+                    continue;
+                }
+            };
+
+            if settings.contents_first {
+                // Every directory at the same depth or deeper is finished once the walk gets here
+                while pending.last().is_some_and(|(_, depth)| *depth >= entry.depth()) {
+                    let (directory, _) = pending.pop().unwrap();
+                    let handled = limited_handle_path(
+                        &directory,
+                        settings,
+                        limiter,
+                        progress,
+                        report,
+                        errors,
+                        &mut outcome,
+                    )?;
+                    if !handled {
+                        return Ok(outcome);
+                    }
+                }
+
+                if entry.file_type().is_dir() {
+                    let depth = entry.depth();
+                    pending.push((entry.into_path(), depth));
+                    continue;
+                }
+            }
+
+            let handled = limited_handle_path(
+                entry.path(),
+                settings,
+                limiter,
+                progress,
+                report,
+                errors,
+                &mut outcome,
+            )?;
+            if !handled {
+                return Ok(outcome);
+            }
+        }
+
+        while let Some((directory, _)) = pending.pop() {
+            let handled = limited_handle_path(
+                &directory,
+                settings,
+                limiter,
+                progress,
+                report,
+                errors,
+                &mut outcome,
+            )?;
+            if !handled {
+                return Ok(outcome);
+            }
+        }
+
+        if settings.include_root
+            && !limited_handle_path(
+                directory,
+                settings,
+                limiter,
+                progress,
+                report,
+                errors,
+                &mut outcome,
+            )?
+        {
+            return Ok(outcome);
+        }
+    }
+
+    Ok(outcome)
+}
+
+// Helper function to create the walker for a directory in immediate mode. The directory itself is
+// only handled with --include-root, after everything in it, so the walk starts below it.
+fn walker(directory: &Path, settings: &Settings) -> walkdir::WalkDir {
+    use walkdir::WalkDir;
+
+    let walker = if settings.recursive_for(directory) {
+        let mut walker = WalkDir::new(directory).min_depth(settings.min_depth.unwrap_or(1));
+        if let Some(max_depth) = settings.max_depth {
+            walker = walker.max_depth(max_depth);
+        }
+        walker
+    } else {
+        WalkDir::new(directory).min_depth(1).max_depth(1)
+    };
+
+    let walker = walker
+        .follow_links(settings.follow_symlinks)
+        .same_file_system(settings.one_file_system);
+    match settings.walk_sort {
+        Some(sort) => walker.sort_by(move |a, b| sort.compare(a, b)),
+        None => walker,
+    }
+}
+
+// Helper function to check if an entry of a walk is a directory excluded by --exclude-dir
+fn is_excluded_entry(entry: &walkdir::DirEntry, settings: &Settings) -> bool {
+    entry.depth() > 0 && entry.file_type().is_dir() && settings.is_excluded(entry.file_name())
+}
+
+// Helper function to handle a path in immediate mode, within the limits of --max-ops-per-sec and
+// --max-hides, and counting it for --progress, --summary-json and the exit code. Returns false once
+// the hide limit or the error limit is reached or the user quits at the prompt, and the error with
+// --fail-fast.
+fn limited_handle_path(
+    path: &Path,
+    settings: &Settings,
+    limiter: &mut Limiter,
+    progress: &Progress,
+    report: &Report,
+    errors: &ErrorList,
+    outcome: &mut Outcome,
+) -> Result<bool> {
+    // Stop at the next path after Ctrl+C, keeping what's been done so far
+    if interrupted() || settings.quit.load(Ordering::Relaxed) || errors.reached() {
+        return Ok(false);
+    }
+
+    limiter.wait();
+    let result = progress.suspend(|| handle_path(path, settings));
+    let hidden = result.as_ref().is_ok_and(|handled| handled.hidden());
+    limiter.record(hidden, settings);
+    progress.record(path, hidden);
+    report.record(path, &result);
+    outcome.record(&result);
+
+    if let Err(e) = result {
+        settings.record_error(path, &e);
+        progress.suspend(|| report_error(e, Some(path.to_path_buf()), settings, errors))?;
+    }
+
+    Ok(!limiter.reached(settings) && !settings.quit.load(Ordering::Relaxed) && !errors.reached())
+}
+
+// Helper function to keep an error in immediate mode to print once it finishes, and keep going, or
+// to return it so the run stops with --fail-fast
+fn report_error(
+    e: anyhow::Error,
+    path: Option<PathBuf>,
+    settings: &Settings,
+    errors: &ErrorList,
+) -> Result<()> {
+    if settings.fail_fast {
+        Err(e)
+    } else {
+        debug!("{}", e);
+        errors.push(path, e);
+        Ok(())
+    }
+}
+
+// The errors of an immediate mode run or a --rescan sweep, printed together once it finishes so
+// they don't get lost among the paths being hidden. With --verbose, each is printed as it happens
+// as well. Immediate mode stops once there are as many as the limit from --max-errors-total.
+#[derive(Default)]
+struct ErrorList {
+    errors: RefCell<Vec<(Option<PathBuf>, anyhow::Error)>>,
+    limit: Option<usize>,
+}
+
+impl ErrorList {
+    fn limited(limit: Option<usize>) -> ErrorList {
+        ErrorList {
+            errors: RefCell::default(),
+            limit,
+        }
+    }
+
+    fn push(&self, path: Option<PathBuf>, e: anyhow::Error) {
+        self.errors.borrow_mut().push((path, e));
+    }
+
+    fn is_empty(&self) -> bool {
+        self.errors.borrow().is_empty()
+    }
+
+    // Check if the limit is reached, so the run stops
+    fn reached(&self) -> bool {
+        self.limit
+            .is_some_and(|limit| self.errors.borrow().len() >= limit)
+    }
+
+    // Print the errors sorted by path, with their causes, saying what they happened during
+    fn print(&self, during: &str) {
+        let mut errors = self.errors.borrow_mut();
+        if errors.is_empty() {
+            return;
+        }
+        errors.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let lines: Vec<String> = errors.iter().map(|(_, e)| format!("  {:#}", e)).collect();
+        error!(
+            "{} {} during {}:\n{}",
+            errors.len(),
+            if errors.len() == 1 { "error" } else { "errors" },
+            during,
+            lines.join("\n")
+        );
+    }
+}
+
+// Handle the files given directly as arguments. These are hidden unconditionally unless the filters
+// should be applied to them as well.
+fn files_mode(
+    files: &HashSet<PathBuf>,
+    settings: &Settings,
+    limiter: &mut Limiter,
+    progress: &Progress,
+    report: &Report,
+    errors: &ErrorList,
+) -> Result<Outcome> {
+    let mut outcome = Outcome::default();
+
+    for file in files {
+        if limiter.reached(settings) || settings.quit.load(Ordering::Relaxed) || errors.reached() {
+            break;
+        }
+
+        limiter.wait();
+        let result = progress.suspend(|| {
+            if settings.match_files {
+                handle_path(file, settings)
+            } else {
+                hide_path(file, None, settings)
+            }
+        });
+        let hidden = result.as_ref().is_ok_and(|handled| handled.hidden());
+        limiter.record(hidden, settings);
+        progress.record(file, hidden);
+        report.record(file, &result);
+        outcome.record(&result);
+
+        if let Err(e) = result {
+            settings.record_error(file, &e);
+            progress.suspend(|| report_error(e, Some(file.clone()), settings, errors))?;
+        }
+    }
+
+    Ok(outcome)
+}
+
+// The rule a path was matched by, printed in test mode and verbose mode, and given as the rule of
+// each path with --format json and ndjson. Names and extensions are the normalized ones that
+// matched.
+#[derive(Clone, Debug, PartialEq)]
+enum MatchReason {
+    Name(String),
+    // The name without its extension, with --match-stem
+    Stem(String),
+    Extension(String),
+    DirectoryName(String),
+    DirectoryExtension(String),
+    GitIgnored,
+    // The path regex, when it's the only rule
+    PathRegex,
+    // No file names or extensions are given, so every path of the hidden file types matches
+    CatchAll,
+}
+
+impl std::fmt::Display for MatchReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MatchReason::Name(name) => write!(f, "file name \"{}\"", name),
+            MatchReason::Stem(stem) => write!(f, "file stem \"{}\"", stem),
+            MatchReason::Extension(extension) => write!(f, "extension \"{}\"", extension),
+            MatchReason::DirectoryName(name) => write!(f, "directory name \"{}\"", name),
+            MatchReason::DirectoryExtension(extension) => {
+                write!(f, "directory extension \"{}\"", extension)
+            }
+            MatchReason::GitIgnored => write!(f, "ignored by git"),
+            MatchReason::PathRegex => write!(f, "path regex"),
+            MatchReason::CatchAll => write!(f, "no file names or extensions given"),
+        }
+    }
+}
+
+// What handling a path did
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Handled {
+    // Not matched by the rules, or left alone (e.g. declined at the prompt)
+    Skipped,
+    // Already hidden, or already visible when unhiding
+    AlreadyHidden,
+    // Hidden, unhidden, or would have been in test mode or list mode
+    Hidden { directory: bool },
+}
+
+impl Handled {
+    fn hidden(self) -> bool {
+        matches!(self, Handled::Hidden { .. })
+    }
+}
+
+// Counts of the paths a run examined, hidden (or would have hidden) and failed to handle, used to
+// pick the exit code and for the summary printed at the end
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+struct Outcome {
+    examined: usize,
+    hidden: usize,
+    hidden_directories: usize,
+    already_hidden: usize,
+    skipped: usize,
+    errors: usize,
+    // Whether immediate mode stopped early at the limit set by --max-errors-total
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    aborted: bool,
+}
+
+impl Outcome {
+    // Count the result of handling a path
+    fn record(&mut self, result: &Result<Handled>) {
+        self.examined += 1;
+        match result {
+            Ok(Handled::Skipped) => self.skipped += 1,
+            Ok(Handled::AlreadyHidden) => self.already_hidden += 1,
+            Ok(Handled::Hidden { directory }) => {
+                self.hidden += 1;
+                self.hidden_directories += *directory as usize;
+            }
+            Err(_) => self.errors += 1,
+        }
+    }
+
+    // Print the counts as a block, once a mode finishes or with --stats-interval
+    fn print_summary(&self, title: &str, elapsed: Duration, settings: &Settings) {
+        let hidden = match (settings.operation, settings.test_mode || settings.list) {
+            (Operation::Hide, false) => "Hidden",
+            (Operation::Hide, true) => "Would hide",
+            (Operation::Unhide, false) => "Unhidden",
+            (Operation::Unhide, true) => "Would unhide",
+            (Operation::Toggle, false) => "Toggled",
+            (Operation::Toggle, true) => "Would toggle",
+        };
+        let already = if settings.operation == Operation::Unhide {
+            "Already visible"
+        } else {
+            "Already hidden"
+        };
+
+        info!(
+            "{} after {}:\n  Examined: {}\n  {}: {} files, {} directories\n  {}: {}\n  \
+            Skipped by rules: {}\n  Errors: {}",
+            title,
+            humantime::format_duration(Duration::from_secs(elapsed.as_secs())),
+            self.examined,
+            hidden,
+            self.hidden - self.hidden_directories,
+            self.hidden_directories,
+            already,
+            self.already_hidden,
+            self.skipped,
+            self.errors
+        );
+    }
+}
+
+impl std::ops::Add for Outcome {
+    type Output = Outcome;
+
+    fn add(self, other: Outcome) -> Outcome {
+        Outcome {
+            examined: self.examined + other.examined,
+            hidden: self.hidden + other.hidden,
+            hidden_directories: self.hidden_directories + other.hidden_directories,
+            already_hidden: self.already_hidden + other.already_hidden,
+            skipped: self.skipped + other.skipped,
+            errors: self.errors + other.errors,
+            aborted: self.aborted || other.aborted,
+        }
+    }
+}
+
+// Counts of what watch mode has handled, for the lines printed every --stats-interval
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+struct Activity {
+    events: usize,
+    hidden: usize,
+    errors: usize,
+}
+
+impl Activity {
+    // Get the counts since an earlier snapshot
+    fn since(self, earlier: Activity) -> Activity {
+        Activity {
+            events: self.events - earlier.events,
+            hidden: self.hidden - earlier.hidden,
+            errors: self.errors - earlier.errors,
+        }
+    }
+}
+
+impl std::fmt::Display for Activity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} events, {} hidden, {} errors",
+            self.events, self.hidden, self.errors
+        )
+    }
+}
+
+// Rate and count limits for hides in immediate mode. After each hide, the next path isn't handled
+// until the interval from --max-ops-per-sec has passed, and immediate mode stops once --max-hides
+// paths have been hidden.
+#[derive(Default)]
+struct Limiter {
+    next: Option<Instant>,
+    hidden: usize,
+}
+
+impl Limiter {
+    // Sleep until the next hide is allowed
+    fn wait(&mut self) {
+        if let Some(next) = self.next.take() {
+            let now = Instant::now();
+            if next > now {
+                std::thread::sleep(next - now);
+            }
+        }
+    }
+
+    // Record the result of handling a path, delaying the next one if a file was actually hidden
+    fn record(&mut self, hidden: bool, settings: &Settings) {
+        if hidden {
+            self.hidden += 1;
+            if let Some(interval) = settings.hide_interval {
+                self.next = Some(Instant::now() + interval);
+            }
+        }
+    }
+
+    // Check if the maximum number of hides has been reached
+    fn reached(&self, settings: &Settings) -> bool {
+        settings.max_hides.is_some_and(|max_hides| self.hidden >= max_hides)
+    }
+}
+
+// Spinner showing how many paths immediate mode has examined and hidden, for --progress. The total
+// isn't known up front, so there's no percentage. It's drawn on stderr, and only when stderr is a
+// terminal, so output piped from stdout isn't affected.
+struct Progress {
+    bar: Option<ProgressBar>,
+    // Whether paths are only being printed rather than hidden
+    dry_run: bool,
+    examined: Cell<u64>,
+    hidden: Cell<u64>,
+}
+
+impl Progress {
+    fn new(enabled: bool, settings: &Settings) -> Progress {
+        let enabled = enabled && settings.output.is_none() && std::io::stderr().is_terminal();
+        let bar = enabled.then(|| {
+            // The message ends with the directory, which is cut off to fit the terminal
+            let bar = ProgressBar::new_spinner();
+            bar.set_style(
+                ProgressStyle::with_template("{spinner} [{elapsed}] {wide_msg}")
+                    .unwrap_or_else(|_| ProgressStyle::default_spinner()),
+            );
+            bar.enable_steady_tick(Duration::from_millis(100));
+            bar
+        });
+
+        Progress {
+            bar,
+            dry_run: settings.test_mode || settings.list,
+            examined: Cell::new(0),
+            hidden: Cell::new(0),
+        }
+    }
+
+    // Run a function that might print something, hiding the spinner while it runs so the output
+    // isn't mixed up with it
+    fn suspend<R>(&self, f: impl FnOnce() -> R) -> R {
+        match &self.bar {
+            Some(bar) => bar.suspend(f),
+            None => f(),
+        }
+    }
+
+    // Count a path that was handled, and whether it was hidden
+    fn record(&self, path: &Path, hidden: bool) {
+        self.examined.set(self.examined.get() + 1);
+        self.hidden.set(self.hidden.get() + hidden as u64);
+
+        if let Some(bar) = &self.bar {
+            bar.set_message(format!(
+                "Examined {} paths, {} {}, in {}",
+                self.examined.get(),
+                if self.dry_run { "would hide" } else { "hidden" },
+                self.hidden.get(),
+                path.parent().unwrap_or(path).display()
+            ));
+        }
+    }
+
+    // Remove the spinner once immediate mode is done
+    fn finish(&self) {
+        if let Some(bar) = &self.bar {
+            bar.finish_and_clear();
+        }
+    }
+}
+
+// The paths found in test mode or list mode with --sort, kept until every path has been examined.
+// Only the matching paths are kept, so memory use grows with the number of matches rather than
+// the size of the tree.
+#[derive(Default)]
+struct SortedPaths {
+    // Each path with the message printed for it in test mode, or None to print the bare path
+    paths: Mutex<Vec<(PathBuf, Option<String>)>>,
+}
+
+impl SortedPaths {
+    fn push(&self, path: &Path, message: Option<String>) {
+        self.paths
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push((path.to_path_buf(), message));
+    }
+
+    // Print the paths found so far in order, and forget them. Messages are grouped under the given
+    // directory each path is in, and bare paths are printed on their own so stdout stays a plain
+    // list.
+    fn print(&self, settings: &Settings) -> Result<()> {
+        let mut paths = std::mem::take(&mut *self.paths.lock().unwrap_or_else(|e| e.into_inner()));
+        paths.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+
+        let mut groups: BTreeMap<Option<&PathBuf>, Vec<(&Path, &str)>> = BTreeMap::new();
+        for (path, message) in &paths {
+            match message {
+                Some(message) => {
+                    let root = settings.root_of(path);
+                    groups.entry(root).or_default().push((path, message));
+                }
+                None => print_path(path, settings)?,
+            }
+        }
+
+        for (root, group) in groups {
+            match root {
+                Some(root) => info!("{} ({} paths):", root.display(), group.len()),
+                None => info!("Paths given directly ({} paths):", group.len()),
+            }
+            for (path, message) in group {
+                info!(target: logger::WOULD_HIDE, path:% = path.display(); "  {}", message);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// The number of paths each rule matched, printed once immediate mode finishes with --count
+struct RuleCounts {
+    json: bool,
+    rules: Mutex<BTreeMap<String, usize>>,
+}
+
+impl RuleCounts {
+    fn new(json: bool) -> RuleCounts {
+        RuleCounts {
+            json,
+            rules: Mutex::default(),
+        }
+    }
+
+    // Count a path, under the rule it matched
+    fn add(&self, reason: Option<&MatchReason>) {
+        let rule = reason.map_or_else(|| "given directly".to_string(), ToString::to_string);
+        *self
+            .rules
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(rule)
+            .or_default() += 1;
+    }
+
+    // Print the total, either as a bare number or along with the number for each rule as JSON
+    fn print(&self, total: usize) -> Result<()> {
+        let rules = self.rules.lock().unwrap_or_else(|e| e.into_inner());
+        output::print_count(total, &rules, self.json)
+    }
+}
+
+// Stdin mode function. Reads paths separated by newlines (or NUL bytes) from stdin and handles each
+// one. Paths that don't exist are reported without stopping the run, but count as errors.
+fn stdin_mode(settings: &Settings, null_data: bool, no_filter: bool) -> Outcome {
+    use std::io::BufRead;
+
+    let delimiter = if null_data { b'\0' } else { b'\n' };
+    let mut outcome = Outcome::default();
+
+    for line in std::io::stdin().lock().split(delimiter) {
+        let mut line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                error!("Failed to read path from stdin: {}", e);
+                outcome.errors += 1;
+                break;
+            }
+        };
+
+        // Strip the carriage return left over from Windows line endings
+        if !null_data && line.last() == Some(&b'\r') {
+            line.pop();
+        }
+
+        // Skip blank lines
+        if line.is_empty() {
+            continue;
+        }
+
+        let path = match path_from_bytes(line) {
+            Ok(path) => path,
+            Err(e) => {
+                error!("{}", e);
+                outcome.errors += 1;
+                continue;
+            }
+        };
+
+        // Use symlink_metadata so that dangling symlinks can still be hidden
+        let result = if fs::symlink_metadata(&path).is_err() {
+            Err(anyhow!("Path {} does not exist!", path.display()))
+        } else if no_filter {
+            hide_path(&path, None, settings)
+        } else {
+            handle_path(&path, settings)
+        };
+        outcome.record(&result);
+
+        if let Err(e) = result {
+            settings.record_error(&path, &e);
+            error!("{}", e);
+        }
+    }
+
+    outcome
+}
+
+// Helper function to convert raw bytes read from stdin into a path. Unix paths can be any bytes,
+// but elsewhere they need to be valid UTF-8.
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> Result<PathBuf> {
+    use std::{ffi::OsString, os::unix::ffi::OsStringExt};
+
+    Ok(PathBuf::from(OsString::from_vec(bytes)))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> Result<PathBuf> {
+    String::from_utf8(bytes)
+        .map(PathBuf::from)
+        .with_context(|| "Failed to convert path read from stdin to a string")
+}
+
+// Inverse of path_from_bytes, used for printing paths losslessly
+#[cfg(unix)]
+fn path_to_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn path_to_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().into_owned().into_bytes()
+}
+
+// Watch mode function. With --watch-existing, immediate mode is run once the directories are
+// watched, and its counts are included.
+fn watch_mode(
+    mut directories: HashSet<PathBuf>,
+    mut settings: Settings,
+    matches: &ArgMatches,
+    existing: Option<ImmediateRun>,
+) -> Result<Outcome> {
+    use crossbeam_channel::RecvTimeoutError;
+    use notify::RecursiveMode;
+
+    // Make everything hidden in this run visible again with --restore-on-exit, even when watch mode
+    // stops with an error
+    let _restore = RestoreOnExit(settings.session.clone());
+
+    // --only-new is anchored to now, before any directory is watched
+    if settings.only_new_since.is_some() {
+        settings.only_new_since = Some(SystemTime::now());
+    }
+
+    // Open a channel to receive the events. It's bounded, so a flood of events the loop can't keep
+    // up with holds up the watcher instead of filling memory.
+    let buffer = settings.event_buffer;
+    let (tx, rx) = crossbeam_channel::bounded(buffer);
+
+    // Create the watchers for --backend, delivering raw events
+    let mut watcher = backend::Watchers::new(settings.backend, tx, settings.poll_interval)?;
+
+    // Kept for --watch-existing, since immediate mode follows the symlinks itself
+    let roots = directories.clone();
+
+    // Symlinks to directories can't be followed by the watcher, so watch their targets as well
+    if settings.follow_symlinks {
+        directories.extend(symlinked_directories(&directories, &settings));
+    }
+
+    // Add the directories to watch. With --best-effort, the directories that can't be watched are
+    // dropped instead.
+    let mut unwatched = Vec::new();
+    for directory in &directories {
+        let mode = if settings.recursive_for(directory) {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        };
+        if let Err(e) = watcher.watch(directory, mode) {
+            let e = watch_error(e, directory, &directories, &settings);
+            if !settings.best_effort {
+                return Err(e);
+            }
+            error!("{}", e);
+            unwatched.push(directory.clone());
+        }
+    }
+    if !unwatched.is_empty() {
+        for directory in &unwatched {
+            directories.remove(directory);
+        }
+        if directories.is_empty() {
+            return Err(anyhow!("None of the directories could be watched!"));
+        }
+        warn!(
+            "Watching {} directories, without the {} that couldn't be watched",
+            directories.len(),
+            unwatched.len()
+        );
+    }
+
+    // If a config file is in use, watch it too so it can be reloaded when it changes. The directory
+    // containing it is watched rather than the file itself, since editors often save by replacing
+    // the file.
+    let config_path = settings.config_path.clone();
+    let mut config_contents = None;
+    if let Some(config_path) = &config_path {
+        if let Some(parent) = config_path.parent() {
+            watcher
+                .watch(parent, RecursiveMode::NonRecursive)
+                .with_context(|| "Failed to add config file to watch!")?;
+        }
+        config_contents = fs::read_to_string(config_path).ok();
+    }
+
+    // Reload the config file on SIGHUP, and rescan on SIGUSR1 (or Ctrl+Break on Windows). Listened
+    // for before --watch-existing runs immediate mode, so a signal sent once it's finished doesn't
+    // stop watch mode.
+    let signals = signals::Signals::listen()?;
+
+    // With --watch-existing, hide what's already there now that the directories are watched, so
+    // nothing created in between is missed. Those paths are seen again as events, which is
+    // harmless since they're already hidden.
+    let existing = match existing {
+        Some(existing) => existing.run(&roots, &mut settings)?,
+        None => Outcome::default(),
+    };
+    if existing.aborted {
+        return Ok(existing);
+    }
+
+    // Start showing notifications for the paths hidden from here on, so the ones immediate mode hid
+    // aren't shown
+    #[cfg(feature = "notifications")]
+    if let Some(window) = settings.notify {
+        notifications::start(window);
+    }
+
+    // Run --on-hide commands on their own thread from here on, so they don't hold up events
+    if settings.on_hide.is_some() {
+        hooks::start_worker();
+    }
+
+    // Start streaming actions to IPC clients. This isn't restarted when the config file is
+    // reloaded.
+    let ipc = settings.ipc_path.as_deref().map(IpcSink::listen).transpose()?;
+
+    // Start the worker threads, if any. The number of threads isn't changed when the config file
+    // is reloaded.
+    let settle = settings.settle.map(|settle| (settle, settings.settle_max));
+    let handler = EventHandler::new(settings.watch_threads, ipc, settings.debounce, settle);
+    #[cfg(feature = "metrics")]
+    metrics::watch(&handler.counters);
+
+    // The directories and settings are shared with the worker threads, and replaced as a whole when
+    // the config file is reloaded
+    let mut directories = Arc::new(directories);
+    let mut settings = Arc::new(settings);
+
+    // Add a global error counter. If this counter reaches 20 errors within 5 seconds, then the
+    // program will exit. The counts of errors and of files hidden so far live in the handler, since
+    // the worker threads update them, so only the count at the start of each period is kept here.
+    let mut timer = std::time::Instant::now();
+    let mut period_errors = 0;
+
+    // Stop once --duration has passed. This isn't changed when the config file is reloaded.
+    let duration = settings.duration;
+    let deadline = duration.map(|duration| Instant::now() + duration);
+
+    // Sweep every directory again every --rescan interval, to catch paths whose events were lost.
+    // The interval isn't changed when the config file is reloaded either.
+    let rescan = settings.rescan;
+    let mut next_rescan = rescan.map(|rescan| Instant::now() + rescan);
+    let mut sweep: Option<std::thread::JoinHandle<()>> = None;
+
+    // Print the counts of the last interval and so far every --stats-interval, which isn't changed
+    // on reload either. Events are counted here as they arrive, before they're handled.
+    let started = Instant::now();
+    let stats_interval = settings.stats_interval;
+    let mut next_stats = stats_interval.map(|interval| started + interval);
+    let mut events = 0;
+    let mut last_stats = Activity::default();
+
+    // Watched directories that were removed or moved away. They don't count as errors, and are
+    // watched again and swept once they're back. Every directory is checked for being gone every
+    // MISSING_POLL_INTERVAL as well.
+    let mut missing: HashMap<PathBuf, Outage> = HashMap::new();
+    let mut returned = HashSet::new();
+    let mut last_missing_check = Instant::now();
+
+    // When the last event arrived or the last heartbeat was printed, for --heartbeat-secs
+    let mut idle_since = Instant::now();
+
+    // Whether the channel is nearly full, so the warning is only printed once until it drains
+    let mut backlogged = false;
+
+    // Watched directories whose events were lost when the event queue overflowed, waiting to be
+    // swept, and when the last of those sweeps started
+    let mut overflowed = HashSet::new();
+    let mut last_overflow_sweep: Option<Instant> = None;
+
+    loop {
+        let now = Instant::now();
+        if deadline.is_some_and(|deadline| now >= deadline) {
+            break;
+        }
+        if interrupted() {
+            info!("Interrupted, stopping...");
+            break;
+        }
+
+        if now >= last_missing_check + MISSING_POLL_INTERVAL {
+            for directory in directories.iter() {
+                if !missing.contains_key(directory) && !directory.is_dir() {
+                    lose_directory(&mut watcher, directory, &mut missing);
+                }
+            }
+            last_missing_check = now;
+        }
+
+        // Checks back off while a directory stays gone, so one that's gone for good isn't checked
+        // constantly
+        missing.retain(|directory, outage| {
+            if now < outage.next_check {
+                return true;
+            }
+            let mode = if settings.recursive_for(directory) {
+                RecursiveMode::Recursive
+            } else {
+                RecursiveMode::NonRecursive
+            };
+            if directory.is_dir() && watcher.watch(directory, mode).is_ok() {
+                let gone = Duration::from_secs(outage.since.elapsed().as_secs());
+                info!(
+                    "Watched directory {} is back after {}, watching it again",
+                    directory.display(),
+                    humantime::format_duration(gone)
+                );
+                #[cfg(feature = "metrics")]
+                metrics::restart();
+                returned.insert(directory.clone());
+                return false;
+            }
+            outage.interval = (outage.interval * 2).min(MISSING_POLL_MAX_INTERVAL);
+            outage.next_check = now + outage.interval;
+            true
+        });
+        #[cfg(feature = "metrics")]
+        metrics::set_watched(directories.len() - missing.len());
+
+        if signals.reload_requested() {
+            info!("Reloading the configuration, as asked by a signal");
+            config_contents = config_path.as_ref().and_then(|path| fs::read_to_string(path).ok());
+            if let Err(e) = reload_config(matches, &mut watcher, &mut directories, &mut settings) {
+                error!("Failed to reload config file, keeping the old config: {:#}", e);
+            }
+            missing.retain(|directory, _| directories.contains(directory));
+        }
+
+        // Handle the paths whose --debounce window has ended
+        handler.flush(false, &directories, &settings);
+
+        // A rescan asked for by a signal doesn't move the next --rescan sweep
+        if signals.rescan_requested() {
+            if sweep.as_ref().is_none_or(|sweep| sweep.is_finished()) {
+                info!("Rescanning {} directories, as asked by a signal", directories.len());
+                sweep = Some(spawn_rescan(
+                    &directories,
+                    &settings,
+                    &handler.counters,
+                    Some("a signal"),
+                ));
+            } else {
+                info!("Skipping the rescan asked for by a signal, since one is already running");
+            }
+        }
+
+        // Sweep the directories that are back, since paths created while they were gone, or before
+        // they were watched again, have no events
+        if !returned.is_empty() && sweep.as_ref().is_none_or(|sweep| sweep.is_finished()) {
+            returned.retain(|directory| directories.contains(directory));
+            let roots = Arc::new(std::mem::take(&mut returned));
+            sweep = Some(spawn_rescan(
+                &roots,
+                &settings,
+                &handler.counters,
+                Some("a watched directory came back"),
+            ));
+        }
+
+        // Sweep the directories whose events were lost when the event queue overflowed, at most
+        // once every OVERFLOW_SWEEP_INTERVAL so repeated overflows don't rescan continuously.
+        // Overflows in the meantime are covered by the next sweep.
+        if !overflowed.is_empty()
+            && last_overflow_sweep.is_none_or(|last| now >= last + OVERFLOW_SWEEP_INTERVAL)
+            && sweep.as_ref().is_none_or(|sweep| sweep.is_finished())
+        {
+            overflowed.retain(|directory| directories.contains(directory));
+            let roots = Arc::new(std::mem::take(&mut overflowed));
+            info!("Rescanning {} directories after the overflow", roots.len());
+            sweep = Some(spawn_rescan(
+                &roots,
+                &settings,
+                &handler.counters,
+                Some("the overflow"),
+            ));
+            last_overflow_sweep = Some(now);
+        }
+
+        // Only one sweep runs at a time, so a sweep that takes longer than the interval delays the
+        // next one instead of piling up
+        if let (Some(rescan), Some(due)) = (rescan, next_rescan) {
+            if now >= due {
+                if settings.active_between.is_some_and(|window| !window.is_active_now()) {
+                    debug!("Skipping rescan outside the active hours");
+                } else if sweep.as_ref().is_none_or(|sweep| sweep.is_finished()) {
+                    sweep = Some(spawn_rescan(&directories, &settings, &handler.counters, None));
+                } else {
+                    debug!("Skipping rescan, since the previous one is still running");
+                }
+                next_rescan = Some(now + rescan);
+            }
+        }
+
+        if let (Some(interval), Some(due)) = (stats_interval, next_stats) {
+            if now >= due {
+                let counters = handler.counters.get();
+                let total = Activity {
+                    events,
+                    hidden: counters.hidden,
+                    errors: counters.errors,
+                };
+                let period = total.since(last_stats);
+                info!(
+                    "Last {}: {}; total: {}",
+                    humantime::format_duration(interval),
+                    period,
+                    total
+                );
+                if let Some(output) = &settings.output {
+                    output.stats(interval, period, total);
+                }
+                last_stats = total;
+                next_stats = Some(now + interval);
+            }
+        }
+
+        // Only wait for an event until the next heartbeat, the end of the duration, a rescan,
+        // stats, a debounced path or a settling file are due, or a missing directory is checked
+        // on, and never for longer than it takes to notice Ctrl+C. Once no event has arrived for
+        // the whole heartbeat, the watcher is idle, so print the heartbeat and keep waiting.
+        let timeout = deadline
+            .into_iter()
+            .chain(next_rescan)
+            .chain(next_stats)
+            .chain(handler.next_due())
+            .chain(missing.values().map(|outage| outage.next_check))
+            .chain(settings.heartbeat.map(|heartbeat| idle_since + heartbeat))
+            .min()
+            .map_or(INTERRUPT_POLL_INTERVAL, |due| {
+                due.saturating_duration_since(now).min(INTERRUPT_POLL_INTERVAL)
+            });
+        let event = match rx.recv_timeout(timeout) {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Timeout) => {
+                if settings.heartbeat.is_some_and(|heartbeat| idle_since.elapsed() >= heartbeat) {
+                    info!(
+                        "Watching {} directories, {} files hidden so far",
+                        directories.len(),
+                        handler.counters.get().hidden
+                    );
+                    idle_since = Instant::now();
+                }
+                continue;
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(anyhow!("Critical error in watcher: event channel disconnected!"));
+            }
+        };
+        idle_since = Instant::now();
+        events += 1;
+
+        // Warn when the events waiting pass three quarters of --event-buffer, and again only once
+        // they've dropped below a quarter
+        let waiting = rx.len();
+        if !backlogged && waiting >= buffer - buffer / 4 {
+            warn!(
+                "{} watcher events are waiting to be handled, out of room for {}. Watch mode may \
+                fall behind, consider raising --event-buffer or --threads-watch.",
+                waiting, buffer
+            );
+            backlogged = true;
+        } else if backlogged && waiting < buffer / 4 {
+            info!("Caught up with the watcher events");
+            backlogged = false;
+        }
+        #[cfg(feature = "metrics")]
+        metrics::event(&event);
+
+        match handle_event(event, config_path.as_deref(), &directories, &settings, &handler) {
+            EventOutcome::Handled => {}
+            EventOutcome::ConfigChanged => {
+                // Editors can fire several events for one save, so only reload when the contents
+                // actually changed
+                let contents = config_path.as_ref().and_then(|path| fs::read_to_string(path).ok());
+                if contents.is_some() && contents != config_contents {
+                    config_contents = contents;
+                    if let Err(e) =
+                        reload_config(matches, &mut watcher, &mut directories, &mut settings)
+                    {
+                        error!("Failed to reload config file, keeping the old config: {:#}", e);
+                    }
+                    missing.retain(|directory, _| directories.contains(directory));
+                }
+            }
+            EventOutcome::Lost(lost) => {
+                for path in lost {
+                    lose_directory(&mut watcher, &path, &mut missing);
+                }
+            }
+            EventOutcome::Overflow(roots) => {
+                warn!(
+                    "The watcher's event queue overflowed, so new paths in {} directories may \
+                    have been missed",
+                    roots.len()
+                );
+                overflowed.extend(roots);
+            }
+        }
+
+        // If the error counter is too high, exit the program
+        let errors = handler.counters.get().errors;
+        if errors - period_errors >= ERROR_LIMIT && timer.elapsed().as_secs() <= ERROR_TIME_LIMIT {
+            return Err(anyhow!(
+                "Too many errors in a short period of time. Exiting program."
+            ));
+        } else if timer.elapsed().as_secs() > 5 {
+            period_errors = errors;
+            timer = std::time::Instant::now();
+        }
+    }
+
+    // Let the worker threads finish the events already queued, including the paths still waiting
+    // for their --debounce window and the files that have settled, and a sweep that's still
+    // running, before reporting
+    handler.flush(true, &directories, &settings);
+    if let Some(sweep) = sweep {
+        let _ = sweep.join();
+    }
+    let mut outcome = handler.finish();
+    hooks::finish();
+
+    // Restore now rather than when watch mode returns, so the errors are counted
+    if let Some(session) = &settings.session {
+        outcome.errors += session.restore_all();
+    }
+
+    outcome.print_summary("Stopped watching", started.elapsed(), &settings);
+
+    Ok(existing + outcome)
+}
+
+// What the watch loop has to do about an event, beyond handling the paths in it
+enum EventOutcome {
+    Handled,
+    // Something changed the config file, which may need to be reloaded
+    ConfigChanged,
+    // Watched directories that were removed or moved away
+    Lost(Vec<PathBuf>),
+    // Watched directories whose events were dropped because the event queue overflowed
+    Overflow(Vec<PathBuf>),
+}
+
+// Handle an event from the watcher. Only creation events and renames are handled, and their paths
+// are passed to the handler. Changes to the config file and removed watched directories are left to
+// the caller, since they need the watcher. Shared by watch mode and --replay-events.
+fn handle_event(
+    event: notify::Result<notify::Event>,
+    config_path: Option<&Path>,
+    directories: &Arc<HashSet<PathBuf>>,
+    settings: &Arc<Settings>,
+    handler: &EventHandler,
+) -> EventOutcome {
+    use notify::event;
+
+    let lost = |paths: &[PathBuf]| {
+        EventOutcome::Lost(
+            paths
+                .iter()
+                .filter(|path| directories.contains(*path))
+                .cloned()
+                .collect(),
+        )
+    };
+
+    match event {
+        // The OS dropped events, so the paths they were about can only be found by a sweep. Only
+        // the watched directories the event names are affected, or all of them if it names none.
+        Ok(event) if event.need_rescan() => {
+            let affected: Vec<PathBuf> = directories
+                .iter()
+                .filter(|directory| event.paths.iter().any(|path| path.starts_with(directory)))
+                .cloned()
+                .collect();
+            EventOutcome::Overflow(if affected.is_empty() {
+                directories.iter().cloned().collect()
+            } else {
+                affected
+            })
+        }
+        Ok(event)
+            if !matches!(event.kind, event::EventKind::Access(_))
+                && config_path.is_some_and(|config_path| {
+                    event.paths.iter().any(|path| path == config_path)
+                }) =>
+        {
+            EventOutcome::ConfigChanged
+        }
+        Ok(event)
+            if matches!(
+                event.kind,
+                event::EventKind::Remove(_)
+                    | event::EventKind::Modify(event::ModifyKind::Name(event::RenameMode::From))
+            ) && event.paths.iter().any(|path| directories.contains(path)) =>
+        {
+            lost(&event.paths)
+        }
+        // Some backends deliver several paths in one event, so each of them is handled
+        Ok(event) if matches!(event.kind, event::EventKind::Create(_)) => {
+            if event.paths.is_empty() {
+                error!("No path in event!");
+                handler.counters.update(|outcome| outcome.errors += 1);
+            }
+            for path in &event.paths {
+                if is_watched(path, directories) {
+                    handler.submit(path, EventKind::Create, directories, settings);
+                } else {
+                    debug!("Ignoring {}, outside the watched directories", path.display());
+                }
+            }
+            EventOutcome::Handled
+        }
+        Ok(event)
+            if matches!(
+                event.kind,
+                event::EventKind::Modify(event::ModifyKind::Name(_))
+            ) && !matches!(
+                event.kind,
+                event::EventKind::Modify(event::ModifyKind::Name(event::RenameMode::From))
+            ) =>
+        {
+            if event.paths.is_empty() {
+                error!("No path in event!");
+                handler.counters.update(|outcome| outcome.errors += 1);
+            }
+
+            // With both ends of a rename, the first path is the old name and the second the new
+            // one. Otherwise the backend may not say which end a path is, so only the paths that
+            // still exist inside a watched directory are handled.
+            let both = matches!(
+                event.kind,
+                event::EventKind::Modify(event::ModifyKind::Name(event::RenameMode::Both))
+            );
+            let paths = event.paths.iter().skip(usize::from(both && event.paths.len() > 1));
+            for path in paths {
+                if fs::symlink_metadata(path).is_err() {
+                    debug!("Ignoring rename of {}, which no longer exists", path.display());
+                } else if !is_watched(path, directories) {
+                    debug!(
+                        "Ignoring rename to {}, outside the watched directories",
+                        path.display()
+                    );
+                } else {
+                    handler.submit(path, EventKind::Rename, directories, settings);
+                }
+            }
+            EventOutcome::Handled
+        }
+        Ok(_) => EventOutcome::Handled,
+        // Errors about a watched directory that's gone are expected once it's removed
+        Err(e)
+            if e.paths
+                .iter()
+                .any(|path| directories.contains(path) && !path.exists()) =>
+        {
+            lost(&e.paths)
+        }
+        Err(e) => {
+            error!("Critical error in watcher: {}", e);
+            handler.counters.update(|outcome| outcome.errors += 1);
+            EventOutcome::Handled
+        }
+    }
+}
+
+// Helper function to check whether a path is in one of the watched directories. Events also come
+// from the directory of the config file, which is only watched to reload the config file.
+fn is_watched(path: &Path, directories: &HashSet<PathBuf>) -> bool {
+    directories.iter().any(|directory| path.starts_with(directory))
+}
+
+// A watched directory that was removed or moved away: when it went, and when it's next checked for
+// being back, with the interval between checks so far
+struct Outage {
+    since: Instant,
+    next_check: Instant,
+    interval: Duration,
+}
+
+// Stop watching a directory that was removed or moved away, warning once, so watch mode carries on
+// with the remaining directories until it's back
+fn lose_directory(
+    watcher: &mut backend::Watchers,
+    directory: &Path,
+    missing: &mut HashMap<PathBuf, Outage>,
+) {
+    if let std::collections::hash_map::Entry::Vacant(entry) =
+        missing.entry(directory.to_path_buf())
+    {
+        let now = Instant::now();
+        entry.insert(Outage {
+            since: now,
+            next_check: now + MISSING_POLL_INTERVAL,
+            interval: MISSING_POLL_INTERVAL,
+        });
+        let _ = watcher.unwatch(directory);
+        warn!(
+            "Watched directory {} was removed, watching the remaining directories until it's back",
+            directory.display()
+        );
+    }
+}
+
+thread_local! {
+    // What caused the path being handled on this thread. Set for each watch mode event, and on the
+    // threads running --rescan sweeps, so the paths they hide can be told apart.
+    static EVENT: Cell<EventKind> = const { Cell::new(EventKind::Scan) };
+}
+
+// Start a --rescan sweep of the directories on its own thread, so the watch loop keeps handling
+// events while it runs. What it finds is added to the watch mode counts. Sweeps that weren't
+// scheduled by --rescan have a reason, and their results are logged with it.
+fn spawn_rescan(
+    directories: &Arc<HashSet<PathBuf>>,
+    settings: &Arc<Settings>,
+    counters: &Arc<WatchCounters>,
+    reason: Option<&'static str>,
+) -> std::thread::JoinHandle<()> {
+    let directories = Arc::clone(directories);
+    let settings = Arc::clone(settings);
+    let counters = Arc::clone(counters);
+
+    std::thread::spawn(move || {
+        EVENT.set(EventKind::Rescan);
+        debug!("Rescanning {} directories", directories.len());
+
+        let progress = Progress::new(false, &settings);
+        let report = Report::new(None, &directories);
+        let errors = ErrorList::default();
+        let result = immediate_mode(
+            &directories,
+            &settings,
+            &mut Limiter::default(),
+            &progress,
+            &report,
+            &errors,
+        );
+        errors.print("the rescan");
+
+        match result {
+            Ok(outcome) => {
+                if let Some(reason) = reason {
+                    info!(
+                        "Rescan after {} finished: examined {}, hid {}, {} errors",
+                        reason, outcome.examined, outcome.hidden, outcome.errors
+                    );
+                }
+                counters.update(|total| *total = *total + outcome);
+            }
+            Err(e) => {
+                error!("Rescan failed: {:#}", e);
+                counters.update(|outcome| outcome.errors += 1);
+            }
+        }
+    })
+}
+
+// Counts shared between the watch loop, the worker threads, and --rescan sweeps
+#[derive(Default)]
+struct WatchCounters {
+    outcome: Mutex<Outcome>,
+}
+
+impl WatchCounters {
+    fn update(&self, update: impl FnOnce(&mut Outcome)) {
+        update(&mut self.outcome.lock().unwrap_or_else(|e| e.into_inner()));
+    }
+
+    fn get(&self) -> Outcome {
+        *self.outcome.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// A path from a watch mode event, with the directories and settings in use when it arrived
+type WatchJob = (PathBuf, EventKind, Arc<HashSet<PathBuf>>, Arc<Settings>);
+
+// Handles the paths from watch mode events, either right away on the watch loop, or on a pool of
+// worker threads with --threads-watch. With --debounce, paths wait in pending until their window
+// ends, so repeated events for a path are handled once. With --settle, new files then wait in
+// settling until they stop changing.
+struct EventHandler {
+    workers: Option<crossbeam_channel::Sender<WatchJob>>,
+    threads: Vec<std::thread::JoinHandle<()>>,
+    ipc: Option<Arc<IpcSink>>,
+    counters: Arc<WatchCounters>,
+    debounce: Option<Duration>,
+    // The paths waiting for their window to end, with the kind of their first event and when
+    // they're due
+    pending: Mutex<HashMap<PathBuf, (EventKind, Instant)>>,
+    // How long new files have to stay unchanged, and how long to wait for that at most
+    settle: Option<(Duration, Duration)>,
+    settling: Mutex<HashMap<PathBuf, Settling>>,
+}
+
+// A new file waiting with --settle for its size and modification time to stop changing
+struct Settling {
+    event: EventKind,
+    size: u64,
+    modified: Option<SystemTime>,
+    // When the file was first seen, and when its size or modification time last changed
+    created: Instant,
+    changed: Instant,
+}
+
+impl EventHandler {
+    fn new(
+        threads: Option<usize>,
+        ipc: Option<IpcSink>,
+        debounce: Option<Duration>,
+        settle: Option<(Duration, Duration)>,
+    ) -> EventHandler {
+        let ipc = ipc.map(Arc::new);
+        let counters = Arc::new(WatchCounters::default());
+
+        let mut handles = Vec::new();
+        let workers = threads.map(|threads| {
+            // The queue is bounded so a burst of events can't use unbounded memory. The watch loop
+            // waits for a free slot once it's full.
+            let (sender, receiver) = crossbeam_channel::bounded::<WatchJob>(WATCH_QUEUE_SIZE);
+            for _ in 0..threads {
+                let receiver = receiver.clone();
+                let ipc = ipc.clone();
+                let counters = Arc::clone(&counters);
+                handles.push(std::thread::spawn(move || {
+                    for (path, event, directories, settings) in receiver {
+                        EVENT.set(event);
+                        handle_watched_path(
+                            &path,
+                            &directories,
+                            &settings,
+                            ipc.as_deref(),
+                            &counters,
+                        );
+                    }
+                }));
+            }
+            sender
+        });
+
+        EventHandler {
+            workers,
+            threads: handles,
+            ipc,
+            counters,
+            debounce,
+            pending: Mutex::new(HashMap::new()),
+            settle,
+            settling: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Wait for the worker threads to handle every queued path, and return the totals
+    fn finish(mut self) -> Outcome {
+        // Closing the queue stops each worker once it's empty
+        self.workers = None;
+        for thread in self.threads.drain(..) {
+            if thread.join().is_err() {
+                self.counters.update(|outcome| outcome.errors += 1);
+            }
+        }
+
+        self.counters.get()
+    }
+
+    // Handle a path from an event, or hold it until the end of its --debounce window. Later events
+    // for a path that's already waiting are dropped.
+    fn submit(
+        &self,
+        path: &Path,
+        event: EventKind,
+        directories: &Arc<HashSet<PathBuf>>,
+        settings: &Arc<Settings>,
+    ) {
+        // Events for NTFS alternate data streams (e.g. "file.txt:stream") are handled as events for
+        // the file the stream belongs to, since attributes can't be set on a stream
+        #[cfg(windows)]
+        if let Some(owner) = stream_owner(path) {
+            return self.submit(&owner, event, directories, settings);
+        }
+
+        let Some(debounce) = self.debounce else {
+            return self.release(path, event, directories, settings);
+        };
+
+        let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+        if pending.contains_key(path) {
+            debug!("Collapsing repeated event for {}", path.display());
+        } else {
+            pending.insert(path.to_path_buf(), (event, Instant::now() + debounce));
+        }
+    }
+
+    // Handle the waiting paths whose window has ended, or all of them, in the order they arrived.
+    // Then hide the new files that have settled. With all, files that haven't are left visible.
+    fn flush(&self, all: bool, directories: &Arc<HashSet<PathBuf>>, settings: &Arc<Settings>) {
+        let now = Instant::now();
+        let mut due = Vec::new();
+        self.pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|path, &mut (event, at)| {
+                let ready = all || at <= now;
+                if ready {
+                    due.push((path.clone(), event, at));
+                }
+                !ready
+            });
+
+        due.sort_by_key(|&(_, _, at)| at);
+        for (path, event, _) in due {
+            self.release(&path, event, directories, settings);
+        }
+
+        if let Some((settle, max)) = self.settle {
+            for (path, event) in self.settled(all, settle, max) {
+                self.dispatch(&path, event, directories, settings);
+            }
+        }
+    }
+
+    // When the next waiting path is due, or the next new file should be checked, if any
+    fn next_due(&self) -> Option<Instant> {
+        let pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+        let settling = self.settling.lock().unwrap_or_else(|e| e.into_inner());
+        let settle = self.settle.map_or(Duration::ZERO, |(settle, _)| settle);
+        pending
+            .values()
+            .map(|(_, due)| *due)
+            .chain(settling.values().map(|file| file.changed + settle))
+            .min()
+    }
+
+    // Handle a path whose window has ended, or start waiting for it to settle if it's a new file
+    fn release(
+        &self,
+        path: &Path,
+        event: EventKind,
+        directories: &Arc<HashSet<PathBuf>>,
+        settings: &Arc<Settings>,
+    ) {
+        if self.settle.is_some() && event == EventKind::Create {
+            let metadata = fs::symlink_metadata(path).ok();
+            if let Some(metadata) = metadata.filter(|metadata| metadata.is_file()) {
+                let now = Instant::now();
+                self.settling
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .entry(path.to_path_buf())
+                    .or_insert(Settling {
+                        event,
+                        size: metadata.len(),
+                        modified: metadata.modified().ok(),
+                        created: now,
+                        changed: now,
+                    });
+                return;
+            }
+        }
+
+        self.dispatch(path, event, directories, settings);
+    }
+
+    // Check the new files that have gone unchanged for the settle duration since they were last
+    // seen changing, and take the ones that are still unchanged. Files that changed wait for
+    // another settle duration, unless they've been changing for longer than max. Files that were
+    // removed are dropped.
+    fn settled(&self, all: bool, settle: Duration, max: Duration) -> Vec<(PathBuf, EventKind)> {
+        let now = Instant::now();
+        let mut settled = Vec::new();
+        self.settling
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|path, file| {
+                if !all && now < file.changed + settle {
+                    return true;
+                }
+
+                let metadata = match fs::symlink_metadata(path) {
+                    Ok(metadata) => metadata,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                        debug!("Dropping {}, which was removed while settling", path.display());
+                        return false;
+                    }
+                    // Handled anyway, so the error is reported
+                    Err(_) => {
+                        settled.push((path.clone(), file.event));
+                        return false;
+                    }
+                };
+
+                let (size, modified) = (metadata.len(), metadata.modified().ok());
+                if size == file.size && modified == file.modified && now >= file.changed + settle {
+                    settled.push((path.clone(), file.event));
+                    false
+                } else if all {
+                    info!("Not hiding {}, which hadn't settled yet", path.display());
+                    false
+                } else if now >= file.created + max {
+                    warn!(
+                        "Not hiding {}, which was still changing after {}",
+                        path.display(),
+                        humantime::format_duration(max)
+                    );
+                    false
+                } else {
+                    debug!("Waiting for {} to settle, since it's still changing", path.display());
+                    (file.size, file.modified, file.changed) = (size, modified, now);
+                    true
+                }
+            });
+        settled
+    }
+
+    // Handle a path right away, or queue it for the worker threads
+    fn dispatch(
+        &self,
+        path: &Path,
+        event: EventKind,
+        directories: &Arc<HashSet<PathBuf>>,
+        settings: &Arc<Settings>,
+    ) {
+        // Outside the active hours, the path is left for a --rescan sweep during them to find
+        if let Some(window) = settings.active_between.filter(|window| !window.is_active_now()) {
+            info!(
+                "Outside the active hours {}, not hiding {}",
+                window,
+                settings.display_path(path).display()
+            );
+            return;
+        }
+
+        match &self.workers {
+            Some(workers) => {
+                let job = (
+                    path.to_path_buf(),
+                    event,
+                    Arc::clone(directories),
+                    Arc::clone(settings),
+                );
+                if workers.send(job).is_err() {
+                    error!("Failed to queue path {}: no worker threads left!", path.display());
+                    self.counters.update(|outcome| outcome.errors += 1);
+                }
+            }
+            None => {
+                EVENT.set(event);
+                handle_watched_path(
+                    path,
+                    directories,
+                    settings,
+                    self.ipc.as_deref(),
+                    &self.counters,
+                )
+            }
+        }
+    }
+}
+
+// Helper function to handle a path from an event and count the result
+fn handle_watched_path(
+    path: &Path,
+    directories: &HashSet<PathBuf>,
+    settings: &Settings,
+    ipc: Option<&IpcSink>,
+    counters: &WatchCounters,
+) {
+    let result = handle_event_path(path, directories, settings, ipc);
+    counters.update(|outcome| outcome.record(&result));
+    if let Err(e) = result {
+        error!("{}", e);
+    }
+}
+
+// Handle a path from a watch mode event. Events in excluded directories, outside the depth limits,
+// or on another file system are skipped, since the watcher itself can't limit what it watches. The
+// depth is relative to the watched directory the path is in, and only limited for recursive
+// directories like in immediate mode. The result is reported to IPC clients, if any.
+fn handle_event_path(
+    path: &Path,
+    directories: &HashSet<PathBuf>,
+    settings: &Settings,
+    ipc: Option<&IpcSink>,
+) -> Result<Handled> {
+    let root = directories
+        .iter()
+        .filter(|directory| path.starts_with(directory))
+        .max_by_key(|directory| directory.components().count());
+
+    // Skip paths inside excluded directories, or excluded directories themselves
+    if !settings.exclude_dirs.is_empty() {
+        let relative = root.and_then(|root| path.strip_prefix(root).ok()).unwrap_or(path);
+        let mut names = relative.components().map(|component| component.as_os_str());
+        let last = names.next_back();
+
+        if names.any(|name| settings.is_excluded(name))
+            || last.is_some_and(|name| path.is_dir() && settings.is_excluded(name))
+        {
+            return Ok(Handled::Skipped);
+        }
+    }
+
+    if let Some(root) = root {
+        if (settings.min_depth.is_some() || settings.max_depth.is_some())
+            && settings.recursive_for(root)
+        {
+            let depth = path.strip_prefix(root).map_or(0, |relative| relative.components().count());
+            if !settings.within_depth(depth) {
+                return Ok(Handled::Skipped);
+            }
+        }
+
+        // Skip paths on a different file system than the watched directory. If the device can't
+        // be read, handle_path reports the error.
+        if let Some(root_device) = settings.root_devices.get(root) {
+            if device_id(path).is_ok_and(|device| device != *root_device) {
+                return Ok(Handled::Skipped);
+            }
+        }
+    }
+
+    let result = handle_path(path, settings);
+
+    // Report the action to IPC clients
+    if let Some(ipc) = ipc {
+        match &result {
+            Ok(Handled::Hidden { .. }) if settings.test_mode || settings.list => {
+                ipc.send(&Action::WouldHide {
+                    path: Action::path(path),
+                })
+            }
+            Ok(Handled::Hidden { .. }) => ipc.send(&Action::Hide {
+                path: Action::path(path),
+            }),
+            Ok(_) => {}
+            Err(e) => ipc.send(&Action::Error {
+                path: Action::path(path),
+                message: format!("{:#}", e),
+            }),
+        }
+    }
+
+    result
+}
+
+// Find the symlinks to directories under the given directories, so they can be watched as their own
+// roots in watch mode. Symlinks are followed while searching, so symlinks inside symlinked
+// directories are found too.
+fn symlinked_directories(directories: &HashSet<PathBuf>, settings: &Settings) -> Vec<PathBuf> {
+    use walkdir::WalkDir;
+
+    let mut symlinks = Vec::new();
+    for directory in directories {
+        if !settings.recursive_for(directory) {
+            continue;
+        }
+
+        // Loops and other errors are reported by immediate mode, so they're skipped here
+        for entry in WalkDir::new(directory).follow_links(true).into_iter().flatten() {
+            if entry.path_is_symlink() && entry.file_type().is_dir() {
+                symlinks.push(entry.into_path());
+            }
+        }
+    }
+
+    symlinks
+}
+
+// Helper function to describe a failure to watch a directory. When the inotify watch limit was
+// reached, the message explains how to get past it.
+fn watch_error(
+    e: notify::Error,
+    directory: &Path,
+    directories: &HashSet<PathBuf>,
+    settings: &Settings,
+) -> anyhow::Error {
+    let mut message = format!("Failed to add directory {} to watch: {}!", directory.display(), e);
+    if backend::is_watch_limit(&e) {
+        message.push('\n');
+        message.push_str(&backend::watch_limit_help(count_directories(directories, settings)));
+    }
+    anyhow!(message)
+}
+
+// Helper function to count the directories watch mode watches, including every directory inside
+// the recursive ones, since each takes a watch of its own
+fn count_directories(directories: &HashSet<PathBuf>, settings: &Settings) -> usize {
+    directories
+        .iter()
+        .map(|directory| {
+            if settings.recursive_for(directory) {
+                walkdir::WalkDir::new(directory)
+                    .into_iter()
+                    .filter_map(Result::ok)
+                    .filter(|entry| entry.file_type().is_dir())
+                    .count()
+            } else {
+                1
+            }
+        })
+        .sum()
+}
+
+// Reload the config file, replacing the settings and adding or removing watched directories to
+// match. If the new config is invalid, an error is returned and the old config stays active.
+fn reload_config(
+    matches: &ArgMatches,
+    watcher: &mut backend::Watchers,
+    directories: &mut Arc<HashSet<PathBuf>>,
+    settings: &mut Arc<Settings>,
+) -> Result<()> {
+    use notify::RecursiveMode;
+
+    let (mut new_directories, _, mut new_settings) = setup(Config::load(matches)?)?;
+    new_settings.session.clone_from(&settings.session);
+    new_settings.script.clone_from(&settings.script);
+    new_settings.journal.clone_from(&settings.journal);
+    // --only-new stays anchored to when watch mode started
+    if new_settings.only_new_since.is_some() {
+        new_settings.only_new_since = settings.only_new_since.or(new_settings.only_new_since);
+    }
+    if new_settings.follow_symlinks {
+        new_directories.extend(symlinked_directories(&new_directories, &new_settings));
+    }
+
+    // Stop watching directories that were removed from the config
+    for directory in directories.difference(&new_directories) {
+        if let Err(e) = watcher.unwatch(directory) {
+            error!("Failed to stop watching {}: {}", directory.display(), e);
+        }
+    }
+
+    // Watch directories that were added to the config, and rewatch directories whose recursive
+    // setting changed
+    for directory in &new_directories {
+        let recursive = new_settings.recursive_for(directory);
+        if directories.contains(directory) {
+            if recursive == settings.recursive_for(directory) {
+                continue;
+            }
+            let _ = watcher.unwatch(directory);
+        }
+
+        let mode = if recursive {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        };
+        if let Err(e) = watcher.watch(directory, mode) {
+            return Err(watch_error(e, directory, &new_directories, &new_settings));
+        }
+    }
+
+    let changes = describe_changes(directories, &new_directories, settings, &new_settings);
+    *directories = Arc::new(new_directories);
+    *settings = Arc::new(new_settings);
+
+    match &settings.config_path {
+        Some(path) => info!("Reloaded config file {}: {}", path.display(), changes),
+        // Reloaded by a signal, without a config file
+        None => info!("Reloaded configuration: {}", changes),
+    }
+
+    Ok(())
+}
+
+// Helper function to summarize what changed between two configs, for the reload log line
+fn describe_changes(
+    old_directories: &HashSet<PathBuf>,
+    new_directories: &HashSet<PathBuf>,
+    old: &Settings,
+    new: &Settings,
+) -> String {
+    let mut changes = Vec::new();
+
+    for directory in new_directories.difference(old_directories) {
+        changes.push(format!("watching {}", directory.display()));
+    }
+    for directory in old_directories.difference(new_directories) {
+        changes.push(format!("stopped watching {}", directory.display()));
+    }
+
+    let mut describe_set = |name: &str, old: &HashSet<String>, new: &HashSet<String>| {
+        let added = new.difference(old).count();
+        let removed = old.difference(new).count();
+        if added > 0 || removed > 0 {
+            changes.push(format!("{} {} added, {} removed", name, added, removed));
+        }
+    };
+    describe_set("file names", &old.matcher.file_names, &new.matcher.file_names);
+    describe_set(
+        "file extensions",
+        &old.matcher.file_extensions,
+        &new.matcher.file_extensions,
+    );
+    describe_set(
+        "directory names",
+        old.matcher.directory_names(),
+        new.matcher.directory_names(),
+    );
+    describe_set(
+        "directory extensions",
+        &old.matcher.dir_extensions,
+        &new.matcher.dir_extensions,
+    );
+
+    let old_regex = old.matcher.path_regex.as_ref().map(Regex::as_str);
+    if old_regex != new.matcher.path_regex.as_ref().map(Regex::as_str) {
+        changes.push("path regex changed".to_string());
+    }
+
+    if old.sections.len() != new.sections.len() {
+        changes.push(format!(
+            "{} sections instead of {}",
+            new.sections.len(),
+            old.sections.len()
+        ));
+    }
+
+    if changes.is_empty() {
+        "no changes to directories or rules".to_string()
+    } else {
+        changes.join(", ")
+    }
+}
+
+// Process a path. Returns whether the path was hidden (or would have been in test mode).
+fn handle_path(path: &Path, settings: &Settings) -> Result<Handled> {
+    if let Some(reveal) = &settings.reveal {
+        return reveal_path(path, reveal, settings);
+    }
+
+    if let Some(since) = settings.only_new_since {
+        if !created_since(path, since) {
+            debug!(
+                target: logger::SKIPPED,
+                "Skipping {}, which existed before watch mode started",
+                path.display()
+            );
+            return Ok(Handled::Skipped);
+        }
+    }
+
+    let Some(reason) = should_hide_file(path, settings.matcher_for(path))? else {
+        return Ok(Handled::Skipped);
+    };
+
+    // Only asked once the other filters match, since running it is slow
+    if let Some(command) = &settings.match_command {
+        if !command.accepts(path)? {
+            debug!(
+                target: logger::SKIPPED,
+                "Skipping {}, which --match-command rejected",
+                path.display()
+            );
+            return Ok(Handled::Skipped);
+        }
+    }
+
+    hide_path(path, Some(&reason), settings)
+}
+
+// Helper function to unhide a path with --reveal-all if it's hidden, whatever its name
+fn reveal_path(path: &Path, reveal: &Reveal, settings: &Settings) -> Result<Handled> {
+    if !is_hidden(path, settings)? {
+        return Ok(Handled::Skipped);
+    }
+
+    if let Reveal::Journaled(journaled) = reveal {
+        if !journaled.contains(path) {
+            debug!(
+                target: logger::SKIPPED,
+                "Skipping {}, which the journal doesn't record hiding",
+                path.display()
+            );
+            return Ok(Handled::Skipped);
+        }
+    }
+
+    hide_path(path, None, settings)
+}
+
+// Helper function to check whether a path was created at or after the given time, for --only-new.
+// The modification time is used where the creation time isn't available, and paths whose times
+// can't be read count as new, so hiding them reports the error.
+fn created_since(path: &Path, since: SystemTime) -> bool {
+    fs::symlink_metadata(path)
+        .and_then(|metadata| metadata.created().or_else(|_| metadata.modified()))
+        .map_or(true, |created| created >= since)
+}
+
+// Hide, unhide or toggle a path as --action says, or just print it if test mode or list mode is
+// enabled. The reason is the rule the path matched, or None if it's hidden without matching.
+fn hide_path(path: &Path, reason: Option<&MatchReason>, settings: &Settings) -> Result<Handled> {
+    // Never hide filehider's own files, which it still needs to find
+    if settings.is_own_file(path) {
+        debug!(target: logger::SKIPPED, "Skipping filehider's own file: {}", path.display());
+        return Ok(Handled::Skipped);
+    }
+
+    // Checked before hiding, since a renamed path is gone afterwards
+    let hidden = Handled::Hidden {
+        directory: path.is_dir(),
+    };
+
+    if settings.list {
+        match (&settings.counts, &settings.sorted) {
+            (Some(counts), _) => counts.add(reason),
+            (None, Some(sorted)) => sorted.push(path, None),
+            (None, None) => print_path(path, settings)?,
+        }
+        return Ok(hidden);
+    }
+
+    let planned = plan_action(path, settings.operation, settings)?;
+    if let PlannedAction::Skip { hidden } = planned {
+        settings.record_action(
+            path,
+            if hidden {
+                PathAction::AlreadyHidden
+            } else {
+                PathAction::AlreadyVisible
+            },
+            reason,
+        );
+        debug!(
+            target: logger::ALREADY_HIDDEN,
+            "Already {}: {}{}",
+            if hidden { "hidden" } else { "visible" },
+            path.display(),
+            target_label(path, settings)
+        );
+        return Ok(Handled::AlreadyHidden);
+    }
+
+    if settings.test_mode {
+        // With --emit-script, the command that would change the path is written instead
+        if let Some(script) = &settings.script {
+            if !script_path(path, planned == PlannedAction::Unhide, script, settings)? {
+                return Ok(Handled::Skipped);
+            }
+        }
+
+        settings.record_action(
+            path,
+            if planned == PlannedAction::Unhide {
+                PathAction::WouldUnhide
+            } else {
+                PathAction::WouldHide
+            },
+            reason,
+        );
+        if let Some(counts) = &settings.counts {
+            counts.add(reason);
+            return Ok(hidden);
+        }
+        let message = (!settings.print0).then(|| {
+            format!(
+                "{}{}: {}{}{}",
+                would_label(planned, settings.operation),
+                rescan_label(),
+                settings.display_path(path).display(),
+                target_label(path, settings),
+                reason_label(reason)
+            )
+        });
+        match (&settings.sorted, message) {
+            (Some(sorted), message) => sorted.push(path, message),
+            (None, Some(message)) => {
+                info!(target: logger::WOULD_HIDE, path:% = path.display(); "{}", message)
+            }
+            (None, None) => print_path(path, settings)?,
+        }
+        Ok(hidden)
+    } else {
+        let unhide = planned == PlannedAction::Unhide;
+        if settings.confirm.load(Ordering::Relaxed) && !confirm_hide(path, unhide, settings)? {
+            debug!(target: logger::SKIPPED, "Skipping declined path: {}", path.display());
+            return Ok(Handled::Skipped);
+        }
+
+        // Read before hiding, since the symlink is renamed
+        let points_to = target_label(path, settings);
+        let changed = if unhide {
+            unhide_file(path, settings)?
+        } else {
+            hide_file(path, settings)?
+        };
+        if changed {
+            settings.record_action(
+                path,
+                if unhide {
+                    PathAction::Unhidden
+                } else {
+                    PathAction::Hidden
+                },
+                reason,
+            );
+            info!(
+                target: logger::HIDDEN,
+                path:% = path.display();
+                "{} file{}: {}{}{}",
+                if unhide { "Unhid" } else { "Hid" },
+                rescan_label(),
+                settings.display_path(path).display(),
+                points_to,
+                if log::log_enabled!(log::Level::Debug) {
+                    reason_label(reason)
+                } else {
+                    String::new()
+                }
+            );
+            if let Some(counts) = &settings.counts {
+                counts.add(reason);
+            }
+            #[cfg(feature = "notifications")]
+            if !unhide {
+                notifications::hidden(path);
+            }
+            if let Some(hook) = &settings.on_hide {
+                hooks::submit(hook, path, if unhide { "unhide" } else { "hide" });
+            }
+            if settings.print0 {
+                print_path(path, settings)?;
+            }
+        }
+        // Hiding can still find the path already hidden, or skip it because of a collision
+        Ok(if changed { hidden } else { Handled::Skipped })
+    }
+}
+
+// Plan what the operation does to a path, from whether it's hidden right now
+fn plan_action(path: &Path, op: Operation, settings: &Settings) -> Result<PlannedAction> {
+    let hidden = is_hidden(path, settings)?;
+    Ok(match (op, hidden) {
+        (Operation::Hide | Operation::Toggle, false) => PlannedAction::Hide,
+        (Operation::Unhide | Operation::Toggle, true) => PlannedAction::Unhide,
+        _ => PlannedAction::Skip { hidden },
+    })
+}
+
+// Helper function to describe a planned action in test mode
+fn would_label(planned: PlannedAction, op: Operation) -> &'static str {
+    match (op, planned) {
+        (Operation::Toggle, PlannedAction::Hide) => "Would toggle (currently visible -> hidden)",
+        (Operation::Toggle, _) => "Would toggle (currently hidden -> visible)",
+        (_, PlannedAction::Unhide) => "Would unhide file",
+        _ => "Would hide file",
+    }
+}
+
+// Helper function to label a path with the rule it matched
+fn reason_label(reason: Option<&MatchReason>) -> String {
+    reason.map_or(String::new(), |reason| format!(" ({})", reason))
+}
+
+// Helper function to label a symlink with where it points, with --show-targets (e.g.
+// " -> ../a.tmp"). The target is shown as it's stored in the link, like "ls -l" does.
+fn target_label(path: &Path, settings: &Settings) -> String {
+    if !settings.show_targets {
+        return String::new();
+    }
+    fs::read_link(path).map_or(String::new(), |target| format!(" -> {}", target.display()))
+}
+
+// Helper function to label the paths hidden by a --rescan sweep
+fn rescan_label() -> &'static str {
+    if EVENT.get() == EventKind::Rescan {
+        " (rescan)"
+    } else {
+        ""
+    }
+}
+
+// Ask the user whether to hide (or unhide) a path. Returns whether it should be changed. Answering
+// "a" turns off confirmation for the rest of the run. Answering "q", or closing stdin, declines the
+// path and stops immediate mode at the next one, so it still prints its summary.
+fn confirm_hide(path: &Path, unhide: bool, settings: &Settings) -> Result<bool> {
+    use std::io::{BufRead, Write};
+
+    loop {
+        eprint!(
+            "{} {}? [y]es/[n]o/[a]ll/[q]uit: ",
+            if unhide { "Unhide" } else { "Hide" },
+            path.display()
+        );
+        std::io::stderr().flush()?;
+
+        let mut answer = String::new();
+        if std::io::stdin()
+            .lock()
+            .read_line(&mut answer)
+            .with_context(|| "Failed to read confirmation from stdin")?
+            == 0
+        {
+            // Stdin was closed, so there's no way to confirm anything else
+            eprintln!();
+            settings.quit.store(true, Ordering::Relaxed);
+            return Ok(false);
+        }
+
+        match answer.trim().to_lowercase().as_str() {
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            "a" | "all" => {
+                settings.confirm.store(false, Ordering::Relaxed);
+                return Ok(true);
+            }
+            "q" | "quit" => {
+                settings.quit.store(true, Ordering::Relaxed);
+                return Ok(false);
+            }
+            _ => continue,
+        }
+    }
+}
+
+// Print a bare path to stdout, terminated by a NUL byte with --print0 or a newline otherwise.
+// Flushed immediately so that output is usable while watch mode is still running.
+fn print_path(path: &Path, settings: &Settings) -> Result<()> {
+    use std::io::Write;
+
+    let terminator: &[u8] = if settings.print0 { b"\0" } else { b"\n" };
+    let path = settings.display_path(path);
+
+    let mut stdout = std::io::stdout().lock();
+    stdout
+        .write_all(&path_to_bytes(&path))
+        .and_then(|_| stdout.write_all(terminator))
+        .and_then(|_| stdout.flush())
+        .with_context(|| format!("Failed to print path {}", path.display()))
+}
+
+// Windows only function to hide a file by setting its attributes. Returns false if the file already
+// had all of the attributes.
+#[cfg(windows)]
+fn hide_file(path: &Path, settings: &Settings) -> Result<bool> {
+    use std::{fs::metadata, io::Error, os::windows::fs::MetadataExt};
+
+    use winapi::{
+        shared::minwindef::FALSE,
+        um::{fileapi::SetFileAttributesW, winnt::FILE_ATTRIBUTE_NORMAL},
+    };
+
+    // Get the current file attributes
+    let attributes = metadata(path)
+        .with_context(|| format!("Failed to get file attributes for path {}", path.display()))?
+        .file_attributes();
+
+    let os_path = wide_path(path)?;
+
+    // Check if the file is already hidden
+    let mask = settings.windows_attributes;
+    if attributes & mask == mask {
+        debug!(target: logger::ALREADY_HIDDEN, "Already hidden: {}", path.display());
+        Ok(false)
+    } else {
+        // Hide the file, keeping any other attributes it already has. FILE_ATTRIBUTE_NORMAL is only
+        // valid on its own, so it has to be cleared when adding other attributes.
+        let result = unsafe {
+            SetFileAttributesW(
+                os_path.as_ptr(),
+                (attributes & !FILE_ATTRIBUTE_NORMAL) | mask,
+            )
+        };
+
+        // Check if the file was hidden successfully
+        if result == FALSE {
+            Err::<bool, anyhow::Error>(Error::last_os_error().into())
+                .with_context(|| format!("Failed to hide path {}", path.display()))
+        } else {
+            settings.record_change(path, Change::Attributes { previous: attributes });
+            Ok(true)
+        }
+    }
+}
+
+// Windows only function to unhide a file by clearing the attributes set when hiding it. Returns false
+// if the file had none of them.
+#[cfg(windows)]
+fn unhide_file(path: &Path, settings: &Settings) -> Result<bool> {
+    use std::{fs::metadata, io::Error, os::windows::fs::MetadataExt};
+
+    use winapi::{
+        shared::minwindef::FALSE,
+        um::{fileapi::SetFileAttributesW, winnt::FILE_ATTRIBUTE_NORMAL},
+    };
+
+    let attributes = metadata(path)
+        .with_context(|| format!("Failed to get file attributes for path {}", path.display()))?
+        .file_attributes();
+
+    let mask = settings.windows_attributes;
+    if attributes & mask == 0 {
+        debug!(target: logger::ALREADY_HIDDEN, "Already visible: {}", path.display());
+        return Ok(false);
+    }
+
+    // A file left with no attributes at all has to be set to FILE_ATTRIBUTE_NORMAL instead
+    let remaining = match attributes & !mask {
+        0 => FILE_ATTRIBUTE_NORMAL,
+        remaining => remaining,
+    };
+
+    if unsafe { SetFileAttributesW(wide_path(path)?.as_ptr(), remaining) } == FALSE {
+        return Err::<bool, anyhow::Error>(Error::last_os_error().into())
+            .with_context(|| format!("Failed to unhide path {}", path.display()));
+    }
+
+    settings.record_change(path, Change::Attributes { previous: attributes });
+    Ok(true)
+}
+
+// Windows only helper function to get the file an NTFS alternate data stream belongs to (e.g.
+// "C:\dir\file.txt" for "C:\dir\file.txt:stream:$DATA"). The drive letter's colon is part of the
+// path prefix, so only a colon in the file name marks a stream. Returns None for other paths.
+#[cfg(windows)]
+fn stream_owner(path: &Path) -> Option<PathBuf> {
+    use std::path::Component;
+
+    let name = match path.components().next_back()? {
+        Component::Normal(name) => name.to_str()?,
+        _ => return None,
+    };
+    let (file, stream) = name.split_once(':').filter(|(file, _)| !file.is_empty())?;
+    debug!("Handling stream {} of {} as the file itself", stream, path.display());
+    Some(path.with_file_name(file))
+}
+
+// Windows only helper function to convert a path to a wide string for the Windows API. Absolute
+// paths longer than MAX_PATH get the extended-length prefix ("\\?\" or "\\?\UNC\" for network
+// shares), since the API fails on them otherwise.
+#[cfg(windows)]
+fn wide_path(path: &Path) -> Result<Vec<u16>> {
+    use std::{ffi::OsStr, os::windows::ffi::OsStrExt};
+
+    use winapi::shared::minwindef::MAX_PATH;
+
+    let path = path.to_str().with_context(|| {
+        format!(
+            "Failed to convert path to string for path {}",
+            path.display()
+        )
+    })?;
+
+    // The limit includes the terminating null. Extended-length paths aren't normalized by the API,
+    // so forward slashes have to be replaced as well.
+    let path = if OsStr::new(path).encode_wide().count() >= MAX_PATH
+        && Path::new(path).is_absolute()
+        && !path.starts_with(r"\\?\")
+    {
+        let path = path.replace('/', r"\");
+        match path.strip_prefix(r"\\") {
+            Some(share) => format!(r"\\?\UNC\{}", share),
+            None => format!(r"\\?\{}", path),
+        }
+    } else {
+        path.to_string()
+    };
+
+    Ok(OsStr::new(&path).encode_wide().chain(Some(0)).collect())
+}
+
+// Much simpler function for non-Windows platforms... just adds a dot to the beginning of the file
+// name if it doesn't already have one. Returns false if the file was already hidden or skipped.
+#[cfg(not(windows))]
+fn hide_file(path: &Path, settings: &Settings) -> Result<bool> {
+    // Directories can be hidden from Finder without renaming them on macOS
+    #[cfg(target_os = "macos")]
+    if settings.macos_finder_hidden
+        && fs::symlink_metadata(path)
+            .with_context(|| format!("Failed to get metadata for path {}", path.display()))?
+            .is_dir()
+    {
+        return finder_set_hidden(path, true, settings);
+    }
+
+    let Some(new_path) = hidden_path(path, settings)? else {
+        return Ok(false);
+    };
+
+    // Rename the file
+    fs::rename(path, &new_path)
+        .with_context(|| format!("Failed to rename path {}", path.display()))?;
+
+    settings.record_change(path, Change::Rename { renamed_to: new_path });
+    Ok(true)
+}
+
+// Helper function to find the path hiding a path renames it to, by adding the dot to the beginning
+// of its file name. Returns None if it's already hidden, or skipped because of a collision.
+#[cfg(not(windows))]
+fn hidden_path(path: &Path, settings: &Settings) -> Result<Option<PathBuf>> {
+    // Get the file name
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("Failed to get file name from path {}", path.display()))?
+        .to_str()
+        .with_context(|| {
+            format!(
+                "Failed to convert file name to string in path {}",
+                path.display()
+            )
+        })?;
+
+    // Check if the file is already hidden
+    let prefix = settings.hidden_prefix.as_str();
+    if file_name.starts_with(prefix) {
+        debug!(target: logger::ALREADY_HIDDEN, "Already hidden: {}", path.display());
+        return Ok(None);
+    }
+
+    // Get the parent directory
+    let parent = path
+        .parent()
+        .with_context(|| format!("Failed to get parent directory of path {}", path.display()))?;
+
+    // Get the new file name
+    let new_path = parent.join(format!("{}{}", prefix, file_name));
+
+    // Make sure the rename won't overwrite an existing path. Use symlink_metadata so that a
+    // dangling symlink still counts as existing.
+    if fs::symlink_metadata(&new_path).is_ok() {
+        return match settings.on_collision {
+            OnCollision::Error => Err(anyhow!(
+                "Failed to hide path {}: {} already exists!",
+                path.display(),
+                new_path.display()
+            )),
+            OnCollision::Skip => {
+                warn!(
+                    "Skipping path {}: {} already exists",
+                    path.display(),
+                    new_path.display()
+                );
+                Ok(None)
+            }
+            OnCollision::Rename => Ok(Some(parent.join(free_name(parent, file_name, prefix)))),
+        };
+    }
+
+    Ok(Some(new_path))
+}
+
+// Inverse of hide_file for non-Windows platforms, removing the dot from the beginning of the file
+// name. Returns false if the file was already visible or skipped.
+#[cfg(not(windows))]
+fn unhide_file(path: &Path, settings: &Settings) -> Result<bool> {
+    #[cfg(target_os = "macos")]
+    if settings.macos_finder_hidden
+        && fs::symlink_metadata(path)
+            .with_context(|| format!("Failed to get metadata for path {}", path.display()))?
+            .is_dir()
+    {
+        return finder_set_hidden(path, false, settings);
+    }
+
+    let Some(new_path) = visible_path(path, settings)? else {
+        return Ok(false);
+    };
+
+    fs::rename(path, &new_path)
+        .with_context(|| format!("Failed to rename path {}", path.display()))?;
+
+    settings.record_change(path, Change::Rename { renamed_to: new_path });
+    Ok(true)
+}
+
+// Helper function to find the path unhiding a path renames it to, by removing the dot from the
+// beginning of its file name. Returns None if it's already visible, or skipped because of a
+// collision.
+#[cfg(not(windows))]
+fn visible_path(path: &Path, settings: &Settings) -> Result<Option<PathBuf>> {
+    let file_name = path
+        .file_name()
+        .and_then(OsStr::to_str)
+        .with_context(|| format!("Failed to get file name from path {}", path.display()))?;
+
+    let Some(visible_name) = file_name
+        .strip_prefix(settings.hidden_prefix.as_str())
+        .filter(|name| !name.is_empty())
+    else {
+        debug!(target: logger::ALREADY_HIDDEN, "Already visible: {}", path.display());
+        return Ok(None);
+    };
+
+    let parent = path
+        .parent()
+        .with_context(|| format!("Failed to get parent directory of path {}", path.display()))?;
+    let new_path = parent.join(visible_name);
+
+    // Make sure the rename won't overwrite an existing path, the same as when hiding
+    if fs::symlink_metadata(&new_path).is_ok() {
+        return match settings.on_collision {
+            OnCollision::Error => Err(anyhow!(
+                "Failed to unhide path {}: {} already exists!",
+                path.display(),
+                new_path.display()
+            )),
+            OnCollision::Skip => {
+                warn!(
+                    "Skipping path {}: {} already exists",
+                    path.display(),
+                    new_path.display()
+                );
+                Ok(None)
+            }
+            OnCollision::Rename => Ok(Some(parent.join(free_name(parent, visible_name, "")))),
+        };
+    }
+
+    Ok(Some(new_path))
+}
+
+// Write the command that would hide (or unhide) a path to the script, with --emit-script, instead
+// of changing it. Returns false if the path is skipped, the same as hide_file.
+#[cfg(not(windows))]
+fn script_path(path: &Path, unhide: bool, script: &Script, settings: &Settings) -> Result<bool> {
+    #[cfg(target_os = "macos")]
+    if settings.macos_finder_hidden
+        && fs::symlink_metadata(path)
+            .with_context(|| format!("Failed to get metadata for path {}", path.display()))?
+            .is_dir()
+    {
+        script.finder_hidden(path, !unhide)?;
+        return Ok(true);
+    }
+
+    let new_path = if unhide {
+        visible_path(path, settings)?
+    } else {
+        hidden_path(path, settings)?
+    };
+    match new_path {
+        Some(new_path) => script.rename(path, &new_path).map(|_| true),
+        None => Ok(false),
+    }
+}
+
+#[cfg(windows)]
+fn script_path(path: &Path, unhide: bool, script: &Script, settings: &Settings) -> Result<bool> {
+    script.attributes(path, settings.windows_attributes, !unhide)?;
+    Ok(true)
+}
+
+// Check if a path is hidden right now, the way hide_file would hide it
+#[cfg(windows)]
+fn is_hidden(path: &Path, settings: &Settings) -> Result<bool> {
+    use std::os::windows::fs::MetadataExt;
+
+    let attributes = fs::metadata(path)
+        .with_context(|| format!("Failed to get file attributes for path {}", path.display()))?
+        .file_attributes();
+    Ok(attributes & settings.windows_attributes == settings.windows_attributes)
+}
+
+#[cfg(not(windows))]
+fn is_hidden(path: &Path, settings: &Settings) -> Result<bool> {
+    #[cfg(target_os = "macos")]
+    if settings.macos_finder_hidden {
+        use std::os::macos::fs::MetadataExt;
+
+        let metadata = fs::symlink_metadata(path)
+            .with_context(|| format!("Failed to get metadata for path {}", path.display()))?;
+        if metadata.is_dir() {
+            return Ok(metadata.st_flags() & libc::UF_HIDDEN != 0);
+        }
+    }
+    Ok(path
+        .file_name()
+        .and_then(OsStr::to_str)
+        .is_some_and(|name| name.starts_with(settings.hidden_prefix.as_str())))
+}
+
+// macOS only function to hide a directory from Finder by setting its UF_HIDDEN flag, or to show it
+// again by clearing the flag, which leaves its name alone. Returns false if the flag was already as
+// wanted.
+#[cfg(target_os = "macos")]
+fn finder_set_hidden(path: &Path, hidden: bool, settings: &Settings) -> Result<bool> {
+    use std::{
+        ffi::CString,
+        io::Error,
+        os::{macos::fs::MetadataExt, unix::ffi::OsStrExt},
+        time::SystemTime,
+    };
+
+    // Get the current flags
+    let flags = fs::metadata(path)
+        .with_context(|| format!("Failed to get flags for path {}", path.display()))?
+        .st_flags();
+
+    // Check if the directory is already hidden (or visible)
+    if (flags & libc::UF_HIDDEN != 0) == hidden {
+        debug!(
+            target: logger::ALREADY_HIDDEN,
+            "Already {}: {}",
+            if hidden { "hidden" } else { "visible" },
+            path.display()
+        );
+        return Ok(false);
+    }
+
+    // Convert the path to a C string for chflags
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .with_context(|| {
+            format!(
+                "Failed to convert path to C string for path {}",
+                path.display()
+            )
+        })?;
+
+    // Set or clear the flag, keeping any other flags it already has
+    let new_flags = if hidden {
+        flags | libc::UF_HIDDEN
+    } else {
+        flags & !libc::UF_HIDDEN
+    };
+    if unsafe { libc::chflags(c_path.as_ptr(), new_flags) } != 0 {
+        return Err::<bool, anyhow::Error>(Error::last_os_error().into()).with_context(|| {
+            format!(
+                "Failed to {} path {}",
+                if hidden { "hide" } else { "unhide" },
+                path.display()
+            )
+        });
+    }
+
+    settings.record_change(path, Change::Flags { previous: flags });
+
+    // Finder doesn't always notice a changed flag until the directory containing it changes, so
+    // touch the parent as well. The directory is hidden either way, so this is only a warning.
+    if let Some(parent) = path.parent() {
+        if let Err(e) = fs::File::open(parent).and_then(|dir| dir.set_modified(SystemTime::now())) {
+            warn!(
+                "Failed to update the modification time of {}: {}",
+                parent.display(),
+                e
+            );
+        }
+    }
+
+    Ok(true)
+}
+
+// Helper function to find a name that doesn't exist yet in the parent directory by appending a
+// numeric suffix before the extension, after the given prefix (e.g. "foo.txt" with the prefix "."
+// becomes ".foo (1).txt")
+#[cfg(not(windows))]
+fn free_name(parent: &Path, file_name: &str, prefix: &str) -> String {
+    let (stem, extension) = match file_name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{}", extension)),
+        _ => (file_name, String::new()),
+    };
+
+    (1..)
+        .map(|n| format!("{}{} ({}){}", prefix, stem, n, extension))
+        .find(|name| fs::symlink_metadata(parent.join(name)).is_err())
+        .unwrap()
+}
+
+// Helper function to build the directory list, file list, and the settings from the configuration
+fn setup(config: Config) -> Result<(HashSet<PathBuf>, HashSet<PathBuf>, Settings)> {
+    let case_sensitive = config.case_sensitive;
+    // Hidden names can only be unhidden by their name without the prefix on non-Windows platforms
+    #[cfg(not(windows))]
+    let visible_prefix =
+        (config.action != Operation::Hide).then(|| config.hidden_prefix.clone());
+    #[cfg(windows)]
+    let visible_prefix = None;
+
+    // The rules git ignores paths by are shared by every section, and read again on reload
+    let gitignore = config.gitignore.then(|| Arc::new(GitIgnored::new()));
+    // Partition the given paths into the set of directories to watch and the set of files to hide
+    // directly, validating that they exist. Return an error if they don't exist, or if a file is
+    // given in watch mode since a plain file can't be watched. Paths are canonicalized so that
+    // output from immediate mode and event paths from watch mode are consistent.
+    let mut directories: HashSet<PathBuf> = HashSet::new();
+    let mut files: HashSet<PathBuf> = HashSet::new();
+
+    for path in &config.directories {
+        for target in resolve_targets(path, config.watch)? {
+            match target {
+                Target::Directory(path) => directories.insert(path),
+                Target::File(path) => files.insert(path),
+            };
+        }
+    }
+
+    check_depths(config.min_depth, config.max_depth)?;
+    if let Some(e) = pattern_errors(&config).into_iter().next() {
+        return Err(e);
+    }
+
+    let exclude_dirs = config
+        .exclude_dirs
+        .iter()
+        .map(|pattern| exclude_dir_pattern(pattern))
+        .collect::<Result<Vec<glob::Pattern>>>()?;
+
+    // Record the device of each directory so paths on other file systems can be skipped
+    let root_devices = if config.one_file_system {
+        directories
+            .iter()
+            .map(|directory| Ok((directory.clone(), device_id(directory)?)))
+            .collect::<Result<HashMap<PathBuf, u64>>>()?
+    } else {
+        HashMap::new()
+    };
+
+    // Create the set of file names to hide, normalized the same way as the names they're compared
+    // against in should_hide_file
+    let file_names: HashSet<String> = split_patterns(&config.file_names)
+        .map(|file_name| normalize(file_name, case_sensitive).into_owned())
+        .collect();
+
+    // Create the set of file extensions to hide. Extensions are compared without their dot, so
+    // ".txt" is the same as "txt".
+    let file_extensions: HashSet<String> = split_patterns(&config.file_extensions)
+        .map(|extension| normalize(bare_extension(extension), case_sensitive).into_owned())
+        .collect();
+
+    // Create the sets of directory names and extensions to hide, the same way. Directories are
+    // matched by the file names when no directory names are given.
+    let dir_names: Option<HashSet<String>> = (!config.dir_names.is_empty()).then(|| {
+        split_patterns(&config.dir_names)
+            .map(|dir_name| normalize(dir_name, case_sensitive).into_owned())
+            .collect()
+    });
+    let dir_extensions: HashSet<String> = split_patterns(&config.dir_extensions)
+        .map(|extension| normalize(bare_extension(extension), case_sensitive).into_owned())
+        .collect();
+
+    let path_regex = path_regex(&config)?;
+
+    // Resolve the owner and permission filters
+    #[cfg(unix)]
+    let owner = config.owner.as_deref().map(parse_owner).transpose()?;
+    #[cfg(unix)]
+    let mode = config.mode.as_deref().map(parse_mode).transpose()?;
 
-    // Create the set of directories to watch, validating that they exist and are directories. Return
-    // an error if they don't exist or aren't directories.
-    let (directories, file_names, file_extensions) = setup(
-        args.directories,
-        args.file_names,
-        args.file_extensions,
-        args.case_sensitive,
-    )?;
+    // Build the matcher for each section of the config file. Sections add to the global file names
+    // and extensions unless they don't inherit them. The global directory names and extensions
+    // apply to sections that inherit them, and other sections match directories by their names.
+    let sections = config
+        .sections
+        .iter()
+        .map(|section| {
+            let path = resolve_section_path(&section.path)?;
 
-    // Set up the rest of the configuration
-    let recursive = args.recursive;
-    let case_sensitive = args.case_sensitive;
-    let hide_files = args.file_types.contains(&FileType::File);
-    let hide_directories = args.file_types.contains(&FileType::Directory);
-    let test_mode = args.test_mode;
+            let (mut names, mut extensions, section_dir_names, section_dir_extensions) =
+                if section.inherit {
+                    (
+                        file_names.clone(),
+                        file_extensions.clone(),
+                        dir_names.clone(),
+                        dir_extensions.clone(),
+                    )
+                } else {
+                    (HashSet::new(), HashSet::new(), None, HashSet::new())
+                };
+            names.extend(
+                split_patterns(&section.names)
+                    .map(|name| normalize(name, case_sensitive).into_owned()),
+            );
+            extensions.extend(
+                split_patterns(&section.extensions).map(|extension| {
+                    normalize(bare_extension(extension), case_sensitive).into_owned()
+                }),
+            );
 
-    // If test mode is enabled, then print a message saying that test mode is enabled and no files
-    // will be hidden.
-    if test_mode {
-        println!("Test mode enabled. No files will be hidden.");
-    }
+            let file_types = section.file_types.as_ref().unwrap_or(&config.file_types);
 
-    // Print an error message if both watch mode and immediate mode are disabled.
-    if !args.watch && args.immediate {
-        return Err(anyhow!("Both watch mode and immediate mode are disabled. At least one of these modes must be enabled."));
-    }
+            Ok(Section {
+                path,
+                recursive: section.recursive.unwrap_or(config.recursive),
+                matcher: Matcher {
+                    file_names: names,
+                    file_extensions: extensions,
+                    dir_names: section_dir_names,
+                    dir_extensions: section_dir_extensions,
+                    match_dir_extensions: config.match_dir_extensions,
+                    path_regex: path_regex.clone(),
+                    case_sensitive,
+                    match_stem: config.match_stem,
+                    visible_prefix: visible_prefix.clone(),
+                    gitignore: gitignore.clone(),
+                    hide_files: file_types.contains(&FileType::File),
+                    hide_directories: file_types.contains(&FileType::Directory),
+                    #[cfg(unix)]
+                    owner,
+                    #[cfg(unix)]
+                    mode,
+                },
+            })
+        })
+        .collect::<Result<Vec<Section>>>()?;
 
-    // If immediate mode is enabled, then immediately hide all files and directories that match the
-    // given file names and extensions.
-    if !args.immediate {
-        if test_mode {
-            println!("Running immediate mode...");
+    let journal_path = config.journal.clone();
+
+    // Collect filehider's own files so they're never hidden. The IPC socket, the report, and the
+    // rotated log files may not exist yet.
+    let own_files: Vec<PathBuf> = config
+        .path
+        .iter()
+        .cloned()
+        .chain(config.ipc.as_deref().and_then(|path| canonicalize_file(path).ok()))
+        .chain(config.summary_json.as_deref().and_then(|path| canonicalize_file(path).ok()))
+        .chain(config.emit_script.as_deref().and_then(|path| canonicalize_file(path).ok()))
+        .chain(
+            journal_path
+                .as_deref()
+                .or(journal::default_path().as_deref())
+                .and_then(|path| canonicalize_file(path).ok()),
+        )
+        .chain(config.log_file.iter().flat_map(|path| {
+            std::iter::once(path.clone())
+                .chain((1..=config.log_keep).map(|index| logger::rotated_path(path, index)))
+                .filter_map(|path| canonicalize_file(&path).ok())
+        }))
+        .collect();
+
+    // Drop directories inside another recursive directory, since everything in them is already
+    // handled by the outer one. Directories were canonicalized, so symlinked aliases of the same
+    // directory were already merged into one.
+    let recursive_for = |directory: &Path| {
+        sections
+            .iter()
+            .find(|section| section.path == directory)
+            .map_or(config.recursive, |section| section.recursive)
+    };
+    let nested: Vec<(PathBuf, PathBuf)> = directories
+        .iter()
+        .filter_map(|directory| {
+            directories
+                .iter()
+                .find(|outer| {
+                    *outer != directory && directory.starts_with(outer) && recursive_for(outer)
+                })
+                .map(|outer| (directory.clone(), outer.clone()))
+        })
+        .collect();
+    for (directory, outer) in nested {
+        match config.on_overlap {
+            OnOverlap::Merge => warn!(
+                "Skipping directory {}, which is inside directory {} that's already \
+                handled recursively",
+                directory.display(),
+                outer.display()
+            ),
+            OnOverlap::Error => {
+                return Err(anyhow!(
+                    "Directory {} is inside directory {}, which is already handled recursively!",
+                    directory.display(),
+                    outer.display()
+                ))
+            }
         }
-        immediate_mode(
-            &directories,
-            &file_names,
-            &file_extensions,
-            recursive,
-            case_sensitive,
-            hide_files,
-            hide_directories,
-            test_mode,
-        );
+        directories.remove(&directory);
     }
 
-    // If watch mode is enabled, then watch for changes to the files and directories and automatically
-    // hide them.
-    if args.watch {
-        if test_mode {
-            println!("Running watch mode...");
-        }
-        watch_mode(
-            &directories,
-            &file_names,
-            &file_extensions,
-            recursive,
-            case_sensitive,
-            hide_files,
-            hide_directories,
-            test_mode,
-        )
-    } else {
-        Ok(())
+    let matcher = Matcher {
+        file_names,
+        file_extensions,
+        dir_names,
+        dir_extensions,
+        match_dir_extensions: config.match_dir_extensions,
+        path_regex,
+        case_sensitive,
+        match_stem: config.match_stem,
+        visible_prefix,
+        gitignore,
+        hide_files: config.file_types.contains(&FileType::File),
+        hide_directories: config.file_types.contains(&FileType::Directory),
+        #[cfg(unix)]
+        owner,
+        #[cfg(unix)]
+        mode,
+    };
+
+    // Find the directories where every path would be hidden because no file names or extensions
+    // apply to them. The global rules apply to the directories outside of every section.
+    let mut unrestricted: Vec<&Path> = Vec::new();
+    if matcher.matches_everything() {
+        unrestricted.extend(
+            directories
+                .iter()
+                .filter(|directory| {
+                    !sections
+                        .iter()
+                        .any(|section| directory.starts_with(&section.path))
+                })
+                .map(PathBuf::as_path),
+        );
     }
-}
+    unrestricted.extend(
+        sections
+            .iter()
+            .filter(|section| section.matcher.matches_everything())
+            .map(|section| section.path.as_path()),
+    );
 
-// Immediate mode function
-fn immediate_mode(
-    directories: &HashSet<PathBuf>,
-    file_names: &HashSet<String>,
-    file_extensions: &HashSet<String>,
-    recursive: bool,
-    case_sensitive: bool,
-    hide_files: bool,
-    hide_directories: bool,
-    test_mode: bool,
-) {
-    use walkdir::WalkDir;
+    // Hiding everything is almost always a mistake, so it has to be asked for explicitly. The
+    // filters aren't used with --reveal-all.
+    if !unrestricted.is_empty() && !config.reveal_all {
+        let unrestricted = unrestricted
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<String>>()
+            .join(", ");
 
-    // Small helper function to get a path from an entry result. Used to have consistent error
-    // messages.
-    fn get_path(entry: &walkdir::Result<walkdir::DirEntry>) -> Option<PathBuf> {
-        match entry {
-            Ok(entry) => Some(entry.path().to_path_buf()),
-            Err(e) => e.path().map(|p| p.to_path_buf()),
+        if config.test_mode || config.list || config.all || config.force {
+            warn!(
+                "No file names or extensions given for {}, so every path in them matches!",
+                unrestricted
+            );
+        } else {
+            return Err(anyhow!(
+                "No file names or extensions given for {}, so every path in them would be hidden! \
+                Pass --all to hide everything anyway, or --test to see what would be hidden.",
+                unrestricted
+            ));
         }
     }
 
-    for directory in directories {
-        for entry in if recursive {
-            WalkDir::new(directory)
-        } else {
-            WalkDir::new(directory).min_depth(1).max_depth(1)
-        } {
-            let path = get_path(&entry);
+    let roots = directories.iter().cloned().collect();
 
-            if entry.is_err() {
-                let entry = entry.with_context(|| {
-                    if let Some(path) = path {
-                        format!("Failed to get path from entry: {}", path.display())
-                    } else {
-                        "Failed to get path from entry".to_string()
-                    }
-                });
+    // Read what the journal records hiding for --reveal-all, before this run adds to it
+    let reveal = if !config.reveal_all {
+        None
+    } else if config.force {
+        Some(Reveal::Everything)
+    } else {
+        let path = journal_path
+            .clone()
+            .or_else(journal::default_path)
+            .with_context(|| "Failed to find the journal, pass it with --journal!")?;
+        // Paths are only renamed to hide them off Windows, so the prefix isn't needed there
+        #[cfg(not(windows))]
+        let hidden_prefix = config.hidden_prefix.as_str();
+        #[cfg(windows)]
+        let hidden_prefix = ".";
+        Some(Reveal::Journaled(journal::hidden_paths(&path, hidden_prefix)?))
+    };
 
-                eprintln!("{}", entry.unwrap_err());
-                continue;
+    Ok((
+        directories,
+        files,
+        Settings {
+            matcher,
+            sections,
+            recursive: config.recursive,
+            include_root: config.include_root,
+            min_depth: config.min_depth.map(|depth| depth as usize),
+            max_depth: config.max_depth.map(|depth| depth as usize),
+            follow_symlinks: config.follow_symlinks,
+            one_file_system: config.one_file_system,
+            root_devices,
+            exclude_dirs,
+            test_mode: config.test_mode,
+            list: config.list,
+            match_files: config.match_files,
+            operation: config.action,
+            on_collision: config.on_collision,
+            #[cfg(windows)]
+            windows_attributes: windows_attributes(&config.windows_attrs),
+            #[cfg(target_os = "macos")]
+            macos_finder_hidden: config.macos_finder_hidden,
+            #[cfg(not(windows))]
+            hidden_prefix: config.hidden_prefix,
+            contents_first: match config.order {
+                WalkOrder::Auto => cfg!(not(windows)),
+                WalkOrder::ParentsFirst => false,
+                WalkOrder::ContentsFirst => true,
+            },
+            walk_sort: config.sort_by,
+            print0: config.print0,
+            sorted: config.sort.then(SortedPaths::default),
+            counts: config
+                .count
+                .then(|| RuleCounts::new(config.format != OutputFormat::Human)),
+            path_style: if config.absolute_paths {
+                PathStyle::Absolute
+            } else if config.relative_paths {
+                PathStyle::Relative
             } else {
-                if let Err(e) = handle_path(
-                    &path.unwrap(),
-                    &file_names,
-                    &file_extensions,
-                    case_sensitive,
-                    hide_files,
-                    hide_directories,
-                    test_mode,
-                ) {
-                    eprintln!("{}", e);
-                }
-            }
-        }
-    }
+                config.path_style
+            },
+            show_targets: config.show_targets,
+            roots,
+            only_new_since: config.only_new.then(SystemTime::now),
+            heartbeat: config.heartbeat_secs.map(Duration::from_secs),
+            duration: config.duration,
+            rescan: config.rescan,
+            stats_interval: config.stats_interval,
+            active_between: config.active_between,
+            #[cfg(feature = "notifications")]
+            notify: config.notify.then_some(config.notify_window),
+            watch_threads: config.threads_watch.map(|threads| threads as usize),
+            event_buffer: config.event_buffer as usize,
+            backend: config.backend,
+            poll_interval: config.poll_interval,
+            best_effort: config.best_effort,
+            debounce: (!config.debounce.is_zero()).then_some(config.debounce),
+            settle: config.settle.filter(|settle| !settle.is_zero()),
+            settle_max: config.settle_max,
+            ipc_path: config.ipc,
+            on_hide: config
+                .on_hide
+                .map(|command| Arc::new(Hook::new(command, config.on_hide_timeout))),
+            match_command: config
+                .match_command
+                .map(|command| MatchCommand::new(command, config.match_command_timeout)),
+            reveal,
+            // Only real hides are throttled
+            hide_interval: config
+                .max_ops_per_sec
+                .filter(|_| !config.test_mode && !config.list)
+                .map(|ops| Duration::from_secs(1) / ops),
+            max_hides: config
+                .max_hides
+                .filter(|_| !config.force && !config.test_mode && !config.list)
+                .map(|max_hides| max_hides as usize),
+            fail_fast: config.fail_fast,
+            max_errors_total: config.max_errors_total.map(|max| max as usize),
+            config_path: config.path,
+            own_files,
+            journal: None,
+            script: None,
+            session: (config.restore_on_exit && !config.test_mode && !config.list)
+                .then(|| Arc::new(Session::default())),
+            // --count prints its own JSON instead
+            output: match config.format {
+                _ if config.count => None,
+                OutputFormat::Human => None,
+                OutputFormat::Json => Some(Arc::new(JsonOutput::new(false))),
+                OutputFormat::Ndjson => Some(Arc::new(JsonOutput::new(true))),
+            },
+            // Only hiding asks, so dry runs never do
+            confirm: AtomicBool::new(config.confirm && !config.test_mode && !config.list),
+            quit: AtomicBool::new(false),
+        },
+    ))
 }
 
-// Watch mode function
-fn watch_mode(
-    directories: &HashSet<PathBuf>,
-    file_names: &HashSet<String>,
-    file_extensions: &HashSet<String>,
-    recursive: bool,
-    case_sensitive: bool,
-    hide_files: bool,
-    hide_directories: bool,
-    test_mode: bool,
-) -> Result<()> {
-    use notify::{event, RecommendedWatcher, RecursiveMode, Watcher};
-    use std::sync::mpsc::channel;
+// Helper function to compile an --exclude-dir pattern
+fn exclude_dir_pattern(pattern: &str) -> Result<glob::Pattern> {
+    glob::Pattern::new(pattern)
+        .with_context(|| format!("Invalid --exclude-dir pattern {}!", pattern))
+}
 
-    // Open a channel to receive the events
-    let (tx, rx) = channel();
+// Helper function to compile the path regex, which follows the same case sensitivity as the names
+fn path_regex(config: &Config) -> Result<Option<Regex>> {
+    config
+        .path_regex
+        .as_deref()
+        .map(|pattern| {
+            RegexBuilder::new(pattern)
+                .case_insensitive(!config.case_sensitive)
+                .build()
+                .with_context(|| format!("Invalid --path-regex pattern {}!", pattern))
+        })
+        .transpose()
+}
 
-    // Create a watcher object, delivering raw events
-    let mut watcher: RecommendedWatcher =
-        Watcher::new(tx, notify::Config::default()).with_context(|| "Failed to create watcher!")?;
+// A path given as an argument, resolved to either a directory to watch or a file to hide directly
+enum Target {
+    Directory(PathBuf),
+    File(PathBuf),
+}
 
-    // Add the directories to watch
-    for directory in directories {
-        watcher
-            .watch(
-                directory.as_path(),
-                if recursive {
-                    RecursiveMode::Recursive
-                } else {
-                    RecursiveMode::NonRecursive
-                },
-            )
-            .with_context(|| "Failed to add directory to watch!")?;
+// Helper function to resolve a path given as an argument, expanding it if it's a glob pattern (e.g.
+// "D:\Projects\*\build") since not every shell does that. A path that exists is always taken
+// literally, even if it contains glob characters. A pattern has to match at least one path, and
+// only directories are used from its matches.
+fn resolve_targets(path: &str, watch: bool) -> Result<Vec<Target>> {
+    if !path.contains(['*', '?', '[']) || Path::new(path).exists() {
+        return Ok(vec![resolve_target(path, watch)?]);
     }
 
-    // Add a global error counter. If this counter reaches 20 errors within 5 seconds, then the
-    // program will exit.
-    let mut error_counter = 0;
-    let mut timer = std::time::Instant::now();
+    let matches = glob::glob(path).with_context(|| format!("Invalid glob pattern {}!", path))?;
 
-    loop {
-        let event = rx.recv().with_context(|| "Critical error in watcher!")?;
-
-        // Only handle creation events and renames.
-        match event {
-            Ok(event) if matches!(event.kind, event::EventKind::Create(_)) => {
-                // Path should exist, but to be safe, check if it does
-                if let Some(path) = event.paths.get(0) {
-                    if let Err(e) = handle_path(
-                        path,
-                        &file_names,
-                        &file_extensions,
-                        case_sensitive,
-                        hide_files,
-                        hide_directories,
-                        test_mode,
-                    ) {
-                        eprintln!("{}", e);
-                        error_counter += 1;
-                    }
-                } else {
-                    eprintln!("No path in event!");
-                    error_counter += 1;
-                }
-            }
-            Ok(event)
-            if matches!(
-                    event.kind,
-                    event::EventKind::Modify(event::ModifyKind::Name(_))
-                ) && !matches!(
-                    event.kind,
-                    event::EventKind::Modify(event::ModifyKind::Name(event::RenameMode::From))
-                ) =>
-                {
-                    // If the length of paths is 2 or more, then the first path is the old name and the
-                    // second path is the new name. If the length is 1, then the path is the new name.
-                    if let Some(path) = event.paths.get(1) {
-                        if let Err(e) = handle_path(
-                            path,
-                            &file_names,
-                            &file_extensions,
-                            case_sensitive,
-                            hide_files,
-                            hide_directories,
-                            test_mode,
-                        ) {
-                            eprintln!("{}", e);
-                            error_counter += 1;
-                        }
-                    } else if let Some(path) = event.paths.get(0) {
-                        if let Err(e) = handle_path(
-                            path,
-                            &file_names,
-                            &file_extensions,
-                            case_sensitive,
-                            hide_files,
-                            hide_directories,
-                            test_mode,
-                        ) {
-                            eprintln!("{}", e);
-                            error_counter += 1;
-                        }
-                    } else {
-                        eprintln!("No path in event!");
-                        error_counter += 1;
-                    }
-                }
-            Ok(_) => {}
+    let mut matched = false;
+    let mut targets = Vec::new();
+    for entry in matches {
+        let entry = match entry {
+            Ok(entry) => entry,
             Err(e) => {
-                eprintln!("Critical error in watcher: {}", e);
-                error_counter += 1;
+                warn!("Failed to expand glob pattern {}: {}", path, e);
+                continue;
             }
-        }
+        };
+        matched = true;
 
-        // If the error counter is too high, exit the program
-        if error_counter >= ERROR_LIMIT && timer.elapsed().as_secs() <= ERROR_TIME_LIMIT {
-            return Err(anyhow!(
-                "Too many errors in a short period of time. Exiting program."
-            ));
-        } else if timer.elapsed().as_secs() > 5 {
-            error_counter = 0;
-            timer = std::time::Instant::now();
+        if entry.is_dir() {
+            targets.push(Target::Directory(entry.canonicalize().with_context(|| {
+                format!("Failed to resolve absolute path of {}", entry.display())
+            })?));
+        } else {
+            warn!(
+                "Skipping {}, which matches {} but isn't a directory",
+                entry.display(),
+                path
+            );
         }
     }
+
+    if !matched {
+        return Err(anyhow!("Glob pattern {} didn't match any paths!", path));
+    }
+
+    Ok(targets)
 }
 
-// Process a path
-fn handle_path(
-    path: &Path,
-    file_names: &HashSet<String>,
-    file_extensions: &HashSet<String>,
-    case_sensitive: bool,
-    hide_files: bool,
-    hide_directories: bool,
-    test_mode: bool,
-) -> Result<()> {
-    if should_hide_file(
-        path,
-        &file_names,
-        &file_extensions,
-        case_sensitive,
-        hide_files,
-        hide_directories,
-    )? {
-        if test_mode {
-            println!("Would hide file: {}", path.display());
-            Ok(())
-        } else {
-            hide_file(path)
-        }
+// Helper function to validate a path given as an argument, resolving it to a directory or a file.
+// Return an error if it doesn't exist, or if it's a file in watch mode since a plain file can't be
+// watched. Paths are canonicalized so that output from immediate mode and event paths from watch
+// mode are consistent.
+fn resolve_target(path: &str, watch: bool) -> Result<Target> {
+    let path = Path::new(path);
+
+    // Check if the path exists. Use try_exists instead of exists to catch file system errors.
+    if !path
+        .try_exists()
+        .with_context(|| format!("Failed to check if path {} exists!", path.display()))?
+    {
+        return Err(anyhow!("Path {} does not exist!", path.display()));
+    }
+
+    if path.is_dir() {
+        Ok(Target::Directory(path.canonicalize().with_context(|| {
+            format!("Failed to resolve absolute path of {}", path.display())
+        })?))
+    } else if watch {
+        Err(anyhow!(
+            "Path {} is a file, and files aren't allowed in watch mode!",
+            path.display()
+        ))
     } else {
-        Ok(())
+        Ok(Target::File(canonicalize_file(path)?))
     }
 }
 
-// Windows only function to hide a file
-#[cfg(windows)]
-fn hide_file(path: &Path) -> Result<()> {
-    use std::{
-        ffi::OsStr,
-        fs::metadata,
-        io::Error,
-        os::windows::{ffi::OsStrExt, fs::MetadataExt},
+// Helper function to canonicalize the path of a file. Only the parent directory is canonicalized so
+// that a symlink is hidden itself rather than the file it points to, and so the file itself doesn't
+// have to exist.
+fn canonicalize_file(path: &Path) -> Result<PathBuf> {
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("Failed to get file name from path {}", path.display()))?;
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
     };
+    Ok(parent
+        .canonicalize()
+        .with_context(|| format!("Failed to resolve absolute path of {}", path.display()))?
+        .join(file_name))
+}
 
-    use winapi::{
-        shared::minwindef::FALSE,
-        um::{fileapi::SetFileAttributesW, winnt::FILE_ATTRIBUTE_HIDDEN},
-    };
+// Helper function to validate and canonicalize the path of a [[watch]] section
+fn resolve_section_path(path: &str) -> Result<PathBuf> {
+    let path = Path::new(path);
 
-    // Get the current file attributes
-    let attributes = metadata(path)
-        .with_context(|| format!("Failed to get file attributes for path {}", path.display()))?
-        .file_attributes();
+    if !path.is_dir() {
+        return Err(anyhow!(
+            "Path {} of [[watch]] section is not a directory!",
+            path.display()
+        ));
+    }
 
-    // Convert the path to a wide string for the Windows API
-    let os_path = OsStr::new(path.to_str().with_context(|| {
+    path.canonicalize().with_context(|| {
         format!(
-            "Failed to convert path to string for path {}",
+            "Failed to resolve absolute path of [[watch]] section {}",
             path.display()
         )
-    })?)
-        .encode_wide()
-        .chain(Some(0))
-        .collect::<Vec<_>>();
+    })
+}
 
-    // Check if the file is already hidden
-    if attributes & FILE_ATTRIBUTE_HIDDEN == FILE_ATTRIBUTE_HIDDEN {
-        Ok(())
-    } else {
-        // Hide the file
-        let result =
-            unsafe { SetFileAttributesW(os_path.as_ptr(), attributes | FILE_ATTRIBUTE_HIDDEN) };
+// Helper function to combine the attributes to set when hiding a file on Windows into a mask
+#[cfg(windows)]
+fn windows_attributes(attributes: &[WindowsAttribute]) -> u32 {
+    use winapi::um::winnt::{
+        FILE_ATTRIBUTE_HIDDEN, FILE_ATTRIBUTE_READONLY, FILE_ATTRIBUTE_SYSTEM,
+    };
 
-        // Check if the file was hidden successfully
-        if result == FALSE {
-            Err::<(), anyhow::Error>(Error::last_os_error().into())
-                .with_context(|| format!("Failed to hide path {}", path.display()))
-        } else {
-            Ok(())
+    attributes
+        .iter()
+        .map(|attribute| match attribute {
+            WindowsAttribute::Hidden => FILE_ATTRIBUTE_HIDDEN,
+            WindowsAttribute::System => FILE_ATTRIBUTE_SYSTEM,
+            WindowsAttribute::Readonly => FILE_ATTRIBUTE_READONLY,
+        })
+        .fold(0, |mask, attribute| mask | attribute)
+}
+
+// Helper function to parse an interval such as --rescan, either as a duration (e.g. "30m") or a
+// bare number of seconds
+fn parse_interval(interval: &str) -> Result<Duration, String> {
+    let interval = interval.trim();
+    match interval.parse::<u64>() {
+        Ok(0) => Err("interval must be more than 0 seconds".to_string()),
+        Ok(secs) => Ok(Duration::from_secs(secs)),
+        Err(_) => humantime::parse_duration(interval).map_err(|e| e.to_string()),
+    }
+}
+
+// Helper function to resolve the --owner filter to a uid, from either a user name or a uid
+#[cfg(unix)]
+fn parse_owner(owner: &str) -> Result<u32> {
+    match owner.parse::<u32>() {
+        Ok(uid) => Ok(uid),
+        Err(_) => uid_by_name(owner).with_context(|| format!("Unknown user {}!", owner)),
+    }
+}
+
+// Helper function to look up the uid of a user by name with getpwnam_r, growing the buffer for the
+// entry's strings until it fits
+#[cfg(unix)]
+fn uid_by_name(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name).ok()?;
+    let mut buffer: Vec<libc::c_char> = vec![0; 1024];
+    loop {
+        let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        let error = unsafe {
+            libc::getpwnam_r(
+                name.as_ptr(),
+                &mut entry,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            )
+        };
+        match error {
+            0 if !result.is_null() => return Some(entry.pw_uid),
+            libc::ERANGE if buffer.len() < 1 << 20 => buffer.resize(buffer.len() * 2, 0),
+            _ => return None,
         }
     }
 }
 
-// Much simpler function for non-Windows platforms... just adds a dot to the beginning of the file
-// name if it doesn't already have one
-#[cfg(not(windows))]
-fn hide_file(path: &Path) -> Result<()> {
-    // Get the file name
-    let file_name = path
-        .file_name()
-        .with_context(|| format!("Failed to get file name from path {}", path.display()))?
-        .to_str()
+// Helper function to parse the --mode filter from an octal mask
+#[cfg(unix)]
+fn parse_mode(mode: &str) -> Result<u32> {
+    u32::from_str_radix(mode.trim_start_matches("0o"), 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
         .with_context(|| {
             format!(
-                "Failed to convert file name to string in path {}",
-                path.display()
+                "Invalid permission mask {}! Expected an octal mask such as 644.",
+                mode
             )
-        })?;
+        })
+}
 
-    // Check if the file is already hidden
-    if file_name.starts_with('.') {
-        Ok(())
-    } else {
-        // Get the parent directory
-        let parent = path.parent().with_context(|| {
-            format!("Failed to get parent directory of path {}", path.display())
-        })?;
+// Helper function to get the id of the device a path is on, for --one-file-system
+#[cfg(unix)]
+fn device_id(path: &Path) -> Result<u64> {
+    use std::os::unix::fs::MetadataExt;
 
-        // Get the new file name
-        let new_file_name = format!(".{}", file_name);
+    Ok(fs::metadata(path)
+        .with_context(|| format!("Failed to get metadata for path {}", path.display()))?
+        .dev())
+}
 
-        // Rename the file
-        fs::rename(path, parent.join(new_file_name))
-            .with_context(|| format!("Failed to rename path {}", path.display()))?;
+// Helper function to get the serial number of the volume a path is on, for --one-file-system
+#[cfg(windows)]
+fn device_id(path: &Path) -> Result<u64> {
+    use std::os::windows::{fs::OpenOptionsExt, io::AsRawHandle};
+    use winapi::um::{
+        fileapi::{GetFileInformationByHandle, BY_HANDLE_FILE_INFORMATION},
+        winbase::FILE_FLAG_BACKUP_SEMANTICS,
+    };
 
-        Ok(())
+    // Directories can only be opened with FILE_FLAG_BACKUP_SEMANTICS. No access is requested,
+    // since only the file information is needed.
+    let file = fs::OpenOptions::new()
+        .access_mode(0)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+        .open(path)
+        .with_context(|| format!("Failed to open path {}", path.display()))?;
+
+    let mut info: BY_HANDLE_FILE_INFORMATION = unsafe { std::mem::zeroed() };
+    if unsafe { GetFileInformationByHandle(file.as_raw_handle() as _, &mut info) } == 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Failed to get volume of path {}", path.display()));
     }
+
+    Ok(u64::from(info.dwVolumeSerialNumber))
 }
 
-// Helper function to build the directory list, file name list, and file extension list
-fn setup(
-    directories: Vec<String>,
-    file_names: Vec<String>,
-    file_extensions: Vec<String>,
-    case_sensitive: bool,
-) -> Result<(HashSet<PathBuf>, HashSet<String>, HashSet<String>)> {
-    // Create the set of directories to watch, validating that they exist and are directories. Return
-    // an error if they don't exist or aren't directories.
-    let directories: HashSet<PathBuf> = directories
-        .into_iter()
-        .map(|directory| {
-            let path = Path::new(&directory).to_path_buf();
-
-            // Check if the path exists and is a directory. Use try_exists instead of exists to
-            // catch file system errors.
-            if path
-                .try_exists()
-                .with_context(|| format!("Failed to check if path {} exists!", path.display()))?
-            {
-                if path.is_dir() {
-                    Ok(path)
-                } else {
-                    Err(anyhow::anyhow!(
-                        "Path {} is not a directory!",
-                        path.display()
-                    ))
-                }
-            } else {
-                Err(anyhow::anyhow!("Path {} does not exist!", path.display()))
-            }
-        })
-        .collect::<Result<HashSet<PathBuf>>>()?;
+// Helper function to check if a file or directory should be hidden, and which rule matched it
+fn should_hide_file(path: &Path, matcher: &Matcher) -> Result<Option<MatchReason>> {
+    // Check the name before reading the metadata, which is much more expensive, since most paths in
+    // a busy directory don't match any of the file names or extensions. Any name can be ignored by
+    // git, so that check needs the metadata.
+    let patterns = matcher.has_patterns();
+    let unrestricted = !patterns && matcher.gitignore.is_none();
 
-    // Create the set of file names to hide
-    let file_names: HashSet<String> = file_names
-        .into_iter()
-        .map(|file_name| {
-            if case_sensitive {
-                file_name.to_lowercase()
-            } else {
-                file_name
-            }
-        })
-        .collect();
+    // The path regex applies on top of every other rule, and only needs the path
+    if let Some(path_regex) = &matcher.path_regex {
+        if !path_regex.is_match(&path.to_string_lossy()) {
+            trace!("No match, path regex: {}", path.display());
+            return Ok(None);
+        }
+    }
 
-    // Create the set of file extensions to hide. Don't need to add a dot to the beginning of the
-    // extension if it doesn't have one because the file name check will take care of that.
-    let file_extensions: HashSet<String> = file_extensions
-        .into_iter()
-        .map(|file_extension| {
-            if case_sensitive {
-                file_extension.to_lowercase()
-            } else {
-                file_extension
-            }
-        })
-        .collect();
+    if patterns && matcher.gitignore.is_none() && !name_could_match(path, matcher) {
+        trace!("No match, file name or extension: {}", path.display());
+        return Ok(None);
+    }
 
-    Ok((directories, file_names, file_extensions))
-}
+    // Use fs::metadata instead of is_file and is_dir to catch file system errors. This follows
+    // symlinks, so a symlink is matched by what it points to, the same as when the walk follows it
+    // with --follow-symlinks.
+    let metadata = fs::metadata(path)
+        .with_context(|| format!("Failed to get metadata for path {}", path.display()))?;
+
+    // The owner and permission filters apply on top of the file name and extension rules
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        if matcher.owner.is_some_and(|owner| metadata.uid() != owner)
+            || matcher.mode.is_some_and(|mode| metadata.mode() & mode != mode)
+        {
+            trace!("No match, owner or mode filtered out: {}", path.display());
+            return Ok(None);
+        }
+    }
+
+    // Paths of a file type that isn't hidden never match, whatever the other rules say
+    if !(metadata.is_file() && matcher.hide_files || metadata.is_dir() && matcher.hide_directories)
+    {
+        trace!("No match, file type not hidden: {}", path.display());
+        return Ok(None);
+    }
 
-// Helper function to check if a file or directory should be hidden
-fn should_hide_file(
-    path: &Path,
-    file_names: &HashSet<String>,
-    file_extensions: &HashSet<String>,
-    case_sensitive: bool,
-    hide_files: bool,
-    hide_directories: bool,
-) -> Result<bool> {
     // If both file names and file extensions are empty, then all files should be hidden
-    if file_names.is_empty() && file_extensions.is_empty() {
-        return Ok(true);
+    if unrestricted {
+        trace!("Match, no file names or extensions given: {}", path.display());
+        return Ok(Some(if matcher.path_regex.is_some() {
+            MatchReason::PathRegex
+        } else {
+            MatchReason::CatchAll
+        }));
     }
 
-    // Use fs::metadata instead of is_file and is_dir to catch file system errors
-    let metadata = fs::metadata(path)
-        .with_context(|| format!("Failed to get metadata for path {}", path.display()))?;
+    // Paths git ignores match whatever their names
+    if let Some(gitignore) = &matcher.gitignore {
+        if gitignore.is_ignored(path, metadata.is_dir()) {
+            trace!("Match, ignored by git: {}", path.display());
+            return Ok(Some(MatchReason::GitIgnored));
+        }
+        if !patterns {
+            trace!("No match, not ignored by git: {}", path.display());
+            return Ok(None);
+        }
+    }
 
     // Check if the path is a file or directory
-    if metadata.is_file() && hide_files {
+    if metadata.is_file() && matcher.hide_files {
         // Get the file name
         let file_name = path
             .file_name()
@@ -531,16 +5452,26 @@ fn should_hide_file(
                 )
             })?;
 
-        // Check if the file name is in the set of file names to hide
-        if file_names.contains(file_name) {
-            Ok(true)
+        let file_name = visible_name(file_name, matcher);
+
+        // Check if the file name (or its stem, with --match-stem) is in the set of file names to
+        // hide
+        let file_name = normalize(file_name, matcher.case_sensitive);
+        if matcher.file_names.contains(file_name.as_ref()) {
+            trace!("Match, file name: {}", path.display());
+            Ok(Some(MatchReason::Name(file_name.into_owned())))
+        } else if let Some(stem) = matcher.match_stem.then(|| matched_stem(path, matcher)).flatten()
+        {
+            trace!("Match, file stem: {}", path.display());
+            Ok(Some(MatchReason::Stem(stem)))
         } else {
-            // Get the file extension
-            let file_extension = path
-                .extension()
-                .with_context(|| {
-                    format!("Failed to get file extension from path {}", path.display())
-                })?
+            // Get the file extension. A file without one can't match any extension, which isn't an
+            // error (and mustn't stop the run with --fail-fast).
+            let Some(file_extension) = path.extension() else {
+                trace!("No match, no file name and no extension: {}", path.display());
+                return Ok(None);
+            };
+            let file_extension = file_extension
                 .to_str()
                 .with_context(|| {
                     format!(
@@ -550,13 +5481,16 @@ fn should_hide_file(
                 })?;
 
             // Check if the file extension is in the set of file extensions to hide
-            if case_sensitive {
-                Ok(file_extensions.contains(file_extension))
+            let file_extension = normalize(file_extension, matcher.case_sensitive);
+            if matcher.file_extensions.contains(file_extension.as_ref()) {
+                trace!("Match, file extension: {}", path.display());
+                Ok(Some(MatchReason::Extension(file_extension.into_owned())))
             } else {
-                Ok(file_extensions.contains(&file_extension.to_lowercase()))
+                trace!("No match, file name or extension: {}", path.display());
+                Ok(None)
             }
         }
-    } else if metadata.is_dir() && hide_directories {
+    } else if metadata.is_dir() && matcher.hide_directories {
         // Get the directory name
         let directory_name = path
             .file_name()
@@ -570,12 +5504,135 @@ fn should_hide_file(
             })?;
 
         // Check if the directory name is in the set of directory names to hide
-        if case_sensitive {
-            Ok(file_names.contains(directory_name))
+        let directory_name = visible_name(directory_name, matcher);
+        let directory_name = normalize(directory_name, matcher.case_sensitive);
+        if matcher.directory_names().contains(directory_name.as_ref()) {
+            trace!("Match, directory name: {}", path.display());
+            return Ok(Some(MatchReason::DirectoryName(directory_name.into_owned())));
+        }
+
+        // Check if the directory extension is in the set of directory extensions to hide
+        let extension = path
+            .extension()
+            .and_then(OsStr::to_str)
+            .map(|extension| normalize(extension, matcher.case_sensitive))
+            .filter(|extension| matcher.matches_dir_extension(extension));
+        if let Some(extension) = extension {
+            trace!("Match, directory extension: {}", path.display());
+            Ok(Some(MatchReason::DirectoryExtension(extension.into_owned())))
         } else {
-            Ok(file_names.contains(&directory_name.to_lowercase()))
+            trace!("No match, directory name or extension: {}", path.display());
+            Ok(None)
         }
     } else {
-        Ok(false)
+        trace!("No match, file type not hidden: {}", path.display());
+        Ok(None)
+    }
+}
+
+// Helper function to check if the name of a path could match one of the file or directory names,
+// stems or extensions, without touching the file system. Whether it's a file or a directory still
+// decides which of them apply. Names that aren't valid UTF-8 are left to should_hide_file to report.
+fn name_could_match(path: &Path, matcher: &Matcher) -> bool {
+    let Some(name) = path.file_name().and_then(OsStr::to_str) else {
+        return true;
+    };
+
+    let name = normalize(visible_name(name, matcher), matcher.case_sensitive);
+    matcher.file_names.contains(name.as_ref())
+        || matcher.dir_names.as_ref().is_some_and(|dir_names| dir_names.contains(name.as_ref()))
+        || (matcher.match_stem && matched_stem(path, matcher).is_some())
+        || path.extension().is_some_and(|extension| {
+            extension.to_str().is_none_or(|extension| {
+                let extension = normalize(extension, matcher.case_sensitive);
+                matcher.file_extensions.contains(extension.as_ref())
+                    || matcher.dir_extensions.contains(extension.as_ref())
+            })
+        })
+}
+
+// Helper function to get the stem of a file (its name without the last extension) if it's one of
+// the file names to hide, for --match-stem. Only names without a dot can match this way, so "file"
+// matches "file.txt", but "archive" doesn't match "archive.tar.gz".
+fn matched_stem(path: &Path, matcher: &Matcher) -> Option<String> {
+    path.file_stem()
+        .and_then(OsStr::to_str)
+        .map(|stem| visible_name(stem, matcher))
+        .filter(|stem| !stem.contains('.'))
+        .map(|stem| normalize(stem, matcher.case_sensitive).into_owned())
+        .filter(|stem| matcher.file_names.contains(stem))
+}
+
+// Helper function to get the name a path is matched by. With visible_prefix, a hidden name is
+// matched by the name it has once unhidden (e.g. ".file.txt" by "file.txt").
+fn visible_name<'a>(name: &'a str, matcher: &Matcher) -> &'a str {
+    match &matcher.visible_prefix {
+        Some(prefix) => name
+            .strip_prefix(prefix.as_str())
+            .filter(|name| !name.is_empty())
+            .unwrap_or(name),
+        None => name,
+    }
+}
+
+// Helper function to normalize a file name, extension, or pattern before comparing them. This must
+// be used for both sides of every comparison. When matching case insensitively, names are
+// lowercased with Rust's Unicode lowercase mapping (str::to_lowercase), which is the same in every
+// locale: "Café.TXT" matches "café.txt" and "ΑΡΧΕΙΟ.txt" matches "αρχειο.txt", but locale specific
+// rules such as the Turkish dotless "ı" and multi-character folds such as "ß" and "SS" are not
+// treated as equal.
+fn normalize(name: &str, case_sensitive: bool) -> Cow<'_, str> {
+    if case_sensitive {
+        Cow::Borrowed(name)
+    } else {
+        Cow::Owned(name.to_lowercase())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // At least one of watch mode and immediate mode has to run
+    #[test]
+    fn check_modes_needs_a_mode() {
+        assert!(check_modes(false, true).is_ok());
+        assert!(check_modes(true, false).is_ok());
+        assert!(check_modes(true, true).is_ok());
+        assert!(check_modes(false, false).is_err());
+    }
+
+    // Case insensitive names are compared by their Unicode lowercase, whatever the script
+    #[test]
+    fn normalize_folds_unicode_case() {
+        assert_eq!(normalize("Café.TXT", false), normalize("café.txt", false));
+        assert_eq!(normalize("ΑΡΧΕΙΟ.txt", false), normalize("αρχειο.txt", false));
+        assert_eq!(normalize("ОТЧЁТ.Doc", false), normalize("отчёт.doc", false));
+    }
+
+    // Case sensitive names are compared as they are
+    #[test]
+    fn normalize_keeps_case_when_sensitive() {
+        assert_eq!(normalize("Café.TXT", true), "Café.TXT");
+        assert_ne!(normalize("Café.TXT", true), normalize("café.txt", true));
+    }
+
+    // Locale specific and multi-character folds aren't applied, as documented on normalize
+    #[test]
+    fn normalize_ignores_locale_folds() {
+        // "I" always lowercases to "i", never to the Turkish dotless "ı"
+        assert_eq!(normalize("KIŞ.txt", false), "kiş.txt");
+        assert_ne!(normalize("KIŞ.txt", false), normalize("kış.txt", false));
+        assert_ne!(normalize("STRASSE", false), normalize("straße", false));
+    }
+
+    // --owner takes a uid or the name of a user, which root always is
+    #[cfg(unix)]
+    #[test]
+    fn parse_owner_resolves_names() {
+        assert_eq!(parse_owner("1234").unwrap(), 1234);
+        assert_eq!(parse_owner("root").unwrap(), 0);
+        assert!(parse_owner("no-such-user-for-filehider").is_err());
+        assert!(parse_owner("root\0").is_err());
     }
 }
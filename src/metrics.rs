@@ -0,0 +1,153 @@
+use std::{
+    fmt::Write,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, OnceLock,
+    },
+};
+
+use anyhow::{anyhow, Result};
+use tiny_http::{Header, Request, Response};
+
+use crate::WatchCounters;
+
+// Content type of the Prometheus text format
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+// The kinds of watcher events counted, as they're labeled
+const EVENT_KINDS: [&str; 6] = ["any", "access", "create", "modify", "remove", "other"];
+
+// Starting value of each event counter, since atomics can't be copied into an array
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicU64 = AtomicU64::new(0);
+
+// Events received from the watcher, indexed like EVENT_KINDS
+static EVENTS: [AtomicU64; EVENT_KINDS.len()] = [ZERO; EVENT_KINDS.len()];
+
+// Times a watched directory was watched again after it was removed or moved away
+static RESTARTS: AtomicU64 = AtomicU64::new(0);
+
+// Directories being watched right now
+static WATCHED: AtomicUsize = AtomicUsize::new(0);
+
+// The counts of paths handled in watch mode, once it's started
+static COUNTERS: OnceLock<Arc<WatchCounters>> = OnceLock::new();
+
+// Start serving the metrics at "/metrics" on the given address, from a thread of their own. Failing
+// to bind is fatal, since the metrics would silently be missing otherwise.
+pub fn serve(addr: SocketAddr) -> Result<()> {
+    let server = tiny_http::Server::http(addr)
+        .map_err(|e| anyhow!("Failed to serve metrics on {}: {}!", addr, e))?;
+
+    std::thread::spawn(move || {
+        for request in server.incoming_requests() {
+            respond(request);
+        }
+    });
+    Ok(())
+}
+
+// Count the paths handled by watch mode in the metrics
+pub fn watch(counters: &Arc<WatchCounters>) {
+    let _ = COUNTERS.set(Arc::clone(counters));
+}
+
+// Count an event received from the watcher
+pub fn event(event: &notify::Result<notify::Event>) {
+    use notify::EventKind;
+
+    if let Ok(event) = event {
+        let index = match event.kind {
+            EventKind::Any => 0,
+            EventKind::Access(_) => 1,
+            EventKind::Create(_) => 2,
+            EventKind::Modify(_) => 3,
+            EventKind::Remove(_) => 4,
+            EventKind::Other => 5,
+        };
+        EVENTS[index].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// Count a watched directory that's watched again after being removed or moved away
+pub fn restart() {
+    RESTARTS.fetch_add(1, Ordering::Relaxed);
+}
+
+// Set the number of directories being watched right now
+pub fn set_watched(directories: usize) {
+    WATCHED.store(directories, Ordering::Relaxed);
+}
+
+// Helper function to answer a request. Only "/metrics" exists, and a client that goes away before
+// the response is sent is ignored.
+fn respond(request: Request) {
+    let response = if request.url().split('?').next() == Some("/metrics") {
+        let mut response = Response::from_string(render());
+        if let Ok(header) = Header::from_bytes("Content-Type", CONTENT_TYPE) {
+            response.add_header(header);
+        }
+        response
+    } else {
+        Response::from_string("Not found").with_status_code(404)
+    };
+    let _ = request.respond(response);
+}
+
+// Helper function to write the metrics in the Prometheus text format
+fn render() -> String {
+    let outcome = COUNTERS.get().map(|counters| counters.get()).unwrap_or_default();
+
+    let mut metrics = String::new();
+    let _ = writeln!(metrics, "# HELP filehider_events_total Events received from the watcher.");
+    let _ = writeln!(metrics, "# TYPE filehider_events_total counter");
+    for (kind, count) in EVENT_KINDS.iter().zip(&EVENTS) {
+        let _ = writeln!(
+            metrics,
+            "filehider_events_total{{kind=\"{}\"}} {}",
+            kind,
+            count.load(Ordering::Relaxed)
+        );
+    }
+
+    let values = [
+        (
+            "filehider_files_hidden_total",
+            "counter",
+            "Files hidden in watch mode.",
+            outcome.hidden - outcome.hidden_directories,
+        ),
+        (
+            "filehider_directories_hidden_total",
+            "counter",
+            "Directories hidden in watch mode.",
+            outcome.hidden_directories,
+        ),
+        (
+            "filehider_errors_total",
+            "counter",
+            "Paths that couldn't be handled in watch mode.",
+            outcome.errors,
+        ),
+        (
+            "filehider_watcher_restarts_total",
+            "counter",
+            "Watched directories watched again after being removed or moved away.",
+            RESTARTS.load(Ordering::Relaxed) as usize,
+        ),
+        (
+            "filehider_watched_directories",
+            "gauge",
+            "Directories being watched.",
+            WATCHED.load(Ordering::Relaxed),
+        ),
+    ];
+    for (name, kind, help, value) in values {
+        let _ = writeln!(metrics, "# HELP {} {}", name, help);
+        let _ = writeln!(metrics, "# TYPE {} {}", name, kind);
+        let _ = writeln!(metrics, "{} {}", name, value);
+    }
+
+    metrics
+}
@@ -0,0 +1,82 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+use log::debug;
+
+// Number of paths hidden within one window that are still shown one notification each. Bursts of
+// more are shown as a single notification with the count.
+const BURST_SIZE: usize = 3;
+
+// Where the paths hidden are sent with --notify, once watch mode has started
+static NOTIFIER: OnceLock<crossbeam_channel::Sender<PathBuf>> = OnceLock::new();
+
+// Whether showing a notification failed, so a failure is only reported once
+static FAILED: AtomicBool = AtomicBool::new(false);
+
+// Start showing desktop notifications for the paths hidden. Notifications are shown on their own
+// thread, after waiting for the given window to pass, so bursts can be coalesced.
+pub fn start(window: Duration) {
+    let (sender, receiver) = crossbeam_channel::unbounded::<PathBuf>();
+    if NOTIFIER.set(sender).is_err() {
+        return;
+    }
+
+    std::thread::spawn(move || {
+        while let Ok(path) = receiver.recv() {
+            let mut paths = vec![path];
+            let deadline = Instant::now() + window;
+            while let Ok(path) = receiver.recv_deadline(deadline) {
+                paths.push(path);
+            }
+
+            if paths.len() > BURST_SIZE {
+                show(&summarize(&paths));
+            } else {
+                for path in &paths {
+                    show(&format!("Hid {}", path.display()));
+                }
+            }
+        }
+    });
+}
+
+// Show a notification for a path that was hidden, if --notify is in use
+pub fn hidden(path: &Path) {
+    if let Some(notifier) = NOTIFIER.get() {
+        let _ = notifier.send(path.to_path_buf());
+    }
+}
+
+// Helper function to describe a burst of paths hidden, naming the directory they're in if they're
+// all in the same one (e.g. "7 files hidden in Downloads")
+fn summarize(paths: &[PathBuf]) -> String {
+    let parent = paths[0].parent();
+    match parent.and_then(Path::file_name) {
+        Some(name) if paths.iter().all(|path| path.parent() == parent) => {
+            format!("{} files hidden in {}", paths.len(), name.to_string_lossy())
+        }
+        _ => format!("{} files hidden", paths.len()),
+    }
+}
+
+// Helper function to show a notification. Failures never affect hiding, and are only logged once,
+// since there's often no notification service at all (e.g. over SSH).
+fn show(body: &str) {
+    let result = notify_rust::Notification::new()
+        .appname("filehider")
+        .summary("filehider")
+        .body(body)
+        .show();
+
+    if let Err(e) = result {
+        if !FAILED.swap(true, Ordering::Relaxed) {
+            debug!("Failed to show a desktop notification: {}", e);
+        }
+    }
+}
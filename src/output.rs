@@ -0,0 +1,285 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::{Activity, MatchReason, Outcome};
+
+// Output for --format json and --format ndjson. With json, what happened to each path is collected
+// as it's handled, and printed to stdout as a single JSON document once the run finishes, with a
+// summary after the paths. With ndjson, each record is printed as a line of JSON as soon as it
+// happens instead, so long running watchers can be followed with tools like jq.
+pub struct JsonOutput {
+    started: Instant,
+    streaming: bool,
+    paths: Mutex<Vec<PathRecord>>,
+}
+
+// What caused a path to be handled
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    // Found by immediate mode, or given directly
+    Scan,
+    Create,
+    Rename,
+    // Found by a --rescan sweep in watch mode
+    Rescan,
+}
+
+// What happened to a path
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PathAction {
+    Hidden,
+    WouldHide,
+    AlreadyHidden,
+    Unhidden,
+    WouldUnhide,
+    AlreadyVisible,
+    Error,
+}
+
+// The document as it's printed
+// (e.g. {"paths": [{"path": "/home/user/a.tmp", "action": "hidden"}], "summary": {...}})
+#[derive(Debug, Serialize)]
+struct Document<'a> {
+    paths: &'a [PathRecord],
+    summary: Summary,
+}
+
+// A line of ndjson output
+// (e.g. {"type": "path", "time": "2023-03-01T08:00:00Z", "event": "create", "path": ..., ...})
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Line<'a> {
+    Start {
+        time: String,
+        roots: Vec<JsonPath>,
+    },
+    Path {
+        time: String,
+        event: EventKind,
+        #[serde(flatten)]
+        record: &'a PathRecord,
+    },
+    // What watch mode handled in the last --stats-interval and in total
+    Stats {
+        time: String,
+        interval_secs: f64,
+        last: Activity,
+        total: Activity,
+    },
+    Stop {
+        time: String,
+        summary: Summary,
+    },
+}
+
+#[derive(Debug, Serialize)]
+struct PathRecord {
+    path: JsonPath,
+    action: PathAction,
+    // The rule the path matched, unless it was hidden without matching (e.g. given directly)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rule: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Summary {
+    #[serde(flatten)]
+    totals: Outcome,
+    duration_secs: f64,
+}
+
+// A path as it's printed. JSON strings have to be valid UTF-8, so other paths are printed
+// losslessly as an array of their raw bytes on Unix, or of their UTF-16 code units on Windows.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum JsonPath {
+    Utf8(String),
+    #[cfg(not(windows))]
+    Raw(Vec<u8>),
+    #[cfg(windows)]
+    Raw(Vec<u16>),
+}
+
+impl JsonPath {
+    fn new(path: &Path) -> JsonPath {
+        if let Some(path) = path.to_str() {
+            return JsonPath::Utf8(path.to_string());
+        }
+
+        #[cfg(unix)]
+        let raw = {
+            use std::os::unix::ffi::OsStrExt;
+            path.as_os_str().as_bytes().to_vec()
+        };
+        #[cfg(windows)]
+        let raw = {
+            use std::os::windows::ffi::OsStrExt;
+            path.as_os_str().encode_wide().collect()
+        };
+        #[cfg(not(any(unix, windows)))]
+        let raw = path.to_string_lossy().into_owned().into_bytes();
+
+        JsonPath::Raw(raw)
+    }
+}
+
+impl JsonOutput {
+    pub fn new(streaming: bool) -> JsonOutput {
+        JsonOutput {
+            started: Instant::now(),
+            streaming,
+            paths: Mutex::new(Vec::new()),
+        }
+    }
+
+    // Print the directories being handled, with ndjson
+    pub fn start(&self, roots: &HashSet<PathBuf>) {
+        if self.streaming {
+            let mut roots: Vec<&PathBuf> = roots.iter().collect();
+            roots.sort();
+            print_line(&Line::Start {
+                time: timestamp(),
+                roots: roots.into_iter().map(|root| JsonPath::new(root)).collect(),
+            });
+        }
+    }
+
+    // Record what happened to a path
+    pub fn record(
+        &self,
+        path: &Path,
+        action: PathAction,
+        reason: Option<&MatchReason>,
+        event: EventKind,
+    ) {
+        self.push(
+            PathRecord {
+                path: JsonPath::new(path),
+                action,
+                rule: reason.map(MatchReason::to_string),
+                error: None,
+            },
+            event,
+        );
+    }
+
+    // Record a path that couldn't be handled
+    pub fn record_error(&self, path: &Path, error: &anyhow::Error, event: EventKind) {
+        self.push(
+            PathRecord {
+                path: JsonPath::new(path),
+                action: PathAction::Error,
+                rule: None,
+                error: Some(format!("{:#}", error)),
+            },
+            event,
+        );
+    }
+
+    fn push(&self, record: PathRecord, event: EventKind) {
+        if self.streaming {
+            print_line(&Line::Path {
+                time: timestamp(),
+                event,
+                record: &record,
+            });
+        } else {
+            self.paths
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push(record);
+        }
+    }
+
+    // Print the counts of the last --stats-interval and of the whole run, with ndjson. Nothing is
+    // printed with json, since the document only has the final totals.
+    pub fn stats(&self, interval: Duration, last: Activity, total: Activity) {
+        if self.streaming {
+            print_line(&Line::Stats {
+                time: timestamp(),
+                interval_secs: interval.as_secs_f64(),
+                last,
+                total,
+            });
+        }
+    }
+
+    // Print the document with the totals of the run, or the last line with ndjson
+    pub fn finish(&self, totals: Outcome) -> Result<()> {
+        let summary = Summary {
+            totals,
+            duration_secs: self.started.elapsed().as_secs_f64(),
+        };
+
+        if self.streaming {
+            print_line(&Line::Stop {
+                time: timestamp(),
+                summary,
+            });
+            return Ok(());
+        }
+
+        let paths = self.paths.lock().unwrap_or_else(|e| e.into_inner());
+        let document = Document {
+            paths: &paths,
+            summary,
+        };
+
+        let mut stdout = std::io::stdout().lock();
+        serde_json::to_writer_pretty(&mut stdout, &document)
+            .with_context(|| "Failed to write JSON output")?;
+        writeln!(stdout).with_context(|| "Failed to write JSON output")
+    }
+}
+
+// The JSON printed by --count, with the number of paths each rule matched
+#[derive(Debug, Serialize)]
+struct Counts<'a> {
+    count: usize,
+    rules: &'a BTreeMap<String, usize>,
+}
+
+// Print the number of paths found for --count, either as a bare number or as JSON with the number
+// for each rule (e.g. {"count": 3, "rules": {"extension \"tmp\"": 3}})
+pub fn print_count(count: usize, rules: &BTreeMap<String, usize>, json: bool) -> Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    if json {
+        serde_json::to_writer_pretty(&mut stdout, &Counts { count, rules })
+            .with_context(|| "Failed to write JSON output")?;
+        writeln!(stdout).with_context(|| "Failed to write JSON output")
+    } else {
+        writeln!(stdout, "{}", count).with_context(|| "Failed to write the count")
+    }
+}
+
+// Helper function to print a line of ndjson output and flush it right away. Lines are written while
+// holding the lock on stdout, so lines from worker threads can't interleave. Printing is best
+// effort, since the paths are handled either way.
+fn print_line(line: &Line) {
+    let mut stdout = std::io::stdout().lock();
+    let result = serde_json::to_writer(&mut stdout, line)
+        .map_err(std::io::Error::other)
+        .and_then(|_| writeln!(stdout))
+        .and_then(|_| stdout.flush());
+    if let Err(e) = result {
+        log::error!("Failed to write JSON output: {}", e);
+    }
+}
+
+// Helper function to format the current time as an RFC 3339 timestamp, in UTC with --utc and local
+// time otherwise
+fn timestamp() -> String {
+    crate::logger::timestamp().to_string()
+}
@@ -0,0 +1,102 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Instant,
+};
+
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use notify::event::{CreateKind, EventKind, ModifyKind, RemoveKind, RenameMode};
+use serde::Deserialize;
+
+use crate::{handle_event, EventHandler, EventOutcome, IpcSink, Outcome, Settings};
+
+// A synthetic watcher event, one per line of the file given to --replay-events
+// (e.g. {"kind": "rename", "paths": ["/tmp/dir/a.txt", "/tmp/dir/b.txt"]})
+#[derive(Debug, Deserialize)]
+struct ReplayEvent {
+    kind: ReplayKind,
+    paths: Vec<PathBuf>,
+}
+
+// The kinds of events that can be replayed, matching what the watcher delivers
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum ReplayKind {
+    Create,
+    // A rename with the old and new name, or only the new one
+    Rename,
+    // A path that was moved away
+    RenameFrom,
+    Remove,
+    // A change to the contents of a path, which is ignored like it is in watch mode
+    Modify,
+}
+
+impl ReplayEvent {
+    fn into_event(self) -> notify::Event {
+        let kind = match self.kind {
+            ReplayKind::Create => EventKind::Create(CreateKind::Any),
+            ReplayKind::Rename if self.paths.len() > 1 => {
+                EventKind::Modify(ModifyKind::Name(RenameMode::Both))
+            }
+            ReplayKind::Rename => EventKind::Modify(ModifyKind::Name(RenameMode::To)),
+            ReplayKind::RenameFrom => EventKind::Modify(ModifyKind::Name(RenameMode::From)),
+            ReplayKind::Remove => EventKind::Remove(RemoveKind::Any),
+            ReplayKind::Modify => EventKind::Modify(ModifyKind::Any),
+        };
+
+        self.paths
+            .into_iter()
+            .fold(notify::Event::new(kind), |event, path| event.add_path(path))
+    }
+}
+
+// Feed the events in a file through the same handling as watch mode, without a real watcher, then
+// wait for them to be handled and return the totals. Nothing is watched, so changes to the config
+// file aren't reloaded, and removed directories are only reported.
+pub fn replay_events(
+    events: &Path,
+    directories: HashSet<PathBuf>,
+    settings: Settings,
+) -> Result<Outcome> {
+    let started = Instant::now();
+    let contents = fs::read_to_string(events)
+        .with_context(|| format!("Failed to read events file {}!", events.display()))?;
+
+    let ipc = settings.ipc_path.as_deref().map(IpcSink::listen).transpose()?;
+    let handler = EventHandler::new(settings.watch_threads, ipc, None, None);
+    let config_path = settings.config_path.clone();
+    let directories = Arc::new(directories);
+    let settings = Arc::new(settings);
+
+    for (number, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let event: ReplayEvent = serde_json::from_str(line).with_context(|| {
+            format!("Failed to parse line {} of events file {}!", number + 1, events.display())
+        })?;
+        debug!("Replaying {:?}", event);
+
+        let event = Ok(event.into_event());
+        match handle_event(event, config_path.as_deref(), &directories, &settings, &handler) {
+            EventOutcome::Handled => {}
+            EventOutcome::ConfigChanged => debug!("Ignoring change to the config file"),
+            EventOutcome::Lost(lost) => {
+                for directory in lost {
+                    warn!("Watched directory {} was removed", directory.display());
+                }
+            }
+            EventOutcome::Overflow(_) => debug!("Ignoring event queue overflow"),
+        }
+    }
+
+    let outcome = handler.finish();
+    outcome.print_summary("Finished replaying events", started.elapsed(), &settings);
+
+    Ok(outcome)
+}
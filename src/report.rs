@@ -0,0 +1,123 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashSet},
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::{Handled, Outcome};
+
+// Report of an immediate mode run for --summary-json. Paths are recorded as they're handled, and
+// the report is written as JSON once the run finishes. Does nothing if no file was given.
+pub struct Report {
+    path: Option<PathBuf>,
+    // The given directories, which the counts are grouped by
+    directories: Vec<PathBuf>,
+    started: SystemTime,
+    counts: RefCell<BTreeMap<PathBuf, Outcome>>,
+    hidden: RefCell<Vec<(PathBuf, SystemTime)>>,
+}
+
+// The report as it's written to the file
+// (e.g. {"started": "2023-03-01T08:00:00Z", "totals": {"examined": 12, ...}, ...})
+#[derive(Debug, Serialize)]
+struct ReportFile {
+    started: String,
+    finished: String,
+    // Whether the hidden paths would only have been hidden, in test mode or list mode
+    dry_run: bool,
+    totals: Outcome,
+    directories: BTreeMap<String, Outcome>,
+    hidden: Vec<HiddenPath>,
+}
+
+#[derive(Debug, Serialize)]
+struct HiddenPath {
+    path: String,
+    time: String,
+}
+
+impl Report {
+    pub fn new(path: Option<PathBuf>, directories: &HashSet<PathBuf>) -> Report {
+        Report {
+            path,
+            directories: directories.iter().cloned().collect(),
+            started: SystemTime::now(),
+            counts: RefCell::new(BTreeMap::new()),
+            hidden: RefCell::new(Vec::new()),
+        }
+    }
+
+    // Record the result of handling a path, counting it for the given directory it's in. Files
+    // given directly are only counted in the totals.
+    pub fn record(&self, path: &Path, result: &Result<Handled>) {
+        if self.path.is_none() {
+            return;
+        }
+
+        // Directories can't be nested once they're set up, so at most one of them matches
+        if let Some(directory) = self.directories.iter().find(|dir| path.starts_with(dir)) {
+            self.counts
+                .borrow_mut()
+                .entry(directory.clone())
+                .or_default()
+                .record(result);
+        }
+
+        if let Ok(Handled::Hidden { .. }) = result {
+            self.hidden
+                .borrow_mut()
+                .push((path.to_path_buf(), SystemTime::now()));
+        }
+    }
+
+    // Write the report with the totals of the run, if a file was given
+    pub fn write(&self, totals: Outcome, dry_run: bool) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let report = ReportFile {
+            started: timestamp(self.started),
+            finished: timestamp(SystemTime::now()),
+            dry_run,
+            totals,
+            directories: self
+                .counts
+                .borrow()
+                .iter()
+                .map(|(directory, outcome)| (directory.to_string_lossy().into_owned(), *outcome))
+                .collect(),
+            hidden: self
+                .hidden
+                .borrow()
+                .iter()
+                .map(|(path, time)| HiddenPath {
+                    path: path.to_string_lossy().into_owned(),
+                    time: timestamp(*time),
+                })
+                .collect(),
+        };
+
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create report file {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, &report)
+            .with_context(|| format!("Failed to write report file {}", path.display()))?;
+        // Flush here, since errors are lost if the writer is only flushed when it's dropped
+        writer
+            .flush()
+            .with_context(|| format!("Failed to write report file {}", path.display()))
+    }
+}
+
+// Helper function to format a time for the report as an RFC 3339 timestamp in UTC
+// (e.g. "2023-03-01T08:00:00Z")
+fn timestamp(time: SystemTime) -> String {
+    humantime::format_rfc3339_seconds(time).to_string()
+}
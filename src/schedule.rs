@@ -0,0 +1,63 @@
+use std::fmt;
+
+use chrono::{Local, NaiveTime, Timelike};
+
+// The hours of the day watch mode hides paths in, from --active-between, in local time. The start
+// is included and the end isn't, and a window that ends before it starts crosses midnight
+// (e.g. "22:00-06:00" is active from 22:00 until 05:59).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ActiveWindow {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl ActiveWindow {
+    // Check if the local time right now is within the window
+    pub fn is_active_now(&self) -> bool {
+        self.contains(Local::now().time())
+    }
+
+    fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            self.start <= time || time < self.end
+        }
+    }
+}
+
+impl fmt::Display for ActiveWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}-{:02}:{:02}",
+            self.start.hour(),
+            self.start.minute(),
+            self.end.hour(),
+            self.end.minute()
+        )
+    }
+}
+
+// Parse a time window given as two 24 hour times (e.g. "22:00-06:00")
+pub fn parse_window(window: &str) -> Result<ActiveWindow, String> {
+    let (start, end) = window.split_once('-').ok_or_else(|| {
+        format!("invalid time window \"{}\", expected e.g. \"22:00-06:00\"", window)
+    })?;
+
+    let window = ActiveWindow {
+        start: parse_time(start)?,
+        end: parse_time(end)?,
+    };
+    if window.start == window.end {
+        return Err(format!("time window \"{}\" starts and ends at the same time", window));
+    }
+    Ok(window)
+}
+
+// Helper function to parse a 24 hour time (e.g. "06:00")
+fn parse_time(time: &str) -> Result<NaiveTime, String> {
+    let time = time.trim();
+    NaiveTime::parse_from_str(time, "%H:%M")
+        .map_err(|_| format!("invalid time \"{}\", expected e.g. \"06:00\"", time))
+}
@@ -0,0 +1,161 @@
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use anyhow::{anyhow, Context, Result};
+
+// Script of the commands that would hide each path, for --emit-script, so they can be reviewed and
+// run later instead of filehider changing anything. It's a shell script, or a PowerShell script on
+// Windows. Each command is followed by a comment with the command that undoes it. Every line is
+// written with a single write as soon as the path is found, so watch mode's worker threads can't
+// interleave them.
+pub struct Script {
+    path: PathBuf,
+    file: Mutex<File>,
+    // The directories renamed by the lines so far, old name first
+    #[cfg(not(windows))]
+    renamed: Mutex<Vec<(PathBuf, PathBuf)>>,
+}
+
+impl Script {
+    // Create the script, replacing any existing file, and write its header
+    pub fn create(path: &Path) -> Result<Script> {
+        let mut file = File::create(path)
+            .with_context(|| format!("Failed to create script {}!", path.display()))?;
+        file.write_all(header().as_bytes())
+            .with_context(|| format!("Failed to write script {}!", path.display()))?;
+
+        // Made executable so it can be run once it's been reviewed
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let _ = file.set_permissions(std::fs::Permissions::from_mode(0o755));
+        }
+
+        Ok(Script {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+            #[cfg(not(windows))]
+            renamed: Mutex::default(),
+        })
+    }
+
+    // Add the command that renames a path, for hiding or unhiding it on non-Windows platforms.
+    // Nothing is renamed until the script runs, so a path inside a directory an earlier line
+    // renames (e.g. with --order parents-first) is written under the directory's new name.
+    #[cfg(not(windows))]
+    pub fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let mut renamed = self.renamed.lock().unwrap_or_else(|e| e.into_inner());
+        let moved = |path: &Path| {
+            renamed.iter().fold(path.to_path_buf(), |path, (old, new)| {
+                match path.strip_prefix(old) {
+                    Ok(relative) if path != *old => new.join(relative),
+                    _ => path,
+                }
+            })
+        };
+        let (moved_from, moved_to) = (moved(from), moved(to));
+        if std::fs::symlink_metadata(from).is_ok_and(|metadata| metadata.is_dir()) {
+            renamed.push((moved_from.clone(), moved_to.clone()));
+        }
+
+        let (from, to) = (quote(&moved_from)?, quote(&moved_to)?);
+        self.write(
+            &format!("mv -n -- {} {}", from, to),
+            &format!("mv -n -- {} {}", to, from),
+        )
+    }
+
+    // Add the command that sets or clears the hidden flag of a directory, with --macos-finder-hidden
+    #[cfg(target_os = "macos")]
+    pub fn finder_hidden(&self, path: &Path, hidden: bool) -> Result<()> {
+        let path = quote(path)?;
+        let (set, clear) = if hidden {
+            ("hidden", "nohidden")
+        } else {
+            ("nohidden", "hidden")
+        };
+        self.write(
+            &format!("chflags {} {}", set, path),
+            &format!("chflags {} {}", clear, path),
+        )
+    }
+
+    // Add the command that sets or clears the attributes of a path on Windows
+    #[cfg(windows)]
+    pub fn attributes(&self, path: &Path, mask: u32, set: bool) -> Result<()> {
+        let path = quote(path)?;
+        let (add, remove) = (attrib_flags(mask, '+'), attrib_flags(mask, '-'));
+        let (command, undo) = if set { (add, remove) } else { (remove, add) };
+        self.write(
+            &format!("attrib {} {}", command, path),
+            &format!("attrib {} {}", undo, path),
+        )
+    }
+
+    // Helper function to write a command and the comment that undoes it
+    fn write(&self, command: &str, undo: &str) -> Result<()> {
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        file.write_all(format!("{}\n# undo: {}\n", command, undo).as_bytes())
+            .with_context(|| format!("Failed to write script {}!", self.path.display()))
+    }
+}
+
+// Helper function to write the first lines of the script, which stop it at the first command that
+// fails
+#[cfg(not(windows))]
+fn header() -> String {
+    format!(
+        "#!/bin/sh\n# Written by filehider on {}\nset -e\n",
+        humantime::format_rfc3339_seconds(SystemTime::now())
+    )
+}
+
+#[cfg(windows)]
+fn header() -> String {
+    format!(
+        "# Written by filehider on {}\n$ErrorActionPreference = \"Stop\"\n",
+        humantime::format_rfc3339_seconds(SystemTime::now())
+    )
+}
+
+// Helper function to quote a path for the script. Single quotes keep everything literal, in both
+// the shell and PowerShell, so only single quotes themselves have to be escaped.
+fn quote(path: &Path) -> Result<String> {
+    let path = path.to_str().ok_or_else(|| {
+        anyhow!(
+            "Path {} isn't valid unicode, so it can't be written to a script!",
+            path.display()
+        )
+    })?;
+
+    Ok(if cfg!(windows) {
+        format!("'{}'", path.replace('\'', "''"))
+    } else {
+        format!("'{}'", path.replace('\'', r"'\''"))
+    })
+}
+
+// Helper function to turn the attributes set when hiding into attrib flags (e.g. "+h +s")
+#[cfg(windows)]
+fn attrib_flags(mask: u32, sign: char) -> String {
+    use winapi::um::winnt::{
+        FILE_ATTRIBUTE_HIDDEN, FILE_ATTRIBUTE_READONLY, FILE_ATTRIBUTE_SYSTEM,
+    };
+
+    [
+        (FILE_ATTRIBUTE_HIDDEN, 'h'),
+        (FILE_ATTRIBUTE_SYSTEM, 's'),
+        (FILE_ATTRIBUTE_READONLY, 'r'),
+    ]
+    .iter()
+    .filter(|(attribute, _)| mask & attribute != 0)
+    .map(|(_, flag)| format!("{}{}", sign, flag))
+    .collect::<Vec<_>>()
+    .join(" ")
+}
@@ -0,0 +1,83 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use anyhow::Result;
+#[cfg(unix)]
+use anyhow::Context;
+
+// Requests made to watch mode from outside, so it can be controlled like a daemon. On Unix, SIGHUP
+// reloads the config file and SIGUSR1 starts a rescan. On Windows, Ctrl+Break starts a rescan. The
+// flags are only set by the signal handlers, and watch mode checks them as it loops.
+#[derive(Default)]
+pub struct Signals {
+    reload: Arc<AtomicBool>,
+    rescan: Arc<AtomicBool>,
+}
+
+// Where Ctrl+Break is recorded, since the console handler can't capture anything
+#[cfg(windows)]
+static CTRL_BREAK: std::sync::OnceLock<Arc<AtomicBool>> = std::sync::OnceLock::new();
+
+impl Signals {
+    // Start listening for SIGHUP and SIGUSR1
+    #[cfg(unix)]
+    pub fn listen() -> Result<Signals> {
+        use signal_hook::consts::{SIGHUP, SIGUSR1};
+
+        let signals = Signals::default();
+        signal_hook::flag::register(SIGHUP, Arc::clone(&signals.reload))
+            .with_context(|| "Failed to set SIGHUP handler!")?;
+        signal_hook::flag::register(SIGUSR1, Arc::clone(&signals.rescan))
+            .with_context(|| "Failed to set SIGUSR1 handler!")?;
+        Ok(signals)
+    }
+
+    // Start listening for Ctrl+Break. The handler is added after the Ctrl+C one, so it's called
+    // first and Ctrl+Break doesn't stop watch mode.
+    #[cfg(windows)]
+    pub fn listen() -> Result<Signals> {
+        use anyhow::anyhow;
+        use winapi::{shared::minwindef::TRUE, um::consoleapi::SetConsoleCtrlHandler};
+
+        let signals = Signals::default();
+        if CTRL_BREAK.set(Arc::clone(&signals.rescan)).is_err() {
+            return Ok(signals);
+        }
+        if unsafe { SetConsoleCtrlHandler(Some(on_console_event), TRUE) } == 0 {
+            return Err(anyhow!(
+                "Failed to set Ctrl+Break handler: {}!",
+                std::io::Error::last_os_error()
+            ));
+        }
+        Ok(signals)
+    }
+
+    // Check whether a reload was asked for since the last check
+    pub fn reload_requested(&self) -> bool {
+        self.reload.swap(false, Ordering::SeqCst)
+    }
+
+    // Check whether a rescan was asked for since the last check
+    pub fn rescan_requested(&self) -> bool {
+        self.rescan.swap(false, Ordering::SeqCst)
+    }
+}
+
+// Console handler for Ctrl+Break. Every other event is passed on to the next handler.
+#[cfg(windows)]
+unsafe extern "system" fn on_console_event(event: u32) -> i32 {
+    use winapi::{
+        shared::minwindef::{FALSE, TRUE},
+        um::wincon::CTRL_BREAK_EVENT,
+    };
+
+    match CTRL_BREAK.get() {
+        Some(rescan) if event == CTRL_BREAK_EVENT => {
+            rescan.store(true, Ordering::SeqCst);
+            TRUE
+        }
+        _ => FALSE,
+    }
+}
@@ -0,0 +1,101 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex, OnceLock,
+};
+
+use anyhow::{anyhow, Result};
+use log::{Level, Record};
+use syslog::{Facility, Formatter3164, LoggerBackend};
+
+// Name messages are tagged with, so they can be found with "journalctl -t filehider"
+const IDENTIFIER: &str = "filehider";
+
+// Socket journald listens on, if it's running
+#[cfg(target_os = "linux")]
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+// Where messages are sent with --syslog, once it's started
+static SYSTEM_LOG: OnceLock<SystemLog> = OnceLock::new();
+
+// Whether sending a message failed, so a failure is only reported once
+static FAILED: AtomicBool = AtomicBool::new(false);
+
+// Destination for --syslog. The systemd journal is preferred where it's running, since it keeps
+// the path of each hide in its own FILEHIDER_PATH field (e.g. "journalctl -t filehider
+// FILEHIDER_PATH=/home/user/a.tmp"). Elsewhere, messages go to the local syslog daemon.
+enum SystemLog {
+    #[cfg(target_os = "linux")]
+    Journal,
+    Syslog(Mutex<syslog::Logger<LoggerBackend, Formatter3164>>),
+}
+
+// Start sending messages to the systemd journal or syslog as well as printing them
+pub fn start() -> Result<()> {
+    #[cfg(target_os = "linux")]
+    if std::path::Path::new(JOURNAL_SOCKET).exists() {
+        let _ = SYSTEM_LOG.set(SystemLog::Journal);
+        return Ok(());
+    }
+
+    let formatter = Formatter3164 {
+        facility: Facility::LOG_DAEMON,
+        hostname: None,
+        process: IDENTIFIER.to_string(),
+        pid: std::process::id(),
+    };
+    let logger =
+        syslog::unix(formatter).map_err(|e| anyhow!("Failed to connect to syslog: {}", e))?;
+    let _ = SYSTEM_LOG.set(SystemLog::Syslog(Mutex::new(logger)));
+    Ok(())
+}
+
+// Send a log record, if --syslog is in use. Failures are reported on stderr once, since the
+// message was already printed.
+pub fn write(record: &Record) {
+    let Some(system_log) = SYSTEM_LOG.get() else {
+        return;
+    };
+
+    let message = record.args().to_string();
+    let result = match system_log {
+        #[cfg(target_os = "linux")]
+        SystemLog::Journal => send_to_journal(record, &message),
+        SystemLog::Syslog(logger) => {
+            let mut logger = logger.lock().unwrap_or_else(|e| e.into_inner());
+            match record.level() {
+                Level::Error => logger.err(message),
+                Level::Warn => logger.warning(message),
+                Level::Info => logger.info(message),
+                Level::Debug | Level::Trace => logger.debug(message),
+            }
+            .map_err(|e| e.to_string())
+        }
+    };
+
+    if let Err(e) = result {
+        if !FAILED.swap(true, Ordering::Relaxed) {
+            eprintln!("Warning: Failed to send messages to the system log: {}", e);
+        }
+    }
+}
+
+// Helper function to send a record to the systemd journal, with the path it's about in its own
+// field if it has one
+#[cfg(target_os = "linux")]
+fn send_to_journal(record: &Record, message: &str) -> Result<(), String> {
+    use libsystemd::logging::{journal_send, Priority};
+
+    let priority = match record.level() {
+        Level::Error => Priority::Error,
+        Level::Warn => Priority::Warning,
+        Level::Info => Priority::Info,
+        Level::Debug | Level::Trace => Priority::Debug,
+    };
+
+    let mut fields = vec![("SYSLOG_IDENTIFIER", IDENTIFIER.to_string())];
+    if let Some(path) = record.key_values().get(log::kv::Key::from_str("path")) {
+        fields.push(("FILEHIDER_PATH", path.to_string()));
+    }
+
+    journal_send(priority, message, fields.into_iter()).map_err(|e| e.to_string())
+}
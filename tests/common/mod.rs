@@ -0,0 +1,294 @@
+// Helpers shared by the integration tests, which run the filehider binary against temporary
+// directories. Not every test file uses every helper.
+#![allow(dead_code)]
+
+use std::{
+    fs,
+    io::{BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Output, Stdio},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError},
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+// Counter to keep the temporary directories of tests running in parallel apart
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+// A temporary directory for a test, removed when it's dropped. The tests hide paths in dir(), and
+// the binary gets a home directory of its own next to it, so it never reads the user's config file
+// or takes the same lock as a real instance.
+pub struct TempDir {
+    root: PathBuf,
+}
+
+impl TempDir {
+    pub fn new() -> TempDir {
+        TempDir::new_in(&std::env::temp_dir())
+    }
+
+    // A temporary directory under the given one, e.g. on another file system
+    pub fn new_in(parent: &Path) -> TempDir {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .subsec_nanos();
+        let root = parent.join(format!(
+            "filehider-test-{}-{}-{}",
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::SeqCst),
+            nanos
+        ));
+        fs::create_dir_all(root.join("dir")).unwrap();
+        fs::create_dir_all(root.join("home")).unwrap();
+
+        // Canonicalized so paths printed by the binary compare equal (e.g. /tmp on macOS)
+        TempDir {
+            root: root.canonicalize().unwrap(),
+        }
+    }
+
+    // The directory the tests hide paths in
+    pub fn dir(&self) -> PathBuf {
+        self.root.join("dir")
+    }
+
+    // The home directory of the binary, outside dir()
+    pub fn home(&self) -> PathBuf {
+        self.root.join("home")
+    }
+
+    // The path of a file or directory relative to dir()
+    pub fn path(&self, relative: &str) -> PathBuf {
+        self.dir().join(relative)
+    }
+
+    // Create an empty file relative to dir(), along with its parents
+    pub fn touch(&self, relative: &str) -> PathBuf {
+        let path = self.path(relative);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "").unwrap();
+        path
+    }
+
+    // Create a directory relative to dir(), along with its parents
+    pub fn mkdir(&self, relative: &str) -> PathBuf {
+        let path = self.path(relative);
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    // Write a file outside dir(), e.g. a config file
+    pub fn write(&self, relative: &str, contents: &str) -> PathBuf {
+        let path = self.root.join(relative);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    // A path outside dir(), e.g. for a journal or a report
+    pub fn outside(&self, relative: &str) -> PathBuf {
+        self.root.join(relative)
+    }
+
+    // Whether a path relative to dir() was hidden: renamed with a leading dot on Unix, or given the
+    // hidden attribute on Windows
+    pub fn is_hidden(&self, relative: &str) -> bool {
+        let path = self.path(relative);
+        if cfg!(windows) {
+            is_hidden_attribute(&path)
+        } else {
+            let name = path.file_name().unwrap().to_str().unwrap();
+            exists(&path.with_file_name(format!(".{}", name)))
+        }
+    }
+
+    // The sorted names in a directory relative to dir(), including hidden ones
+    pub fn names(&self, relative: &str) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(self.path(relative))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    // A command running the binary in dir(), isolated from the user's environment
+    pub fn filehider(&self) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_filehider"));
+        command
+            .current_dir(self.dir())
+            .env("HOME", self.home())
+            .env("XDG_CONFIG_HOME", self.home().join(".config"))
+            .env("XDG_DATA_HOME", self.home().join(".local/share"))
+            .env("XDG_RUNTIME_DIR", self.home().join("run"))
+            .env("APPDATA", self.home().join("AppData/Roaming"))
+            .env("LOCALAPPDATA", self.home().join("AppData/Local"))
+            .env_remove("FILEHIDER_DIRS")
+            .env_remove("FILEHIDER_NAMES")
+            .env_remove("FILEHIDER_EXTENSIONS")
+            .env("NO_COLOR", "1")
+            .stdin(Stdio::null());
+        command
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+// Helper function to run a command to completion, failing the test if it can't be started
+pub fn run(command: &mut Command) -> Output {
+    command.output().expect("Failed to run filehider!")
+}
+
+// Helper function to get everything a finished command printed as text. Messages go to stdout or
+// stderr depending on the output mode, so both are included.
+pub fn logged(output: &Output) -> String {
+    format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    )
+}
+
+// How long a background command gets to log what a test waits for
+const LOG_TIMEOUT: Duration = Duration::from_secs(30);
+
+// A command running in the background, with what it prints read on threads of their own so a test
+// can wait for a line without blocking forever
+pub struct Running {
+    child: Child,
+    lines: Receiver<String>,
+    logged: String,
+}
+
+impl Running {
+    pub fn spawn(command: &mut Command) -> Running {
+        let mut child = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("Failed to start filehider!");
+
+        let (sender, lines) = mpsc::channel();
+        let stdout: Box<dyn Read + Send> = Box::new(child.stdout.take().unwrap());
+        let stderr: Box<dyn Read + Send> = Box::new(child.stderr.take().unwrap());
+        for stream in [stdout, stderr] {
+            let sender = sender.clone();
+            thread::spawn(move || {
+                for line in BufReader::new(stream).lines().map_while(Result::ok) {
+                    if sender.send(line).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        Running {
+            child,
+            lines,
+            logged: String::new(),
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    // Wait until a line containing the given text is logged, failing the test if it isn't within
+    // the timeout or the command exits first
+    pub fn wait_for(&mut self, text: &str) {
+        let started = Instant::now();
+        while !self.logged.contains(text) {
+            let left = LOG_TIMEOUT.saturating_sub(started.elapsed());
+            match self.lines.recv_timeout(left) {
+                Ok(line) => {
+                    self.logged.push_str(&line);
+                    self.logged.push('\n');
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    panic!("Timed out waiting for \"{}\":\n{}", text, self.logged)
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    panic!(
+                        "filehider exited before logging \"{}\":\n{}",
+                        text, self.logged
+                    )
+                }
+            }
+        }
+    }
+
+    // Wait for the command to exit on its own, returning its status and everything it printed
+    pub fn wait(mut self) -> (ExitStatus, String) {
+        let status = self.child.wait().unwrap();
+        let rest: Vec<String> = self.lines.iter().collect();
+        for line in rest {
+            self.logged.push_str(&line);
+            self.logged.push('\n');
+        }
+        (status, std::mem::take(&mut self.logged))
+    }
+
+    // Kill the command, returning everything it printed
+    pub fn stop(mut self) -> String {
+        let _ = self.child.kill();
+        self.wait().1
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// Helper function to poll until a condition holds, failing the test if it doesn't within the
+// timeout
+pub fn wait_until(what: &str, mut condition: impl FnMut() -> bool) {
+    let started = Instant::now();
+    while !condition() {
+        assert!(
+            started.elapsed() < LOG_TIMEOUT,
+            "Timed out waiting for {}",
+            what
+        );
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+// Helper function to check whether a path exists without following a symlink at its end
+pub fn exists(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok()
+}
+
+// Helper function to check whether a path has the hidden attribute on Windows
+#[cfg(windows)]
+pub fn is_hidden_attribute(path: &Path) -> bool {
+    use std::os::windows::fs::MetadataExt;
+
+    // FILE_ATTRIBUTE_HIDDEN
+    fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_attributes() & 0x2 != 0)
+}
+
+#[cfg(not(windows))]
+pub fn is_hidden_attribute(_path: &Path) -> bool {
+    false
+}
+
+// Helper function to send a signal to a process by its name (e.g. "HUP"), with kill(1)
+#[cfg(unix)]
+pub fn signal(pid: u32, name: &str) {
+    let status = Command::new("kill")
+        .args(["-s", name, &pid.to_string()])
+        .status()
+        .expect("Failed to run kill!");
+    assert!(status.success(), "Failed to send SIG{} to {}", name, pid);
+}
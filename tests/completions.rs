@@ -0,0 +1,91 @@
+// Tests of the completions and manpage subcommands
+mod common;
+
+use common::{logged, run, TempDir};
+
+// Some of the long flags every completion script should offer, without their dashes since fish
+// gives them as "-l name"
+const LONG_FLAGS: &[&str] = &[
+    "file-names",
+    "file-extensions",
+    "recursive",
+    "watch",
+    "file-types",
+    "config",
+    "backend",
+];
+
+// Helper function to generate the completion script for a shell
+fn completions(tmp: &TempDir, shell: &str) -> String {
+    let output = run(tmp.filehider().args(["completions", shell]));
+    assert!(output.status.success(), "{}: {}", shell, logged(&output));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+// Each shell's completion script offers the long flags
+#[test]
+fn completions_contain_long_flags() {
+    let tmp = TempDir::new();
+
+    for shell in ["bash", "elvish", "fish", "powershell", "zsh"] {
+        let script = completions(&tmp, shell);
+        for flag in LONG_FLAGS {
+            assert!(
+                script.contains(flag),
+                "{} completions lack --{}",
+                shell,
+                flag
+            );
+        }
+    }
+}
+
+// The shells that complete values offer the possible values of --file-types
+#[test]
+fn completions_contain_possible_values() {
+    let tmp = TempDir::new();
+
+    for (shell, values) in [
+        ("bash", "file directory"),
+        ("fish", "directory\\t"),
+        ("zsh", "(file directory)"),
+    ] {
+        let script = completions(&tmp, shell);
+        assert!(
+            script.contains(values),
+            "{} completions lack file types",
+            shell
+        );
+    }
+}
+
+// An unknown shell is rejected
+#[test]
+fn completions_reject_unknown_shell() {
+    let tmp = TempDir::new();
+
+    let output = run(tmp.filehider().args(["completions", "tcsh"]));
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+}
+
+// The man page documents the long flags
+#[test]
+fn manpage_contains_long_flags() {
+    let tmp = TempDir::new();
+
+    let output = run(tmp.filehider().arg("manpage"));
+
+    assert!(output.status.success(), "{}", logged(&output));
+    let page = String::from_utf8(output.stdout).unwrap();
+    assert!(page.contains(".TH filehider 1"));
+    for flag in LONG_FLAGS {
+        // Dashes are escaped in roff
+        assert!(
+            page.contains(&flag.replace('-', "\\-")),
+            "man page lacks --{}",
+            flag
+        );
+    }
+}
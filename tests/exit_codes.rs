@@ -0,0 +1,227 @@
+// Tests of the exit codes: 0 on success, 1 on a fatal error, 2 when some paths failed, and 3 when
+// --fail-on-match finds paths in test mode or list mode
+mod common;
+
+use common::{logged, run, TempDir};
+
+const EXIT_FATAL: i32 = 1;
+const EXIT_ERRORS: i32 = 2;
+const EXIT_MATCHES: i32 = 3;
+
+// A run without any errors succeeds, whether or not anything was hidden
+#[test]
+fn success_exits_zero() {
+    let tmp = TempDir::new();
+    tmp.touch("a.tmp");
+
+    for extension in ["tmp", "log"] {
+        let output = run(tmp.filehider().args([".", "-x", extension]));
+        assert_eq!(output.status.code(), Some(0), "{}", logged(&output));
+    }
+}
+
+// A directory that doesn't exist is fatal
+#[test]
+fn missing_directory_exits_fatal() {
+    let tmp = TempDir::new();
+
+    let output = run(tmp.filehider().args(["missing", "-x", "tmp"]));
+
+    assert_eq!(
+        output.status.code(),
+        Some(EXIT_FATAL),
+        "{}",
+        logged(&output)
+    );
+    assert!(logged(&output).contains("does not exist!"));
+}
+
+// A path that fails to hide doesn't stop the others, but the run exits with EXIT_ERRORS
+#[cfg(unix)]
+#[test]
+fn path_errors_exit_errors() {
+    let tmp = TempDir::new();
+    tmp.touch("a.tmp");
+    tmp.touch(".a.tmp");
+    tmp.touch("b.tmp");
+
+    let output = run(tmp.filehider().args([".", "-x", "tmp"]));
+
+    assert_eq!(
+        output.status.code(),
+        Some(EXIT_ERRORS),
+        "{}",
+        logged(&output)
+    );
+    assert!(tmp.is_hidden("b.tmp"));
+}
+
+// Matches in test mode only change the exit code with --fail-on-match
+#[test]
+fn test_mode_matches_exit_matches() {
+    let tmp = TempDir::new();
+    tmp.touch("a.tmp");
+
+    let output = run(tmp.filehider().args([".", "-x", "tmp", "--test"]));
+    assert_eq!(output.status.code(), Some(0), "{}", logged(&output));
+
+    let output = run(tmp
+        .filehider()
+        .args([".", "-x", "tmp", "--test", "--fail-on-match"]));
+    assert_eq!(
+        output.status.code(),
+        Some(EXIT_MATCHES),
+        "{}",
+        logged(&output)
+    );
+    assert!(!tmp.is_hidden("a.tmp"));
+}
+
+// --fail-if-found exits with EXIT_MATCHES only when something is found
+#[test]
+fn fail_if_found_exit_codes() {
+    let tmp = TempDir::new();
+    tmp.touch("a.tmp");
+
+    for (extension, code) in [("tmp", EXIT_MATCHES), ("log", 0)] {
+        let output = run(tmp
+            .filehider()
+            .args([".", "-x", extension, "--test", "--fail-if-found"]));
+        assert_eq!(output.status.code(), Some(code), "{}", logged(&output));
+    }
+}
+
+// --fail-if-found leaves stdout to --count and --format json
+#[test]
+fn fail_if_found_with_count_and_json() {
+    let tmp = TempDir::new();
+    tmp.touch("a.tmp");
+    tmp.touch("b.tmp");
+
+    let output =
+        run(tmp
+            .filehider()
+            .args([".", "-x", "tmp", "--test", "--fail-if-found", "--count"]));
+    assert_eq!(
+        output.status.code(),
+        Some(EXIT_MATCHES),
+        "{}",
+        logged(&output)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "2\n");
+
+    let output = run(tmp.filehider().args([
+        ".",
+        "-x",
+        "tmp",
+        "--test",
+        "--fail-if-found",
+        "--format",
+        "json",
+    ]));
+    assert_eq!(
+        output.status.code(),
+        Some(EXIT_MATCHES),
+        "{}",
+        logged(&output)
+    );
+    let document: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(document["summary"]["hidden"], 2);
+}
+
+// --fail-if-found can't be used in watch mode, whether it's enabled on the command line or in the
+// config file
+#[test]
+fn fail_if_found_rejected_in_watch_mode() {
+    let tmp = TempDir::new();
+
+    let output = run(tmp
+        .filehider()
+        .args([".", "-x", "tmp", "-w", "--fail-if-found"]));
+    assert_eq!(
+        output.status.code(),
+        Some(EXIT_FATAL),
+        "{}",
+        logged(&output)
+    );
+
+    let config = tmp.write("config.toml", "watch-mode = true\n");
+    let output = run(tmp
+        .filehider()
+        .args([".", "-x", "tmp", "--fail-if-found", "--config"])
+        .arg(config));
+    assert_eq!(
+        output.status.code(),
+        Some(EXIT_FATAL),
+        "{}",
+        logged(&output)
+    );
+    assert!(logged(&output).contains("--fail-on-match can't be used in watch mode!"));
+}
+
+// check-config runs the same validation as a normal run, exiting with EXIT_FATAL on an error and
+// printing a summary of the rules otherwise
+#[test]
+fn check_config_exit_codes() {
+    let tmp = TempDir::new();
+
+    let output = run(tmp.filehider().args(["-n", "a.tmp", "-r", "check-config"]));
+    assert!(output.status.success(), "{}", logged(&output));
+    assert!(logged(&output).starts_with("OK\n"), "{}", logged(&output));
+
+    for (args, error) in [
+        (
+            &["-n", "a/b"][..],
+            "File name \"a/b\" can never match a file!",
+        ),
+        (
+            &["-x", "tmp", "--min-depth", "2", "--max-depth", "1"],
+            "Minimum depth 2 is greater than maximum depth 1!",
+        ),
+    ] {
+        for last in ["check-config", "."] {
+            let output = run(tmp.filehider().args(args).args(["-r", last]));
+            assert_eq!(
+                output.status.code(),
+                Some(EXIT_FATAL),
+                "{}",
+                logged(&output)
+            );
+            assert!(logged(&output).contains(error), "{}", logged(&output));
+        }
+    }
+}
+
+// check-config reports every problem in the config, not just the first one
+#[test]
+fn check_config_lists_every_error() {
+    let tmp = TempDir::new();
+    let config = tmp.write(
+        "config.toml",
+        r#"
+        directories = ["missing"]
+        file-names = ["a/b"]
+        file-extensions = ["tmp", "."]
+        watch-mode = false
+        immediate = false
+        "#,
+    );
+
+    let output = run(tmp.filehider().arg("--config").arg(config).arg("check-config"));
+
+    assert_eq!(
+        output.status.code(),
+        Some(EXIT_FATAL),
+        "{}",
+        logged(&output)
+    );
+    for error in [
+        "4 errors during check-config",
+        "Both watch mode and immediate mode are disabled.",
+        "missing",
+        "File name \"a/b\" can never match a file!",
+        "File extension \".\" can never match a file!",
+    ] {
+        assert!(logged(&output).contains(error), "{}", logged(&output));
+    }
+}
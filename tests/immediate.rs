@@ -0,0 +1,556 @@
+// Tests of immediate mode, running the binary once against a temporary directory. Hiding a path
+// renames it with a leading dot on Unix, which the tests that check names rely on.
+mod common;
+
+#[cfg(unix)]
+use std::fs;
+
+use common::{logged, run, TempDir};
+
+// Without any directories, the current directory is used and its absolute path is printed
+#[cfg(unix)]
+#[test]
+fn no_directories_uses_current_directory() {
+    let tmp = TempDir::new();
+    tmp.touch("a.tmp");
+    tmp.touch("b.txt");
+
+    let output = run(tmp.filehider().args(["-x", "tmp"]));
+
+    assert!(output.status.success(), "{}", logged(&output));
+    assert!(logged(&output).contains(&format!(
+        "No directories given, using current directory: {}",
+        tmp.dir().display()
+    )));
+    assert_eq!(tmp.names("."), [".a.tmp", "b.txt"]);
+}
+
+// Paths found through the current directory are absolute in test mode, the same as when the
+// directory is given
+#[test]
+fn no_directories_prints_absolute_paths() {
+    let tmp = TempDir::new();
+    tmp.touch("a.tmp");
+
+    let output = run(tmp.filehider().args(["-x", "tmp", "--test"]));
+
+    assert!(output.status.success(), "{}", logged(&output));
+    assert!(logged(&output).contains(&tmp.path("a.tmp").display().to_string()));
+    assert_eq!(tmp.names("."), ["a.tmp"]);
+}
+
+// By default, a path whose hidden name already exists is an error, and neither is overwritten
+#[cfg(unix)]
+#[test]
+fn collision_is_an_error_by_default() {
+    let tmp = TempDir::new();
+    fs::write(tmp.path("foo.txt"), "visible").unwrap();
+    fs::write(tmp.path(".foo.txt"), "hidden").unwrap();
+
+    let output = run(tmp.filehider().args([".", "-n", "foo.txt"]));
+
+    assert_eq!(output.status.code(), Some(2), "{}", logged(&output));
+    assert!(logged(&output).contains("already exists"));
+    assert_eq!(fs::read_to_string(tmp.path("foo.txt")).unwrap(), "visible");
+    assert_eq!(fs::read_to_string(tmp.path(".foo.txt")).unwrap(), "hidden");
+}
+
+// Reaching --max-errors-total stops the run, but still prints the summary and writes the report
+#[cfg(unix)]
+#[test]
+fn max_errors_total_stops_with_summary() {
+    let tmp = TempDir::new();
+    for name in ["a.txt", "b.txt", "c.txt"] {
+        tmp.touch(name);
+        tmp.touch(&format!(".{}", name));
+    }
+    let report = tmp.outside("report.json");
+
+    let output = run(tmp
+        .filehider()
+        .args([".", "-x", "txt", "--max-errors-total", "2"])
+        .arg("--summary-json")
+        .arg(&report));
+
+    assert_eq!(output.status.code(), Some(2), "{}", logged(&output));
+    assert!(logged(&output).contains("Immediate mode aborted"));
+    assert!(logged(&output).contains("Errors: 2"));
+    let report = fs::read_to_string(&report).unwrap();
+    assert!(report.contains("\"aborted\": true"), "{}", report);
+}
+
+// A report that can't be written is a fatal error, even when it's small enough to be buffered
+// until the end. /dev/full fails every write with ENOSPC.
+#[cfg(target_os = "linux")]
+#[test]
+fn report_write_error_is_fatal() {
+    let tmp = TempDir::new();
+    tmp.touch("a.tmp");
+
+    let output = run(tmp
+        .filehider()
+        .args([".", "-x", "tmp", "--test", "--summary-json", "/dev/full"]));
+
+    assert_eq!(output.status.code(), Some(1), "{}", logged(&output));
+    assert!(logged(&output).contains("Failed to write report file /dev/full"));
+}
+
+// With --on-collision skip, the path is left visible with a warning
+#[cfg(unix)]
+#[test]
+fn collision_skip_leaves_path_visible() {
+    let tmp = TempDir::new();
+    fs::write(tmp.path("foo.txt"), "visible").unwrap();
+    fs::write(tmp.path(".foo.txt"), "hidden").unwrap();
+
+    let output = run(tmp
+        .filehider()
+        .args([".", "-n", "foo.txt", "--on-collision", "skip"]));
+
+    assert!(output.status.success(), "{}", logged(&output));
+    assert!(logged(&output).contains("Warning: Skipping path"));
+    assert_eq!(tmp.names("."), [".foo.txt", "foo.txt"]);
+    assert_eq!(fs::read_to_string(tmp.path(".foo.txt")).unwrap(), "hidden");
+}
+
+// With --on-collision rename, the path is hidden under the first free numbered name
+#[cfg(unix)]
+#[test]
+fn collision_rename_adds_number() {
+    let tmp = TempDir::new();
+    fs::write(tmp.path("foo.txt"), "visible").unwrap();
+    fs::write(tmp.path(".foo.txt"), "hidden").unwrap();
+    fs::write(tmp.path(".foo (1).txt"), "hidden too").unwrap();
+
+    let output = run(tmp
+        .filehider()
+        .args([".", "-n", "foo.txt", "--on-collision", "rename"]));
+
+    assert!(output.status.success(), "{}", logged(&output));
+    assert_eq!(tmp.names("."), [".foo (1).txt", ".foo (2).txt", ".foo.txt"]);
+    assert_eq!(
+        fs::read_to_string(tmp.path(".foo (2).txt")).unwrap(),
+        "visible"
+    );
+    assert_eq!(fs::read_to_string(tmp.path(".foo.txt")).unwrap(), "hidden");
+}
+
+// Names with spaces, dashes, non-ASCII letters and newlines that --print0 has to keep intact
+fn tricky_names() -> Vec<&'static str> {
+    let mut names = vec!["-dash.tmp", "a b.tmp", "ünï.tmp"];
+    if cfg!(unix) {
+        names.push("new\nline.tmp");
+    }
+    names.sort();
+    names
+}
+
+// Helper function to build the output --print0 should give for the given names in dir()
+fn print0_records(tmp: &TempDir, names: &[&str]) -> Vec<u8> {
+    names
+        .iter()
+        .flat_map(|name| {
+            let mut record = tmp.path(name).into_os_string().into_encoded_bytes();
+            record.push(0);
+            record
+        })
+        .collect()
+}
+
+// With --print0 in test mode, stdout has nothing but each path that would be hidden followed by
+// a NUL byte
+#[test]
+fn print0_prints_exact_bytes() {
+    let tmp = TempDir::new();
+    let names = tricky_names();
+    for name in &names {
+        tmp.touch(name);
+    }
+    tmp.touch("keep.txt");
+
+    let output = run(tmp
+        .filehider()
+        .args([".", "-x", "tmp", "--test", "--print0", "--sort"]));
+
+    assert!(output.status.success(), "{}", logged(&output));
+    assert_eq!(output.stdout, print0_records(&tmp, &names));
+    let summary = format!("Would hide: {} files", names.len());
+    assert!(String::from_utf8_lossy(&output.stderr).contains(&summary));
+}
+
+// When hiding, --print0 prints the original path of each path hidden, in the order they're hidden
+#[test]
+fn print0_prints_hidden_paths() {
+    let tmp = TempDir::new();
+    let names = tricky_names();
+    for name in &names {
+        tmp.touch(name);
+    }
+
+    let output = run(tmp.filehider().args([".", "-x", "tmp", "--print0"]));
+
+    assert!(output.status.success(), "{}", logged(&output));
+    let mut records: Vec<&[u8]> = output.stdout.split_inclusive(|&byte| byte == 0).collect();
+    records.sort();
+    assert_eq!(records.concat(), print0_records(&tmp, &names));
+}
+
+// Names and extensions match case insensitively across scripts, and exactly with --case-sensitive
+#[cfg(unix)]
+#[test]
+fn unicode_names_match_case_insensitively() {
+    let tmp = TempDir::new();
+    tmp.touch("Café.TXT");
+    tmp.touch("ОТЧЁТ.doc");
+    tmp.touch("kış.txt");
+
+    let output = run(tmp
+        .filehider()
+        .args([".", "-n", "café.txt", "отчёт.doc", "KIŞ.txt"]));
+
+    assert!(output.status.success(), "{}", logged(&output));
+    // Only the Turkish name is left, since "I" doesn't fold to the dotless "ı"
+    let mut expected = vec![".Café.TXT", ".ОТЧЁТ.doc", "kış.txt"];
+    expected.sort();
+    assert_eq!(tmp.names("."), expected);
+}
+
+// With --case-sensitive, only the name with the same case is hidden
+#[cfg(unix)]
+#[test]
+fn unicode_names_match_exactly_when_case_sensitive() {
+    let tmp = TempDir::new();
+    tmp.touch("Café.TXT");
+    tmp.touch("café.txt");
+
+    let output = run(tmp.filehider().args([".", "-n", "café.txt", "-c"]));
+
+    assert!(output.status.success(), "{}", logged(&output));
+    assert_eq!(tmp.names("."), [".café.txt", "Café.TXT"]);
+}
+
+// --min-depth and --max-depth limit which levels below the directory are hidden
+#[test]
+fn depth_limits_levels_hidden() {
+    let tmp = TempDir::new();
+    tmp.touch("a.tmp");
+    tmp.mkdir("b/c");
+    tmp.touch("b/a.tmp");
+    tmp.touch("b/c/a.tmp");
+
+    let output = run(tmp.filehider().args([
+        ".",
+        "-r",
+        "-x",
+        "tmp",
+        "--min-depth",
+        "2",
+        "--max-depth",
+        "2",
+    ]));
+
+    assert!(output.status.success(), "{}", logged(&output));
+    assert!(!tmp.is_hidden("a.tmp"));
+    assert!(tmp.is_hidden("b/a.tmp"));
+    assert!(!tmp.is_hidden("b/c/a.tmp"));
+}
+
+// A depth of 0 is rejected from the command line and from the config file, so the directory
+// itself is never hidden
+#[test]
+fn depth_below_one_rejected() {
+    let tmp = TempDir::new();
+    tmp.touch("a.tmp");
+
+    for flag in ["--min-depth", "--max-depth"] {
+        let output = run(tmp.filehider().args([".", "-r", "-x", "tmp", flag, "0"]));
+        assert_eq!(output.status.code(), Some(1), "{}", logged(&output));
+    }
+
+    for key in ["min-depth", "max-depth"] {
+        let config = tmp.write("config.toml", &format!("{} = 0\n", key));
+        let output = run(tmp
+            .filehider()
+            .args([".", "-r", "-x", "tmp", "--config"])
+            .arg(config));
+        assert_eq!(output.status.code(), Some(1), "{}", logged(&output));
+        assert!(logged(&output).contains(&format!("{} must be at least 1!", key)));
+    }
+
+    assert_eq!(tmp.names("."), ["a.tmp"]);
+}
+
+// A minimum depth greater than the maximum depth is an error
+#[test]
+fn min_depth_above_max_depth_rejected() {
+    let tmp = TempDir::new();
+
+    let output = run(tmp.filehider().args([
+        ".",
+        "-r",
+        "-x",
+        "tmp",
+        "--min-depth",
+        "3",
+        "--max-depth",
+        "2",
+    ]));
+
+    assert_eq!(output.status.code(), Some(1), "{}", logged(&output));
+    assert!(logged(&output).contains("Minimum depth 3 is greater than maximum depth 2!"));
+}
+
+// Without a terminal, --confirm is ignored with a warning when hiding, but dry runs and checking
+// the config never prompt, so they don't warn
+#[test]
+fn confirm_warns_only_when_hiding() {
+    let tmp = TempDir::new();
+    tmp.touch("a.tmp");
+    let warning = "so --confirm is ignored";
+
+    for args in [
+        &["--confirm", "--all", "check-config"][..],
+        &[".", "--confirm", "--test", "-x", "tmp"],
+        &[".", "--confirm", "--list", "-x", "tmp"],
+    ] {
+        let output = run(tmp.filehider().args(args));
+        assert!(output.status.success(), "{:?}: {}", args, logged(&output));
+        assert!(!logged(&output).contains(warning), "{:?}", args);
+    }
+
+    let output = run(tmp.filehider().args([".", "--confirm", "-x", "tmp"]));
+    assert!(output.status.success(), "{}", logged(&output));
+    assert!(logged(&output).contains(warning));
+    assert!(tmp.is_hidden("a.tmp"));
+}
+
+// With --order parents-first, the script renames a directory before the paths in it, so those are
+// written under its new name and the script still runs to the end
+#[cfg(unix)]
+#[test]
+fn emit_script_parents_first_runs() {
+    let tmp = TempDir::new();
+    tmp.mkdir("d/b/b");
+    tmp.touch("d/b/b/a.tmp");
+    tmp.touch("d/b/a.tmp");
+    let script = tmp.outside("hide.sh");
+
+    let output = run(tmp
+        .filehider()
+        .args([
+            ".",
+            "-r",
+            "-n",
+            "b",
+            "-x",
+            "tmp",
+            "--order",
+            "parents-first",
+        ])
+        .arg("--emit-script")
+        .arg(&script));
+    assert!(output.status.success(), "{}", logged(&output));
+    assert_eq!(tmp.names("d"), ["b"]);
+
+    let output = run(std::process::Command::new("sh").arg(&script));
+
+    assert!(output.status.success(), "{}", logged(&output));
+    assert_eq!(tmp.names("d"), [".b"]);
+    assert_eq!(tmp.names("d/.b"), [".a.tmp", ".b"]);
+    assert_eq!(tmp.names("d/.b/.b"), [".a.tmp"]);
+}
+
+// Symlinks to directories are only walked with --follow-symlinks
+#[cfg(unix)]
+#[test]
+fn follow_symlinks_walks_linked_directory() {
+    let tmp = TempDir::new();
+    let outside = tmp.write("out/a.tmp", "");
+    std::os::unix::fs::symlink(outside.parent().unwrap(), tmp.path("link")).unwrap();
+
+    let output = run(tmp.filehider().args([".", "-r", "-x", "tmp"]));
+    assert!(output.status.success(), "{}", logged(&output));
+    assert!(!tmp.is_hidden("link/a.tmp"));
+
+    let output = run(tmp
+        .filehider()
+        .args([".", "-r", "-x", "tmp", "--follow-symlinks"]));
+    assert!(output.status.success(), "{}", logged(&output));
+    assert!(tmp.is_hidden("link/a.tmp"));
+}
+
+// A symlink back to an ancestor is skipped with a warning, and the rest of the walk goes on
+#[cfg(unix)]
+#[test]
+fn follow_symlinks_skips_loop() {
+    let tmp = TempDir::new();
+    tmp.touch("sub/a.tmp");
+    std::os::unix::fs::symlink("..", tmp.path("sub/loop")).unwrap();
+
+    let output = run(tmp
+        .filehider()
+        .args([".", "-r", "-x", "tmp", "--follow-symlinks"]));
+
+    assert!(output.status.success(), "{}", logged(&output));
+    assert!(logged(&output).contains("Skipping symlink loop at"));
+    assert!(tmp.is_hidden("sub/a.tmp"));
+    assert_eq!(tmp.names("sub"), [".a.tmp", "loop"]);
+}
+
+// With --one-file-system, the walk doesn't cross into another file system, here a tmpfs reached
+// through a symlink. Skipped where /dev/shm isn't a separate file system.
+#[cfg(target_os = "linux")]
+#[test]
+fn one_file_system_stays_on_device() {
+    use std::os::unix::fs::MetadataExt;
+
+    let shm = std::path::Path::new("/dev/shm");
+    let tmp = TempDir::new();
+    let other = match fs::metadata(shm) {
+        Ok(metadata) if metadata.dev() != fs::metadata(tmp.dir()).unwrap().dev() => {
+            TempDir::new_in(shm)
+        }
+        _ => return,
+    };
+    other.touch("a.tmp");
+    std::os::unix::fs::symlink(other.dir(), tmp.path("link")).unwrap();
+    tmp.touch("a.tmp");
+
+    let output = run(tmp.filehider().args([
+        ".",
+        "-r",
+        "-x",
+        "tmp",
+        "--follow-symlinks",
+        "--one-file-system",
+    ]));
+
+    assert!(output.status.success(), "{}", logged(&output));
+    assert!(tmp.is_hidden("a.tmp"));
+    assert!(!other.is_hidden("a.tmp"), "{}", logged(&output));
+
+    let output = run(tmp
+        .filehider()
+        .args([".", "-r", "-x", "tmp", "--follow-symlinks"]));
+    assert!(output.status.success(), "{}", logged(&output));
+    assert!(other.is_hidden("a.tmp"));
+}
+
+// A directory inside another recursive directory is skipped with a warning, so its paths are only
+// handled once
+#[test]
+fn nested_directory_merged() {
+    let tmp = TempDir::new();
+    tmp.touch("sub/a.tmp");
+
+    let output = run(tmp.filehider().args([".", "sub", "-r", "-x", "tmp"]));
+
+    assert!(output.status.success(), "{}", logged(&output));
+    assert!(logged(&output).contains("Skipping directory"));
+    assert!(logged(&output).contains("Hidden: 1 files"));
+    assert!(logged(&output).contains("Already hidden: 0"));
+    assert!(tmp.is_hidden("sub/a.tmp"));
+}
+
+// With --on-overlap error, a nested directory is an error instead
+#[test]
+fn nested_directory_rejected() {
+    let tmp = TempDir::new();
+    tmp.touch("sub/a.tmp");
+
+    let output =
+        run(tmp
+            .filehider()
+            .args([".", "sub", "-r", "-x", "tmp", "--on-overlap", "error"]));
+
+    assert_eq!(output.status.code(), Some(1), "{}", logged(&output));
+    assert!(logged(&output).contains("which is already handled recursively!"));
+    assert!(!tmp.is_hidden("sub/a.tmp"));
+}
+
+// The same directory given twice, or through a symlink, is only handled once
+#[test]
+fn identical_directories_merged() {
+    let tmp = TempDir::new();
+    tmp.touch("a.tmp");
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(tmp.dir(), tmp.outside("alias")).unwrap();
+
+    let mut command = tmp.filehider();
+    command.arg(".").arg(tmp.dir());
+    #[cfg(unix)]
+    command.arg(tmp.outside("alias"));
+    command.args(["-x", "tmp"]);
+    let output = run(&mut command);
+
+    assert!(output.status.success(), "{}", logged(&output));
+    assert!(
+        logged(&output).contains("Examined: 1"),
+        "{}",
+        logged(&output)
+    );
+    assert!(tmp.is_hidden("a.tmp"));
+}
+
+// Helper function to hide "file" in a directory with files of that name and others, returning the
+// names left visible
+#[cfg(unix)]
+fn hide_file_named(args: &[&str]) -> Vec<String> {
+    let tmp = TempDir::new();
+    for name in ["file", "file.txt", "file.log", "file.tar.gz", "other.txt"] {
+        tmp.touch(name);
+    }
+
+    let output = run(tmp.filehider().args([".", "-n", "file"]).args(args));
+    assert!(output.status.success(), "{}", logged(&output));
+    tmp.names(".")
+        .into_iter()
+        .filter(|name| !name.starts_with('.'))
+        .collect()
+}
+
+// A name without a dot only matches the file without an extension, unless --match-stem is given,
+// when it matches the name without its last extension
+#[cfg(unix)]
+#[test]
+fn match_stem_matches_any_extension() {
+    assert_eq!(
+        hide_file_named(&[]),
+        ["file.log", "file.tar.gz", "file.txt", "other.txt"]
+    );
+    assert_eq!(
+        hide_file_named(&["--match-stem"]),
+        ["file.tar.gz", "other.txt"]
+    );
+}
+
+// With --all, only the file types given with -t are hidden
+#[cfg(unix)]
+#[test]
+fn all_hides_only_given_file_types() {
+    let tmp = TempDir::new();
+    tmp.touch("a.txt");
+    tmp.mkdir("sub");
+
+    let output = run(tmp.filehider().args([".", "--all", "-t", "file"]));
+
+    assert!(output.status.success(), "{}", logged(&output));
+    assert_eq!(tmp.names("."), [".a.txt", "sub"]);
+}
+
+// --path-regex on its own only hides the file types given with -t
+#[cfg(unix)]
+#[test]
+fn path_regex_hides_only_given_file_types() {
+    let tmp = TempDir::new();
+    tmp.touch("foo.txt");
+    tmp.touch("bar.txt");
+    tmp.mkdir("foodir");
+
+    let output = run(tmp
+        .filehider()
+        .args([".", "--path-regex", "foo", "-t", "file"]));
+
+    assert!(output.status.success(), "{}", logged(&output));
+    assert_eq!(tmp.names("."), [".foo.txt", "bar.txt", "foodir"]);
+}
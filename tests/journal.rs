@@ -0,0 +1,58 @@
+// Tests of the journal of hidden paths and the undo command. The default journal and config file
+// are looked up in the XDG directories under home(), which only applies on Linux.
+mod common;
+
+use common::{exists, logged, run, TempDir};
+
+// Checking the config and dry runs hide nothing, so they don't create the journal
+#[cfg(target_os = "linux")]
+#[test]
+fn journal_only_created_when_hiding() {
+    let tmp = TempDir::new();
+    tmp.touch("a.tmp");
+    let journal = tmp.home().join(".local/share/filehider/journal.jsonl");
+
+    let config = tmp.write("config.toml", "file-extensions = [\"tmp\"]\n");
+    let config = config.to_str().unwrap();
+    for args in [
+        &["--config", config, "check-config"][..],
+        &["--config", config, "--test"],
+        &["--config", config, "--list"],
+    ] {
+        let output = run(tmp.filehider().args(args));
+        assert!(output.status.success(), "{:?}: {}", args, logged(&output));
+        assert!(!exists(&journal), "{:?} created the journal", args);
+    }
+
+    let output = run(tmp.filehider().args(["--config", config]));
+    assert!(output.status.success(), "{}", logged(&output));
+    assert!(exists(&journal));
+    assert!(tmp.is_hidden("a.tmp"));
+}
+
+// Undo restores the names the journal records, and only reads the config file when no journal is
+// given, so a broken one doesn't stop it
+#[cfg(target_os = "linux")]
+#[test]
+fn undo_with_journal_ignores_broken_config() {
+    let tmp = TempDir::new();
+    tmp.touch("a.tmp");
+    let journal = tmp.outside("journal.jsonl");
+
+    let output = run(tmp
+        .filehider()
+        .args([".", "-x", "tmp", "--journal"])
+        .arg(&journal));
+    assert!(output.status.success(), "{}", logged(&output));
+    assert!(tmp.is_hidden("a.tmp"));
+
+    tmp.write("home/.config/filehider/config.toml", "file-names = [");
+    let output = run(tmp.filehider().arg("undo").arg("--journal").arg(&journal));
+
+    assert!(output.status.success(), "{}", logged(&output));
+    assert!(!tmp.is_hidden("a.tmp"));
+    assert!(exists(&tmp.path("a.tmp")));
+
+    let output = run(tmp.filehider().arg("undo"));
+    assert_eq!(output.status.code(), Some(1), "{}", logged(&output));
+}
@@ -0,0 +1,84 @@
+// Tests of the lock that keeps two instances from hiding in the same directories
+mod common;
+
+use common::{logged, run, Running, TempDir};
+
+// The exit code when another instance holds the lock
+const EXIT_LOCKED: i32 = 4;
+
+// A second instance on the same directories exits with EXIT_LOCKED, naming the first one's PID,
+// and runs once the first one is gone
+#[test]
+fn second_instance_exits_locked() {
+    let tmp = TempDir::new();
+    let mut first = Running::spawn(tmp.filehider().args([".", "-x", "tmp", "--watch-existing"]));
+    first.wait_for("Immediate mode finished");
+
+    let output = run(tmp.filehider().args([".", "-x", "tmp"]));
+
+    assert_eq!(
+        output.status.code(),
+        Some(EXIT_LOCKED),
+        "{}",
+        logged(&output)
+    );
+    assert!(logged(&output).contains(&format!("(PID {})", first.id())));
+
+    first.stop();
+    let output = run(tmp.filehider().args([".", "-x", "tmp"]));
+    assert!(output.status.success(), "{}", logged(&output));
+}
+
+// With --no-lock, a second instance runs anyway
+#[test]
+fn no_lock_runs_alongside() {
+    let tmp = TempDir::new();
+    let mut first = Running::spawn(tmp.filehider().args([".", "-x", "tmp", "--watch-existing"]));
+    first.wait_for("Immediate mode finished");
+
+    let output = run(tmp.filehider().args([".", "-x", "tmp", "--no-lock"]));
+
+    assert!(output.status.success(), "{}", logged(&output));
+}
+
+// Test mode doesn't hide anything, so it doesn't need the lock
+#[test]
+fn test_mode_ignores_lock() {
+    let tmp = TempDir::new();
+    let mut first = Running::spawn(tmp.filehider().args([".", "-x", "tmp", "--watch-existing"]));
+    first.wait_for("Immediate mode finished");
+
+    let output = run(tmp.filehider().args([".", "-x", "tmp", "--test"]));
+
+    assert!(output.status.success(), "{}", logged(&output));
+}
+
+// Instances on different directories don't get in each other's way
+#[test]
+fn other_directories_not_locked() {
+    let tmp = TempDir::new();
+    tmp.mkdir("a");
+    tmp.mkdir("b");
+    let mut first = Running::spawn(tmp.filehider().args(["a", "-x", "tmp", "--watch-existing"]));
+    first.wait_for("Immediate mode finished");
+
+    let output = run(tmp.filehider().args(["b", "-x", "tmp"]));
+
+    assert!(output.status.success(), "{}", logged(&output));
+}
+
+// The lock is released when an instance stops on Ctrl+C
+#[cfg(unix)]
+#[test]
+fn lock_released_on_interrupt() {
+    let tmp = TempDir::new();
+    let mut first = Running::spawn(tmp.filehider().args([".", "-x", "tmp", "--watch-existing"]));
+    first.wait_for("Immediate mode finished");
+
+    common::signal(first.id(), "INT");
+    let (status, logged_first) = first.wait();
+    assert!(status.success(), "{}", logged_first);
+
+    let output = run(tmp.filehider().args([".", "-x", "tmp"]));
+    assert!(output.status.success(), "{}", logged(&output));
+}
@@ -0,0 +1,201 @@
+// Tests of --format json and ndjson, parsing what's printed back with serde_json
+mod common;
+
+use serde_json::Value;
+
+use common::{logged, run, TempDir};
+
+// Helper function to find the record of the path with the given name in a JSON document
+fn record<'a>(document: &'a Value, tmp: &TempDir, name: &str) -> &'a Value {
+    let path = tmp.path(name);
+    document["paths"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|record| record["path"] == path.to_str().unwrap())
+        .unwrap_or_else(|| panic!("No record of {}: {}", name, document))
+}
+
+// Each path handled gets a record of what happened to it, with a summary after them. Only the
+// document goes to stdout, so it parses as a whole.
+#[cfg(unix)]
+#[test]
+fn format_json_describes_each_path() {
+    let tmp = TempDir::new();
+    tmp.touch("a.tmp");
+    tmp.touch(".b.tmp");
+    tmp.touch("c.tmp");
+    tmp.touch(".c.tmp");
+    tmp.touch("d.txt");
+
+    let output = run(tmp.filehider().args([".", "-x", "tmp", "--format", "json"]));
+
+    assert_eq!(output.status.code(), Some(2), "{}", logged(&output));
+    let document: Value = serde_json::from_slice(&output.stdout).unwrap();
+
+    let hidden = record(&document, &tmp, "a.tmp");
+    assert_eq!(hidden["action"], "hidden");
+    assert_eq!(hidden["rule"], "extension \"tmp\"");
+    assert_eq!(
+        record(&document, &tmp, ".b.tmp")["action"],
+        "already_hidden"
+    );
+    let failed = record(&document, &tmp, "c.tmp");
+    assert_eq!(failed["action"], "error");
+    assert!(failed["error"].as_str().unwrap().contains("already exists"));
+    assert!(failed.get("rule").is_none());
+
+    let summary = &document["summary"];
+    assert_eq!(summary["examined"], 5);
+    assert_eq!(summary["hidden"], 1);
+    assert_eq!(summary["already_hidden"], 2);
+    assert_eq!(summary["skipped"], 1);
+    assert_eq!(summary["errors"], 1);
+    assert!(summary["duration_secs"].is_f64());
+
+    // The human readable messages still go to stderr
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Hid file"));
+}
+
+// Test mode records the paths it would hide
+#[test]
+fn format_json_in_test_mode() {
+    let tmp = TempDir::new();
+    tmp.touch("a.tmp");
+
+    let output = run(tmp
+        .filehider()
+        .args([".", "-x", "tmp", "--format", "json", "--test"]));
+
+    assert!(output.status.success(), "{}", logged(&output));
+    let document: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(record(&document, &tmp, "a.tmp")["action"], "would_hide");
+    assert_eq!(document["summary"]["hidden"], 1);
+}
+
+// A path that isn't valid UTF-8 is printed losslessly as an array of its bytes. Its name can't be
+// matched, so it's recorded as an error.
+#[cfg(target_os = "linux")]
+#[test]
+fn format_json_prints_raw_bytes() {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    let tmp = TempDir::new();
+    let name = OsStr::from_bytes(b"\xff.tmp");
+    std::fs::write(tmp.dir().join(name), "").unwrap();
+
+    let output = run(tmp
+        .filehider()
+        .args([".", "-x", "tmp", "--format", "json", "--test"]));
+
+    assert_eq!(output.status.code(), Some(2), "{}", logged(&output));
+    let document: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(document["paths"][0]["action"], "error");
+    let expected: Vec<Value> = tmp
+        .dir()
+        .join(name)
+        .as_os_str()
+        .as_bytes()
+        .iter()
+        .map(|byte| Value::from(*byte))
+        .collect();
+    assert_eq!(document["paths"][0]["path"], Value::Array(expected));
+}
+
+// With ndjson, every line is a JSON object of its own, from the start to the stop
+#[test]
+fn format_ndjson_prints_lines() {
+    let tmp = TempDir::new();
+    tmp.touch("a.tmp");
+
+    let output = run(tmp
+        .filehider()
+        .args([".", "-x", "tmp", "--format", "ndjson"]));
+
+    assert!(output.status.success(), "{}", logged(&output));
+    let lines: Vec<Value> = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let types: Vec<&str> = lines
+        .iter()
+        .map(|line| line["type"].as_str().unwrap())
+        .collect();
+    assert_eq!(types, ["start", "path", "stop"]);
+    assert_eq!(lines[1]["action"], "hidden");
+    assert_eq!(lines[1]["event"], "scan");
+}
+
+// Test mode prints the rule each path matched after it, for every kind of rule
+#[test]
+fn test_mode_prints_match_reason() {
+    let tmp = TempDir::new();
+    tmp.touch("a.tmp");
+    tmp.touch("Thumbs.db");
+    tmp.touch("notes.txt");
+    tmp.mkdir("build");
+    tmp.mkdir("Photos.app");
+
+    let cases: &[(&[&str], &str, &str)] = &[
+        (&["-x", "tmp"], "a.tmp", "(extension \"tmp\")"),
+        (
+            &["-n", "Thumbs.db"],
+            "Thumbs.db",
+            "(file name \"thumbs.db\")",
+        ),
+        (
+            &["-n", "notes", "--match-stem"],
+            "notes.txt",
+            "(file stem \"notes\")",
+        ),
+        (
+            &["--dir-names", "build"],
+            "build",
+            "(directory name \"build\")",
+        ),
+        (
+            &["--dir-extensions", "app"],
+            "Photos.app",
+            "(directory extension \"app\")",
+        ),
+        (&["--path-regex", "notes"], "notes.txt", "(path regex)"),
+        (&["--all"], "a.tmp", "(no file names or extensions given)"),
+    ];
+    for (args, name, reason) in cases {
+        let output = run(tmp.filehider().arg(".").args(*args).arg("--test"));
+        assert!(output.status.success(), "{:?}: {}", args, logged(&output));
+        let line = format!("{} {}", tmp.path(name).display(), reason);
+        assert!(
+            logged(&output).contains(&line),
+            "{:?}: {}",
+            args,
+            logged(&output)
+        );
+    }
+}
+
+// Verbose mode prints the rule after each path hidden, and JSON output gives it as the rule
+#[test]
+fn verbose_and_json_give_match_reason() {
+    let tmp = TempDir::new();
+    tmp.touch("a.tmp");
+    tmp.touch("b.txt");
+
+    let output = run(tmp.filehider().args([".", "-x", "tmp", "-v"]));
+    assert!(output.status.success(), "{}", logged(&output));
+    assert!(logged(&output).contains(&format!(
+        "{} (extension \"tmp\")",
+        tmp.path("a.tmp").display()
+    )));
+
+    let output = run(tmp
+        .filehider()
+        .args([".", "-n", "b.txt", "--format", "json", "--test"]));
+    assert!(output.status.success(), "{}", logged(&output));
+    let document: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        record(&document, &tmp, "b.txt")["rule"],
+        "file name \"b.txt\""
+    );
+}
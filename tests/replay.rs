@@ -0,0 +1,145 @@
+// Tests of watch mode's event handling, feeding synthetic events through it with --replay-events
+mod common;
+
+use std::path::Path;
+
+use common::{exists, logged, run, TempDir};
+
+// Helper function to write one event as a line of an events file. The paths are plain ASCII, so
+// their debug form is valid JSON.
+fn event(kind: &str, paths: &[&Path]) -> String {
+    let paths: Vec<String> = paths
+        .iter()
+        .map(|path| format!("{:?}", path.to_str().unwrap()))
+        .collect();
+    format!(
+        "{{\"kind\": \"{}\", \"paths\": [{}]}}\n",
+        kind,
+        paths.join(", ")
+    )
+}
+
+// Helper function to replay the given events in watch mode on dir(), returning what was printed
+fn replay(tmp: &TempDir, events: &[String], args: &[&str]) -> std::process::Output {
+    let file = tmp.write("events.jsonl", &events.concat());
+    run(tmp
+        .filehider()
+        .args([".", "-w", "--no-immediate", "--replay-events"])
+        .arg(file)
+        .args(args))
+}
+
+// Created paths are hidden if they match, and left alone otherwise
+#[test]
+fn replayed_creates_are_handled() {
+    let tmp = TempDir::new();
+    let matching = tmp.touch("a.tmp");
+    let other = tmp.touch("b.txt");
+
+    let output = replay(
+        &tmp,
+        &[event("create", &[&matching]), event("create", &[&other])],
+        &["-x", "tmp"],
+    );
+
+    assert!(output.status.success(), "{}", logged(&output));
+    assert!(logged(&output).contains("Finished replaying events"));
+    assert!(tmp.is_hidden("a.tmp"));
+    assert!(!tmp.is_hidden("b.txt"));
+}
+
+// Paths already there aren't hidden without an event, since immediate mode is off
+#[test]
+fn replay_only_handles_events() {
+    let tmp = TempDir::new();
+    tmp.touch("a.tmp");
+    let created = tmp.touch("b.tmp");
+
+    let output = replay(&tmp, &[event("create", &[&created])], &["-x", "tmp"]);
+
+    assert!(output.status.success(), "{}", logged(&output));
+    assert!(!tmp.is_hidden("a.tmp"));
+    assert!(tmp.is_hidden("b.tmp"));
+}
+
+// A path renamed to a matching name is hidden under its new name
+#[test]
+fn replayed_rename_hides_new_name() {
+    let tmp = TempDir::new();
+    let old = tmp.path("a.txt");
+    let new = tmp.touch("a.tmp");
+
+    let output = replay(&tmp, &[event("rename", &[&old, &new])], &["-x", "tmp"]);
+
+    assert!(output.status.success(), "{}", logged(&output));
+    assert!(tmp.is_hidden("a.tmp"));
+}
+
+// Modifying a path doesn't hide it, since only new names are handled
+#[test]
+fn replayed_modify_is_ignored() {
+    let tmp = TempDir::new();
+    let path = tmp.touch("a.tmp");
+
+    let output = replay(&tmp, &[event("modify", &[&path])], &["-x", "tmp"]);
+
+    assert!(output.status.success(), "{}", logged(&output));
+    assert!(!tmp.is_hidden("a.tmp"));
+}
+
+// Events about paths outside the watched directories are ignored
+#[test]
+fn replayed_events_outside_directories_ignored() {
+    let tmp = TempDir::new();
+    let outside = tmp.write("elsewhere/a.tmp", "");
+
+    let output = replay(&tmp, &[event("create", &[&outside])], &["-x", "tmp"]);
+
+    assert!(output.status.success(), "{}", logged(&output));
+    assert!(exists(&outside));
+}
+
+// Removing a watched directory is reported rather than counted as an error
+#[test]
+fn replayed_directory_removal_reported() {
+    let tmp = TempDir::new();
+
+    let output = replay(&tmp, &[event("remove", &[&tmp.dir()])], &["-x", "tmp"]);
+
+    assert!(output.status.success(), "{}", logged(&output));
+    assert!(logged(&output).contains("was removed"));
+}
+
+// A line that isn't a valid event fails the run, naming the line
+#[test]
+fn invalid_replay_event_fails() {
+    let tmp = TempDir::new();
+    let path = tmp.touch("a.tmp");
+
+    let output = replay(
+        &tmp,
+        &[
+            event("create", &[&path]),
+            "{\"kind\": \"explode\"}\n".to_string(),
+        ],
+        &["-x", "tmp"],
+    );
+
+    assert_eq!(output.status.code(), Some(1), "{}", logged(&output));
+    assert!(logged(&output).contains("Failed to parse line 2"));
+}
+
+// Replaying needs watch mode
+#[test]
+fn replay_requires_watch_mode() {
+    let tmp = TempDir::new();
+    let file = tmp.write("events.jsonl", "");
+
+    let output = run(tmp
+        .filehider()
+        .args([".", "-x", "tmp", "--replay-events"])
+        .arg(file));
+
+    assert_eq!(output.status.code(), Some(1), "{}", logged(&output));
+    assert!(logged(&output).contains("--replay-events can only be used in watch mode!"));
+}
@@ -0,0 +1,63 @@
+// Tests of --reveal-all, which only unhides the paths the journal records hiding. Hiding a path
+// renames it with a leading dot on Unix, which these tests rely on.
+#![cfg(unix)]
+
+mod common;
+
+use common::{logged, run, TempDir};
+
+// A single run reveals a hidden path inside a hidden directory, although the journal recorded it
+// before the directory was renamed
+#[test]
+fn reveal_all_reveals_inside_hidden_directory() {
+    let tmp = TempDir::new();
+    tmp.mkdir("d/b");
+    tmp.touch("d/b/child.txt");
+    let journal = tmp.outside("journal.jsonl");
+
+    let output = run(tmp
+        .filehider()
+        .args([".", "-r", "-n", "b,child.txt", "--journal"])
+        .arg(&journal));
+    assert!(output.status.success(), "{}", logged(&output));
+    assert_eq!(tmp.names("d/.b"), [".child.txt"]);
+
+    let output = run(tmp
+        .filehider()
+        .args([".", "--reveal-all", "-r", "--journal"])
+        .arg(&journal));
+
+    assert!(output.status.success(), "{}", logged(&output));
+    assert_eq!(tmp.names("d"), ["b"]);
+    assert_eq!(tmp.names("d/b"), ["child.txt"]);
+}
+
+// A path unhidden since it was hidden isn't recorded as hidden anymore, so a new dotfile with its
+// hidden name is left alone, as are dotfiles the journal never recorded
+#[test]
+fn reveal_all_skips_unhidden_paths() {
+    let tmp = TempDir::new();
+    tmp.touch("a.txt");
+    let journal = tmp.outside("journal.jsonl");
+
+    let output = run(tmp
+        .filehider()
+        .args([".", "-n", "a.txt", "--journal"])
+        .arg(&journal));
+    assert!(output.status.success(), "{}", logged(&output));
+    let output = run(tmp
+        .filehider()
+        .args([".", "-n", "a.txt", "--action", "unhide", "--journal"])
+        .arg(&journal));
+    assert!(output.status.success(), "{}", logged(&output));
+    tmp.touch(".a.txt");
+    tmp.touch(".b.txt");
+
+    let output = run(tmp
+        .filehider()
+        .args([".", "--reveal-all", "--journal"])
+        .arg(&journal));
+
+    assert!(output.status.success(), "{}", logged(&output));
+    assert_eq!(tmp.names("."), [".a.txt", ".b.txt", "a.txt"]);
+}
@@ -0,0 +1,93 @@
+// Tests of the signals watch mode handles on Unix, sent with kill(1). The directories are polled
+// too rarely to notice anything on their own, so only the signals can make watch mode act.
+#![cfg(unix)]
+
+mod common;
+
+use common::{signal, Running, TempDir};
+
+// Arguments that keep watch mode from seeing any events by itself
+const NO_EVENTS: &[&str] = &["--backend", "poll", "--poll-interval", "1h"];
+
+// SIGUSR1 sweeps the directories again, hiding what has no event
+#[test]
+fn sigusr1_rescans() {
+    let tmp = TempDir::new();
+    let mut watcher = Running::spawn(
+        tmp.filehider()
+            .args([".", "-x", "tmp", "--watch-existing"])
+            .args(NO_EVENTS),
+    );
+    watcher.wait_for("Immediate mode finished");
+
+    tmp.touch("a.tmp");
+    signal(watcher.id(), "USR1");
+    watcher.wait_for("Rescan after a signal finished");
+
+    let logged = watcher.stop();
+    assert!(tmp.is_hidden("a.tmp"), "{}", logged);
+    assert!(logged.contains("Rescanning 1 directories, as asked by a signal"));
+}
+
+// SIGHUP reloads the config file, so the rules in it apply from then on
+#[test]
+fn sighup_reloads_config() {
+    let tmp = TempDir::new();
+    tmp.touch("a.txt");
+    tmp.touch("b.txt");
+    let config = tmp.write("config.toml", "file-names = [\"a.txt\"]\n");
+    let mut watcher = Running::spawn(
+        tmp.filehider()
+            .args([
+                ".",
+                "--watch-existing",
+                "--config",
+                config.to_str().unwrap(),
+            ])
+            .args(NO_EVENTS),
+    );
+    watcher.wait_for("Immediate mode finished");
+    assert!(tmp.is_hidden("a.txt"));
+
+    tmp.write("config.toml", "file-names = [\"b.txt\"]\n");
+    signal(watcher.id(), "HUP");
+    watcher.wait_for("Reloaded config file");
+    // Reloading doesn't sweep the directories by itself
+    assert!(!tmp.is_hidden("b.txt"));
+
+    signal(watcher.id(), "USR1");
+    watcher.wait_for("Rescan after a signal finished");
+
+    let logged = watcher.stop();
+    assert!(tmp.is_hidden("b.txt"), "{}", logged);
+    assert!(logged.contains("Reloading the configuration, as asked by a signal"));
+}
+
+// A config file that no longer parses is rejected on SIGHUP, and the old rules stay
+#[test]
+fn sighup_keeps_config_when_invalid() {
+    let tmp = TempDir::new();
+    let config = tmp.write("config.toml", "file-names = [\"a.txt\"]\n");
+    let mut watcher = Running::spawn(
+        tmp.filehider()
+            .args([
+                ".",
+                "--watch-existing",
+                "--config",
+                config.to_str().unwrap(),
+            ])
+            .args(NO_EVENTS),
+    );
+    watcher.wait_for("Immediate mode finished");
+
+    tmp.write("config.toml", "file-names = [");
+    signal(watcher.id(), "HUP");
+    watcher.wait_for("Failed to reload config file, keeping the old config");
+
+    tmp.touch("a.txt");
+    signal(watcher.id(), "USR1");
+    watcher.wait_for("Rescan after a signal finished");
+
+    let logged = watcher.stop();
+    assert!(tmp.is_hidden("a.txt"), "{}", logged);
+}
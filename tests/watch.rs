@@ -0,0 +1,227 @@
+// Tests of watch mode, running the binary in the background against a temporary directory and
+// changing files under it. --watch-existing is used to know when the directories are watched,
+// since immediate mode only runs once they are.
+mod common;
+
+use std::{fs, thread, time::Duration};
+
+use common::{exists, is_hidden_attribute, logged, run, wait_until, Running, TempDir};
+
+// How long to wait for an event that shouldn't do anything, before checking that it didn't
+const QUIET_PERIOD: Duration = Duration::from_millis(500);
+
+// Paths created next to the config file aren't hidden, since its directory is only watched to
+// reload it
+#[test]
+fn config_directory_is_not_hidden() {
+    let tmp = TempDir::new();
+    let config = tmp.write(
+        "cfg/config.toml",
+        &format!(
+            "directories = [{:?}]\nfile-names = [\"foo.txt\"]\n",
+            tmp.dir().to_str().unwrap()
+        ),
+    );
+    let beside_config = config.with_file_name("foo.txt");
+
+    let mut watcher = Running::spawn(tmp.filehider().args([
+        "--watch-existing",
+        "--debounce",
+        "0s",
+        "--config",
+        config.to_str().unwrap(),
+    ]));
+    watcher.wait_for("Immediate mode finished");
+
+    fs::write(&beside_config, "").unwrap();
+    tmp.touch("foo.txt");
+    wait_until("foo.txt to be hidden", || tmp.is_hidden("foo.txt"));
+    thread::sleep(QUIET_PERIOD);
+
+    let logged = watcher.stop();
+    assert!(exists(&beside_config), "{}", logged);
+    assert!(!is_hidden_attribute(&beside_config), "{}", logged);
+}
+
+// Helper function to start watching dir() for the given extension, once it's ready
+fn watch_extension(tmp: &TempDir, extension: &str) -> Running {
+    let mut watcher = Running::spawn(tmp.filehider().args([
+        ".",
+        "-x",
+        extension,
+        "--watch-existing",
+        "--debounce",
+        "0s",
+    ]));
+    watcher.wait_for("Immediate mode finished");
+    watcher
+}
+
+// A path renamed to a matching name within a watched directory is hidden
+#[test]
+fn rename_within_directory_hidden() {
+    let tmp = TempDir::new();
+    tmp.touch("a.txt");
+    let watcher = watch_extension(&tmp, "tmp");
+
+    fs::rename(tmp.path("a.txt"), tmp.path("a.tmp")).unwrap();
+    wait_until("a.tmp to be hidden", || tmp.is_hidden("a.tmp"));
+
+    watcher.stop();
+}
+
+// A matching path moved into a watched directory from outside is hidden
+#[test]
+fn rename_into_directory_hidden() {
+    let tmp = TempDir::new();
+    let outside = tmp.write("elsewhere/a.tmp", "");
+    let watcher = watch_extension(&tmp, "tmp");
+
+    fs::rename(&outside, tmp.path("a.tmp")).unwrap();
+    wait_until("a.tmp to be hidden", || tmp.is_hidden("a.tmp"));
+
+    watcher.stop();
+}
+
+// A matching path moved out of a watched directory is left alone
+#[test]
+fn rename_out_of_directory_ignored() {
+    let tmp = TempDir::new();
+    tmp.touch("a.txt");
+    let outside = tmp.outside("a.tmp");
+    let watcher = watch_extension(&tmp, "tmp");
+
+    fs::rename(tmp.path("a.txt"), &outside).unwrap();
+    // Something to wait for, so the rename has been seen
+    tmp.touch("b.tmp");
+    wait_until("b.tmp to be hidden", || tmp.is_hidden("b.tmp"));
+    thread::sleep(QUIET_PERIOD);
+
+    let logged = watcher.stop();
+    assert!(exists(&outside), "{}", logged);
+    assert!(!is_hidden_attribute(&outside), "{}", logged);
+}
+
+// Every path created is handled, however the backend groups their events
+#[test]
+fn every_created_path_hidden() {
+    let tmp = TempDir::new();
+    let watcher = watch_extension(&tmp, "tmp");
+
+    let names: Vec<String> = (0..20).map(|n| format!("{}.tmp", n)).collect();
+    for name in &names {
+        tmp.touch(name);
+    }
+    for name in &names {
+        wait_until(name, || tmp.is_hidden(name));
+    }
+
+    watcher.stop();
+}
+
+// With --restore-on-exit, the paths hidden are made visible again when watch mode stops with an
+// error too, here when --watch-existing reaches --max-errors-total. Sorted by name, a.tmp is hidden
+// before b.tmp collides with the existing .b.tmp.
+#[cfg(unix)]
+#[test]
+fn restore_on_exit_after_error() {
+    let tmp = TempDir::new();
+    tmp.touch("a.tmp");
+    tmp.touch("b.tmp");
+    tmp.touch(".b.tmp");
+
+    let output = run(tmp.filehider().args([
+        ".",
+        "-x",
+        "tmp",
+        "--watch-existing",
+        "--restore-on-exit",
+        "--sort-by",
+        "name",
+        "--max-errors-total",
+        "1",
+    ]));
+
+    assert_eq!(output.status.code(), Some(2), "{}", logged(&output));
+    assert!(logged(&output).contains("Hid file"), "{}", logged(&output));
+    assert!(logged(&output).contains("Restored 1 paths, 0 errors"));
+    assert_eq!(tmp.names("."), [".b.tmp", "a.tmp", "b.tmp"]);
+}
+
+// With --follow-symlinks, symlinked directories found at startup are watched as well
+#[cfg(unix)]
+#[test]
+fn follow_symlinks_watches_linked_directory() {
+    let tmp = TempDir::new();
+    let outside = tmp.write("out/a.txt", "");
+    let target = outside.parent().unwrap();
+    std::os::unix::fs::symlink(target, tmp.path("link")).unwrap();
+    let mut watcher = Running::spawn(tmp.filehider().args([
+        ".",
+        "-r",
+        "-x",
+        "tmp",
+        "--follow-symlinks",
+        "--watch-existing",
+        "--debounce",
+        "0s",
+    ]));
+    watcher.wait_for("Immediate mode finished");
+
+    fs::write(target.join("b.tmp"), "").unwrap();
+    wait_until("b.tmp to be hidden", || exists(&target.join(".b.tmp")));
+
+    watcher.stop();
+}
+
+// With --debounce, repeated events for a path within the window are collapsed, so it's handled
+// once after the last of them, here although it was removed and created again in between
+#[test]
+fn debounce_handles_path_once() {
+    let tmp = TempDir::new();
+    let mut watcher = Running::spawn(tmp.filehider().args([
+        ".",
+        "-x",
+        "tmp",
+        "-v",
+        "--watch-existing",
+        "--debounce",
+        "2s",
+    ]));
+    watcher.wait_for("Immediate mode finished");
+
+    tmp.touch("a.tmp");
+    fs::remove_file(tmp.path("a.tmp")).unwrap();
+    tmp.touch("a.tmp");
+    wait_until("a.tmp to be hidden", || tmp.is_hidden("a.tmp"));
+    thread::sleep(QUIET_PERIOD);
+
+    let logged = watcher.stop();
+    assert!(logged.contains("Collapsing repeated event"), "{}", logged);
+    assert_eq!(logged.matches("Hid file").count(), 1, "{}", logged);
+    assert!(!logged.contains("Failed"), "{}", logged);
+}
+
+// With --backend poll, a path created in a watched directory is found by the next scan
+#[test]
+fn poll_backend_hides_created_path() {
+    let tmp = TempDir::new();
+    let mut watcher = Running::spawn(tmp.filehider().args([
+        ".",
+        "-x",
+        "tmp",
+        "--watch-existing",
+        "--debounce",
+        "0s",
+        "--backend",
+        "poll",
+        "--poll-interval",
+        "100ms",
+    ]));
+    watcher.wait_for("Immediate mode finished");
+
+    tmp.touch("a.tmp");
+    wait_until("a.tmp to be hidden", || tmp.is_hidden("a.tmp"));
+
+    watcher.stop();
+}